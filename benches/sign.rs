@@ -0,0 +1,145 @@
+//! Signing throughput benchmarks against an in-memory card which answers
+//! every GENERAL AUTHENTICATE with a canned signature.
+//!
+//! Besides the criterion timings, the number of heap allocations made per
+//! signature by [`key::sign_data`] and [`key::sign_batch`] is reported.
+
+use criterion::{criterion_group, BenchmarkId, Criterion, Throughput};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use yubikey_piv::{
+    key::{self, AlgorithmId, SlotId},
+    transport::CardTransport,
+    Error, YubiKey,
+};
+
+/// Global allocator counting allocations
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// Card answering just enough to be opened and to sign with P-256
+struct SigningCard {
+    /// Dynamic authentication template with a dummy 72-byte ECDSA signature
+    signature_response: Vec<u8>,
+}
+
+impl SigningCard {
+    fn new() -> Self {
+        let mut signature_response = vec![0x7c, 0x4a, 0x82, 0x48];
+        signature_response.extend_from_slice(&[0x30; 72]);
+        signature_response.extend_from_slice(&[0x90, 0x00]);
+
+        SigningCard { signature_response }
+    }
+}
+
+impl CardTransport for SigningCard {
+    fn transmit(&self, send: &[u8], recv: &mut [u8]) -> Result<usize, Error> {
+        let response: &[u8] = match send[1] {
+            // SELECT
+            0xa4 => &[0x90, 0x00],
+            // GET VERSION
+            0xfd => &[0x05, 0x04, 0x03, 0x90, 0x00],
+            // GET SERIAL
+            0xf8 => &[0x00, 0xbc, 0x61, 0x4e, 0x90, 0x00],
+            // GENERAL AUTHENTICATE
+            0x87 => &self.signature_response,
+            ins => panic!("unexpected instruction: {:02x}", ins),
+        };
+
+        recv[..response.len()].copy_from_slice(response);
+        Ok(response.len())
+    }
+}
+
+const BATCH_SIZE: usize = 16;
+
+fn open() -> YubiKey {
+    YubiKey::open_with_transport(SigningCard::new()).unwrap()
+}
+
+fn sign(c: &mut Criterion) {
+    let mut yubikey = open();
+    let digest = [0x11; 32];
+    let inputs = [&digest[..]; BATCH_SIZE];
+
+    let mut group = c.benchmark_group("sign");
+    group.throughput(Throughput::Elements(BATCH_SIZE as u64));
+
+    group.bench_function(BenchmarkId::new("sign_data", BATCH_SIZE), |b| {
+        b.iter(|| {
+            for input in &inputs {
+                key::sign_data(&mut yubikey, input, AlgorithmId::EccP256, SlotId::Signature)
+                    .unwrap();
+            }
+        })
+    });
+
+    group.bench_function(BenchmarkId::new("sign_batch", BATCH_SIZE), |b| {
+        b.iter(|| {
+            key::sign_batch(
+                &mut yubikey,
+                &inputs,
+                AlgorithmId::EccP256,
+                SlotId::Signature,
+            )
+            .unwrap()
+        })
+    });
+
+    group.finish();
+}
+
+/// Print the number of allocations per signature of each signing path
+fn report_allocations() {
+    let mut yubikey = open();
+    let digest = [0x11; 32];
+    let inputs = [&digest[..]; BATCH_SIZE];
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for input in &inputs {
+        key::sign_data(&mut yubikey, input, AlgorithmId::EccP256, SlotId::Signature).unwrap();
+    }
+    let sign_data = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    key::sign_batch(
+        &mut yubikey,
+        &inputs,
+        AlgorithmId::EccP256,
+        SlotId::Signature,
+    )
+    .unwrap();
+    let sign_batch = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    println!(
+        "allocations per signature: sign_data {:.1}, sign_batch {:.1}",
+        sign_data as f64 / BATCH_SIZE as f64,
+        sign_batch as f64 / BATCH_SIZE as f64
+    );
+}
+
+criterion_group!(benches, sign);
+
+fn main() {
+    report_allocations();
+    benches();
+    Criterion::default().configure_from_args().final_summary();
+}
@@ -52,12 +52,15 @@ impl ReadersCmd {
         stream: &mut StandardStreamLock<'_>,
         index: usize,
         name: &str,
-        serial: Serial,
+        serial: Option<Serial>,
     ) -> Result<(), io::Error> {
         stream.set_color(ColorSpec::new().set_bold(true))?;
         write!(stream, "{:>3}:", index)?;
         stream.reset()?;
-        writeln!(stream, " {} (serial: {})", name, serial)?;
+        match serial {
+            Some(serial) => writeln!(stream, " {} (serial: {})", name, serial)?,
+            None => writeln!(stream, " {} (not a YubiKey)", name)?,
+        }
         stream.flush()?;
         Ok(())
     }
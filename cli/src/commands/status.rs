@@ -22,8 +22,17 @@ impl StatusCmd {
         s.reset().unwrap();
 
         self.attr(&mut s, "name", yk.name()).unwrap();
-        self.attr(&mut s, "version", yk.version()).unwrap();
-        self.attr(&mut s, "serial", yk.serial()).unwrap();
+        if let Some(version) = yk.version() {
+            self.attr(&mut s, "version", version).unwrap();
+        } else {
+            self.attr(&mut s, "version", NONE_STR).unwrap();
+        }
+
+        if let Some(serial) = yk.serial() {
+            self.attr(&mut s, "serial", serial).unwrap();
+        } else {
+            self.attr(&mut s, "serial", NONE_STR).unwrap();
+        }
 
         if let Ok(chuid) = yk.chuid() {
             self.attr(&mut s, "CHUID", chuid).unwrap();
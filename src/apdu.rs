@@ -30,17 +30,40 @@
 // (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
 // OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
-use crate::{error::Error, transaction::Transaction, Buffer};
-use log::trace;
+use crate::{
+    error::Error,
+    key::{AlgorithmId, SlotId},
+    metadata::OBJ_PRINTED,
+    serialization::Tlv,
+    transaction::Transaction,
+    Buffer,
+};
+use log::{debug, error, trace};
+use std::fmt;
 use zeroize::{Zeroize, Zeroizing};
 
 /// Maximum amount of command data that can be included in an APDU
 const APDU_DATA_MAX: usize = 0xFF;
 
+/// Dynamic authentication template (SP 800-73-4 Part 2, table 7)
+const TAG_DYN_AUTH: u8 = 0x7c;
+
+/// Dynamic authentication template: witness
+pub(crate) const TAG_AUTH_WITNESS: u8 = 0x80;
+
+/// Dynamic authentication template: challenge
+pub(crate) const TAG_AUTH_CHALLENGE: u8 = 0x81;
+
+/// Dynamic authentication template: response
+pub(crate) const TAG_AUTH_RESPONSE: u8 = 0x82;
+
+/// Dynamic authentication template: exponentiation (ECDH)
+pub(crate) const TAG_AUTH_EXPONENTIATION: u8 = 0x85;
+
 /// Application Protocol Data Unit (APDU).
 ///
 /// These messages are packets used to communicate with the YubiKey.
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Eq, PartialEq)]
 pub(crate) struct APDU {
     /// Instruction class: indicates the type of command (e.g. inter-industry or proprietary)
     cla: u8,
@@ -56,6 +79,12 @@ pub(crate) struct APDU {
 
     /// Command data to be sent (`lc` is calculated as `data.len()`)
     data: Vec<u8>,
+
+    /// Maximum number of response bytes expected, if any (`0` means 256)
+    le: Option<u8>,
+
+    /// Redact the response data from logs
+    redact_response: bool,
 }
 
 impl APDU {
@@ -67,6 +96,8 @@ impl APDU {
             p1: 0,
             p2: 0,
             data: vec![],
+            le: None,
+            redact_response: false,
         }
     }
 
@@ -108,27 +139,185 @@ impl APDU {
         self
     }
 
+    /// Set the expected response length (`le`) for this APDU
+    pub fn le(&mut self, value: u8) -> &mut Self {
+        self.le = Some(value);
+        self
+    }
+
+    /// Redact the response data from logs, e.g. for a GET RESPONSE which
+    /// continues the response to a command returning secrets.
+    ///
+    /// Responses to sensitive commands are always redacted.
+    pub fn redact_response(&mut self, redact: bool) -> &mut Self {
+        self.redact_response = redact;
+        self
+    }
+
     /// Transmit this APDU using the given card transaction
     pub fn transmit(&self, txn: &Transaction<'_>, recv_len: usize) -> Result<Response, Error> {
-        trace!(">>> {:?}", self);
+        trace!(">>> {}", self);
         let response = Response::from(txn.transmit(&self.to_bytes(), recv_len)?);
-        trace!("<<< {:?}", &response);
+
+        if self.response_is_sensitive() {
+            trace!(
+                "<<< {:?} <{} bytes redacted>",
+                response.status_words(),
+                response.data().len()
+            );
+        } else {
+            trace!("<<< {:?}", &response);
+        }
+
+        debug!("{}", self.summary(response.status_words()));
         Ok(response)
     }
 
+    /// Transmit this APDU like [`APDU::transmit`], receiving the response
+    /// into `recv_buffer` instead of allocating it.
+    ///
+    /// Returns the status words and the response data, borrowed from
+    /// `recv_buffer`.
+    pub fn transmit_into<'r>(
+        &self,
+        txn: &Transaction<'_>,
+        recv_buffer: &'r mut [u8],
+    ) -> Result<(StatusWords, &'r [u8]), Error> {
+        trace!(">>> {}", self);
+        let len = txn.transmit_into(&self.to_bytes(), recv_buffer)?;
+        let response = &recv_buffer[..len];
+
+        let (status_words, data) = if len < 2 {
+            (StatusWords::None, response)
+        } else {
+            let sw = (response[len - 2] as u16) << 8 | (response[len - 1] as u16);
+            (StatusWords::from(sw), &response[..len - 2])
+        };
+
+        if self.response_is_sensitive() {
+            trace!("<<< {:?} <{} bytes redacted>", status_words, data.len());
+        } else {
+            trace!("<<< {:?} {:?}", status_words, data);
+        }
+
+        debug!("{}", self.summary(status_words));
+        Ok((status_words, data))
+    }
+
     /// Serialize this APDU as a self-zeroizing byte buffer
     pub fn to_bytes(&self) -> Buffer {
-        let mut bytes = Vec::with_capacity(5 + self.data.len());
+        let mut bytes = Vec::with_capacity(6 + self.data.len());
         bytes.push(self.cla);
         bytes.push(self.ins.code());
         bytes.push(self.p1);
         bytes.push(self.p2);
-        bytes.push(self.data.len() as u8);
-        bytes.extend_from_slice(self.data.as_ref());
+
+        // Without command data, `le` takes the place of `lc`
+        if !self.data.is_empty() || self.le.is_none() {
+            bytes.push(self.data.len() as u8);
+            bytes.extend_from_slice(self.data.as_ref());
+        }
+
+        if let Some(le) = self.le {
+            bytes.push(le);
+        }
+
         Zeroizing::new(bytes)
     }
 }
 
+impl APDU {
+    /// Command data, unless it may contain secrets
+    fn loggable_data(&self) -> Option<&[u8]> {
+        if self.ins.is_sensitive() && !self.data.is_empty() {
+            None
+        } else {
+            Some(&self.data[..])
+        }
+    }
+
+    /// May the response to this command contain secrets?
+    fn response_is_sensitive(&self) -> bool {
+        self.redact_response || response_is_sensitive(self.ins, &self.data)
+    }
+
+    /// One-line summary of an exchange for debug logging, e.g.
+    /// `INS 0x87 -> 0x9000`.
+    ///
+    /// Only the instruction and status words are included, never command or
+    /// response data, so unlike the `trace!` dumps it is safe to log for
+    /// commands carrying PINs or keys.
+    fn summary(&self, status_words: StatusWords) -> String {
+        format!(
+            "INS {:#04x} -> {:#06x}",
+            self.ins.code(),
+            status_words.code()
+        )
+    }
+}
+
+impl fmt::Debug for APDU {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("APDU");
+        s.field("cla", &self.cla)
+            .field("ins", &self.ins)
+            .field("p1", &self.p1)
+            .field("p2", &self.p2);
+
+        match self.loggable_data() {
+            Some(data) => s.field("data", &data),
+            None => s.field(
+                "data",
+                &format_args!("<{} bytes redacted>", self.data.len()),
+            ),
+        };
+
+        s.field("le", &self.le).finish()
+    }
+}
+
+/// Renders the APDU as hex, e.g.:
+///
+/// ```text
+/// CLA 00 INS cb P1 3f P2 ff Lc 05 DATA 5c 03 5f c1 02
+/// ```
+///
+/// Command data which may contain secrets (PINs, keys) is redacted.
+impl fmt::Display for APDU {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "CLA {:02x} INS {:02x} P1 {:02x} P2 {:02x}",
+            self.cla,
+            self.ins.code(),
+            self.p1,
+            self.p2
+        )?;
+
+        if !self.data.is_empty() || self.le.is_none() {
+            write!(f, " Lc {:02x}", self.data.len())?;
+        }
+
+        if !self.data.is_empty() {
+            match self.loggable_data() {
+                Some(data) => {
+                    f.write_str(" DATA")?;
+                    for byte in data {
+                        write!(f, " {:02x}", byte)?;
+                    }
+                }
+                None => write!(f, " DATA <{} bytes redacted>", self.data.len())?,
+            }
+        }
+
+        if let Some(le) = self.le {
+            write!(f, " Le {:02x}", le)?;
+        }
+
+        Ok(())
+    }
+}
+
 impl Drop for APDU {
     fn drop(&mut self) {
         self.zeroize();
@@ -142,6 +331,195 @@ impl Zeroize for APDU {
     }
 }
 
+/// May the response to a command with the given instruction and command data
+/// contain secrets?
+///
+/// This is the case for sensitive commands (see [`Ins::is_sensitive`]), for
+/// GET DATA of the PIN-protected data object, which holds the management
+/// key, and for GENERAL AUTHENTICATE, which returns RSA decryption plaintext
+/// and ECDH shared secrets (raw RSA signing and decryption look the same to
+/// the card, so all of its responses are redacted).
+pub(crate) fn response_is_sensitive(ins: Ins, data: &[u8]) -> bool {
+    ins.is_sensitive()
+        || ins == Ins::Authenticate
+        || (ins == Ins::GetData && data.ends_with(&OBJ_PRINTED.to_be_bytes()[1..]))
+}
+
+/// Header of the APDUs sent by [`Transaction::transfer_data`], which fills
+/// in the command data (and the command chaining bit of the class).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct ApduTemplate {
+    /// Instruction class
+    pub cla: u8,
+
+    /// Instruction code
+    pub ins: Ins,
+
+    /// Instruction parameter 1
+    pub p1: u8,
+
+    /// Instruction parameter 2
+    pub p2: u8,
+}
+
+impl ApduTemplate {
+    /// Create a new template with the given instruction code and parameters
+    pub fn new(ins: Ins, p1: u8, p2: u8) -> Self {
+        Self {
+            cla: 0,
+            ins,
+            p1,
+            p2,
+        }
+    }
+
+    /// Serialize this template as `[cla, ins, p1, p2]`
+    pub fn to_bytes(self) -> [u8; 4] {
+        [self.cla, self.ins.code(), self.p1, self.p2]
+    }
+}
+
+/// Builder for the dynamic authentication template sent as the command data
+/// of GENERAL AUTHENTICATE (SP 800-73-4 Part 2, section 3.2.4).
+///
+/// Data objects are written in the order they are added. An empty data
+/// object asks the card to return that object in its response.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct GeneralAuthenticate<'a> {
+    /// Algorithm reference (P1)
+    algorithm: u8,
+
+    /// Key reference (P2)
+    key: u8,
+
+    /// Tags and values of the data objects in the template
+    objects: Vec<(u8, &'a [u8])>,
+}
+
+impl<'a> GeneralAuthenticate<'a> {
+    /// Create an empty template for the given algorithm and key references
+    pub fn new(algorithm: u8, key: u8) -> Self {
+        Self {
+            algorithm,
+            key,
+            objects: vec![],
+        }
+    }
+
+    /// Template signing `data` (already padded for RSA) with the given key
+    pub fn sign(algorithm: AlgorithmId, key: SlotId, data: &'a [u8]) -> Self {
+        Self::new(algorithm.into(), key.into())
+            .object(TAG_AUTH_RESPONSE, &[])
+            .object(TAG_AUTH_CHALLENGE, data)
+    }
+
+    /// Template decrypting `data` with an RSA key, or performing ECDH with
+    /// an ECC key and the peer's public point in `data`
+    pub fn decipher(algorithm: AlgorithmId, key: SlotId, data: &'a [u8]) -> Self {
+        let tag = match algorithm {
+            AlgorithmId::EccP256 | AlgorithmId::EccP384 => TAG_AUTH_EXPONENTIATION,
+            AlgorithmId::Rsa1024 | AlgorithmId::Rsa2048 => TAG_AUTH_CHALLENGE,
+        };
+
+        Self::new(algorithm.into(), key.into())
+            .object(TAG_AUTH_RESPONSE, &[])
+            .object(tag, data)
+    }
+
+    /// Add a data object to the template
+    pub fn object(mut self, tag: u8, value: &'a [u8]) -> Self {
+        self.objects.push((tag, value));
+        self
+    }
+
+    /// Header of the GENERAL AUTHENTICATE APDU(s) carrying this template
+    pub fn template(&self) -> ApduTemplate {
+        ApduTemplate::new(Ins::Authenticate, self.algorithm, self.key)
+    }
+
+    /// Send the template in a single GENERAL AUTHENTICATE APDU
+    pub fn transmit(&self, txn: &Transaction<'_>) -> Result<Response, Error> {
+        APDU::new(Ins::Authenticate)
+            .params(self.algorithm, self.key)
+            .data(self.to_bytes()?)
+            .transmit(txn, 261)
+    }
+
+    /// Length of the serialized template
+    pub fn encoded_len(&self) -> usize {
+        tlv_len(self.value_len())
+    }
+
+    /// Serialize the template as command data into `buf`, returning the
+    /// number of bytes written
+    pub fn write(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        Tlv::write_as(buf, TAG_DYN_AUTH, self.value_len(), |buf| {
+            let mut offset = 0;
+
+            for &(tag, value) in &self.objects {
+                offset += Tlv::write(&mut buf[offset..], tag, value).expect("large enough");
+            }
+        })
+    }
+
+    /// Serialize the template as command data
+    pub fn to_bytes(&self) -> Result<Buffer, Error> {
+        let mut bytes = Zeroizing::new(vec![0u8; self.encoded_len()]);
+        self.write(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Length of the contents of the template
+    fn value_len(&self) -> usize {
+        self.objects
+            .iter()
+            .map(|(_, value)| tlv_len(value.len()))
+            .sum()
+    }
+
+    /// Find the value of the data object with the given tag in the dynamic
+    /// authentication template returned by the card.
+    pub fn parse_response(response: &[u8], tag: u8) -> Result<&[u8], Error> {
+        let (_, template) = Tlv::parse(response)?;
+
+        if template.tag != TAG_DYN_AUTH {
+            error!(
+                "expected a dynamic authentication template, found tag {:02x}",
+                template.tag
+            );
+            return Err(Error::ParseError);
+        }
+
+        let mut objects = template.value;
+
+        while !objects.is_empty() {
+            let (remaining, object) = Tlv::parse(objects)?;
+
+            if object.tag == tag {
+                return Ok(object.value);
+            }
+
+            objects = remaining;
+        }
+
+        error!("dynamic authentication template lacks tag {:02x}", tag);
+        Err(Error::ParseError)
+    }
+}
+
+/// Size of a TLV with a value of the given length
+fn tlv_len(len: usize) -> usize {
+    let len_len = if len < 0x80 {
+        1
+    } else if len < 0x100 {
+        2
+    } else {
+        3
+    };
+
+    1 + len_len + len
+}
+
 /// APDU instruction codes
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Ins {
@@ -195,6 +573,9 @@ pub enum Ins {
     /// Get device serial
     GetSerial,
 
+    /// Get key or PIN metadata (YubiKey 5.3+)
+    GetMetadata,
+
     /// Other/unrecognized instruction codes
     Other(u8),
 }
@@ -219,11 +600,30 @@ impl Ins {
             Ins::SetPinRetries => 0xfa,
             Ins::Attest => 0xf9,
             Ins::GetSerial => 0xf8,
+            Ins::GetMetadata => 0xf7,
             Ins::Other(code) => code,
         }
     }
 }
 
+impl Ins {
+    /// May the command data for this instruction contain secrets?
+    pub fn is_sensitive(self) -> bool {
+        match self {
+            Ins::Verify
+            | Ins::ChangeReference
+            | Ins::ResetRetry
+            | Ins::SetMgmKey
+            | Ins::ImportKey
+            // Objects written include the PIN-protected management key
+            | Ins::PutData => true,
+            // Management application WRITE CONFIG, which can carry lock codes
+            Ins::Other(0x1c) => true,
+            _ => false,
+        }
+    }
+}
+
 impl From<u8> for Ins {
     fn from(code: u8) -> Self {
         match code {
@@ -243,6 +643,7 @@ impl From<u8> for Ins {
             0xfa => Ins::SetPinRetries,
             0xf9 => Ins::Attest,
             0xf8 => Ins::GetSerial,
+            0xf7 => Ins::GetMetadata,
             code => Ins::Other(code),
         }
     }
@@ -360,6 +761,9 @@ pub(crate) enum StatusWords {
     /// https://github.com/Yubico/yubikey-manager/blob/1f22620b623c6b345dd9f9193ec765a542dddc80/ykman/driver_ccid.py#L55
     WrongLengthError,
 
+    /// Secure messaging not supported
+    SecureMessagingUnsupported,
+
     /// Security status not satisfied
     SecurityStatusError,
 
@@ -384,15 +788,21 @@ pub(crate) enum StatusWords {
     /// Not enough memory
     NoSpaceError,
 
+    /// Referenced data (e.g. a key) not found
+    ReferenceNotFoundError,
+
+    /// Instruction code not supported or invalid
+    InstructionNotSupported,
+
+    /// Class not supported
+    ClassNotSupported,
+
     //
     // Custom Yubico Status Word extensions
     //
     /// Incorrect card slot error
     IncorrectSlotError,
 
-    /// Not supported error
-    NotSupportedError,
-
     /// https://github.com/Yubico/yubikey-manager/blob/1f22620b623c6b345dd9f9193ec765a542dddc80/ykman/driver_ccid.py#L65
     CommandAbortedError,
 
@@ -406,8 +816,9 @@ impl StatusWords {
         match self {
             StatusWords::None => 0,
             StatusWords::NoInputDataError => 0x6285,
-            StatusWords::VerifyFailError { tries } => 0x63c0 & tries as u16,
+            StatusWords::VerifyFailError { tries } => 0x63c0 | (tries & 0x0f) as u16,
             StatusWords::WrongLengthError => 0x6700,
+            StatusWords::SecureMessagingUnsupported => 0x6882,
             StatusWords::SecurityStatusError => 0x6982,
             StatusWords::AuthBlockedError => 0x6983,
             StatusWords::DataInvalidError => 0x6984,
@@ -416,8 +827,10 @@ impl StatusWords {
             StatusWords::IncorrectParamError => 0x6a80,
             StatusWords::NotFoundError => 0x6a82,
             StatusWords::NoSpaceError => 0x6a84,
+            StatusWords::ReferenceNotFoundError => 0x6a88,
             StatusWords::IncorrectSlotError => 0x6b00,
-            StatusWords::NotSupportedError => 0x6d00,
+            StatusWords::InstructionNotSupported => 0x6d00,
+            StatusWords::ClassNotSupported => 0x6e00,
             StatusWords::CommandAbortedError => 0x6f00,
             StatusWords::Success => 0x9000,
             StatusWords::Other(n) => n,
@@ -428,6 +841,24 @@ impl StatusWords {
     pub fn is_success(self) -> bool {
         self == StatusWords::Success
     }
+
+    /// Error to report for these status words when a command fails and the
+    /// caller has no more specific interpretation of them.
+    ///
+    /// Unknown instructions and classes are reported as
+    /// [`Error::NotSupported`], which allows detecting commands missing from
+    /// a device's firmware (or from non-YubiKey PIV cards). Secure messaging
+    /// being unsupported is reported as [`Error::SecureMessagingUnsupported`],
+    /// so that callers can fall back to plain APDUs.
+    pub fn to_error(self) -> Error {
+        match self {
+            StatusWords::InstructionNotSupported | StatusWords::ClassNotSupported => {
+                Error::NotSupported
+            }
+            StatusWords::SecureMessagingUnsupported => Error::SecureMessagingUnsupported,
+            _ => Error::GenericError,
+        }
+    }
 }
 
 impl From<u16> for StatusWords {
@@ -439,6 +870,7 @@ impl From<u16> for StatusWords {
                 tries: (sw & 0x000f) as u8,
             },
             0x6700 => StatusWords::WrongLengthError,
+            0x6882 => StatusWords::SecureMessagingUnsupported,
             0x6982 => StatusWords::SecurityStatusError,
             0x6983 => StatusWords::AuthBlockedError,
             0x6984 => StatusWords::DataInvalidError,
@@ -447,8 +879,10 @@ impl From<u16> for StatusWords {
             0x6a80 => StatusWords::IncorrectParamError,
             0x6a82 => StatusWords::NotFoundError,
             0x6a84 => StatusWords::NoSpaceError,
+            0x6a88 => StatusWords::ReferenceNotFoundError,
             0x6b00 => StatusWords::IncorrectSlotError,
-            0x6d00 => StatusWords::NotSupportedError,
+            0x6d00 => StatusWords::InstructionNotSupported,
+            0x6e00 => StatusWords::ClassNotSupported,
             0x6f00 => StatusWords::CommandAbortedError,
             0x9000 => StatusWords::Success,
             _ => StatusWords::Other(sw),
@@ -461,3 +895,222 @@ impl From<StatusWords> for u16 {
         sw.code()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apdu_template_bytes() {
+        // the raw templates `transfer_data` used to take
+        let templates = [
+            (Ins::Authenticate, 0x11, 0x9c, [0x00, 0x87, 0x11, 0x9c]),
+            (Ins::ChangeReference, 0x00, 0x80, [0x00, 0x24, 0x00, 0x80]),
+            (Ins::GetData, 0x3f, 0xff, [0x00, 0xcb, 0x3f, 0xff]),
+            (Ins::PutData, 0x3f, 0xff, [0x00, 0xdb, 0x3f, 0xff]),
+        ];
+
+        for &(ins, p1, p2, bytes) in &templates {
+            assert_eq!(ApduTemplate::new(ins, p1, p2).to_bytes(), bytes);
+        }
+    }
+
+    #[test]
+    fn apdu_display() {
+        let mut get_data = APDU::new(Ins::GetData);
+        get_data
+            .params(0x3f, 0xff)
+            .data(&[0x5c, 0x03, 0x5f, 0xc1, 0x02]);
+        assert_eq!(
+            get_data.to_string(),
+            "CLA 00 INS cb P1 3f P2 ff Lc 05 DATA 5c 03 5f c1 02"
+        );
+
+        let mut get_response = APDU::new(Ins::GetResponseApdu);
+        get_response.le(0x10);
+        assert_eq!(get_response.to_string(), "CLA 00 INS c0 P1 00 P2 00 Le 10");
+    }
+
+    #[test]
+    fn apdu_redaction() {
+        let mut verify = APDU::new(Ins::Verify);
+        verify.params(0x00, 0x80).data(b"123456\xff\xff");
+
+        assert_eq!(
+            verify.to_string(),
+            "CLA 00 INS 20 P1 00 P2 80 Lc 08 DATA <8 bytes redacted>"
+        );
+        assert_eq!(
+            format!("{:?}", verify),
+            "APDU { cla: 0, ins: Verify, p1: 0, p2: 128, data: <8 bytes redacted>, le: None }"
+        );
+    }
+
+    #[test]
+    fn response_redaction() {
+        let mut get_protected = APDU::new(Ins::GetData);
+        get_protected
+            .params(0x3f, 0xff)
+            .data(&[0x5c, 0x03, 0x5f, 0xc1, 0x09]);
+        assert!(get_protected.response_is_sensitive());
+
+        let mut get_chuid = APDU::new(Ins::GetData);
+        get_chuid
+            .params(0x3f, 0xff)
+            .data(&[0x5c, 0x03, 0x5f, 0xc1, 0x02]);
+        assert!(!get_chuid.response_is_sensitive());
+
+        // ECDH shared secret
+        let mut ecdh = APDU::new(Ins::Authenticate);
+        ecdh.params(0x14, 0x9d)
+            .data(&[0x7c, 0x05, 0x82, 0x00, 0x85, 0x01, 0x04]);
+        assert!(ecdh.response_is_sensitive());
+
+        // Final chunk of a chained RSA decryption, without the template header
+        let mut decrypt = APDU::new(Ins::Authenticate);
+        decrypt.params(0x07, 0x9d).data(&[0xaa; 5]);
+        assert!(decrypt.response_is_sensitive());
+
+        let mut get_response = APDU::new(Ins::GetResponseApdu);
+        assert!(!get_response.response_is_sensitive());
+        assert!(get_response.redact_response(true).response_is_sensitive());
+
+        let mut put_data = APDU::new(Ins::PutData);
+        put_data
+            .params(0x3f, 0xff)
+            .data(&[0x5c, 0x03, 0x5f, 0xc1, 0x09]);
+        assert_eq!(
+            put_data.to_string(),
+            "CLA 00 INS db P1 3f P2 ff Lc 05 DATA <5 bytes redacted>"
+        );
+    }
+
+    #[test]
+    fn apdu_summary() {
+        let mut verify = APDU::new(Ins::Verify);
+        verify.params(0x00, 0x80).data(b"123456\xff\xff");
+
+        assert_eq!(verify.summary(StatusWords::Success), "INS 0x20 -> 0x9000");
+        assert_eq!(
+            verify.summary(StatusWords::VerifyFailError { tries: 2 }),
+            "INS 0x20 -> 0x63c2"
+        );
+    }
+
+    #[test]
+    fn general_authenticate_bytes() {
+        let digest = [0x11; 32];
+        let mut sign = vec![0x7c, 0x24, 0x82, 0x00, 0x81, 0x20];
+        sign.extend_from_slice(&digest);
+
+        let request = GeneralAuthenticate::sign(AlgorithmId::EccP256, SlotId::Signature, &digest);
+        assert_eq!(request.template().to_bytes(), [0x00, 0x87, 0x11, 0x9c]);
+        assert_eq!(request.to_bytes().unwrap().as_slice(), sign.as_slice());
+
+        // ECDH uses the exponentiation tag
+        let point = [0x04; 65];
+        let request =
+            GeneralAuthenticate::decipher(AlgorithmId::EccP256, SlotId::KeyManagement, &point);
+        assert_eq!(
+            &request.to_bytes().unwrap()[..6],
+            &[0x7c, 0x45, 0x82, 0x00, 0x85, 0x41]
+        );
+
+        // RSA decryption uses the challenge tag, with long form lengths
+        let ciphertext = [0x55; 256];
+        let request =
+            GeneralAuthenticate::decipher(AlgorithmId::Rsa2048, SlotId::KeyManagement, &ciphertext);
+        let bytes = request.to_bytes().unwrap();
+        assert_eq!(bytes.len(), 266);
+        assert_eq!(
+            &bytes[..10],
+            &[0x7c, 0x82, 0x01, 0x06, 0x82, 0x00, 0x81, 0x82, 0x01, 0x00]
+        );
+
+        // management key witness request
+        let request = GeneralAuthenticate::new(0x03, 0x9b).object(TAG_AUTH_WITNESS, &[]);
+        assert_eq!(
+            request.to_bytes().unwrap().as_slice(),
+            &[0x7c, 0x02, 0x80, 0x00]
+        );
+    }
+
+    #[test]
+    fn general_authenticate_response() {
+        let response = [0x7c, 0x06, 0x80, 0x00, 0x82, 0x02, 0xaa, 0xbb];
+
+        assert_eq!(
+            GeneralAuthenticate::parse_response(&response, TAG_AUTH_RESPONSE),
+            Ok(&[0xaa, 0xbb][..])
+        );
+        assert_eq!(
+            GeneralAuthenticate::parse_response(&response, TAG_AUTH_CHALLENGE),
+            Err(Error::ParseError)
+        );
+        assert_eq!(
+            GeneralAuthenticate::parse_response(&[0x30, 0x02, 0x82, 0x00], TAG_AUTH_RESPONSE),
+            Err(Error::ParseError)
+        );
+    }
+
+    #[test]
+    fn status_words_decoding() {
+        let status_words = [
+            (0x0000, StatusWords::None),
+            (0x6285, StatusWords::NoInputDataError),
+            (0x63c0, StatusWords::VerifyFailError { tries: 0 }),
+            (0x63c3, StatusWords::VerifyFailError { tries: 3 }),
+            (0x6700, StatusWords::WrongLengthError),
+            (0x6882, StatusWords::SecureMessagingUnsupported),
+            (0x6982, StatusWords::SecurityStatusError),
+            (0x6983, StatusWords::AuthBlockedError),
+            (0x6984, StatusWords::DataInvalidError),
+            (0x6985, StatusWords::ConditionsNotSatisfiedError),
+            (0x6986, StatusWords::CommandNotAllowedError),
+            (0x6a80, StatusWords::IncorrectParamError),
+            (0x6a82, StatusWords::NotFoundError),
+            (0x6a84, StatusWords::NoSpaceError),
+            (0x6a88, StatusWords::ReferenceNotFoundError),
+            (0x6b00, StatusWords::IncorrectSlotError),
+            (0x6d00, StatusWords::InstructionNotSupported),
+            (0x6e00, StatusWords::ClassNotSupported),
+            (0x6f00, StatusWords::CommandAbortedError),
+            (0x9000, StatusWords::Success),
+            (0x6a81, StatusWords::Other(0x6a81)),
+        ];
+
+        for &(code, sw) in &status_words {
+            assert_eq!(StatusWords::from(code), sw, "decoding {:04x}", code);
+            assert_eq!(sw.code(), code, "encoding {:?}", sw);
+        }
+    }
+
+    #[test]
+    fn unsupported_status_words() {
+        assert_eq!(
+            StatusWords::from(0x6d00),
+            StatusWords::InstructionNotSupported
+        );
+        assert_eq!(StatusWords::from(0x6e00), StatusWords::ClassNotSupported);
+        assert_eq!(StatusWords::ClassNotSupported.code(), 0x6e00);
+
+        assert_eq!(
+            StatusWords::InstructionNotSupported.to_error(),
+            Error::NotSupported
+        );
+        assert_eq!(
+            StatusWords::ClassNotSupported.to_error(),
+            Error::NotSupported
+        );
+        assert_eq!(StatusWords::NotFoundError.to_error(), Error::GenericError);
+    }
+
+    #[test]
+    fn secure_messaging_unsupported() {
+        assert_eq!(
+            StatusWords::from(0x6882).to_error(),
+            Error::SecureMessagingUnsupported
+        );
+        assert_eq!(StatusWords::SecureMessagingUnsupported.code(), 0x6882);
+    }
+}
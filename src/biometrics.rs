@@ -0,0 +1,70 @@
+//! Cardholder biometric data objects: the Cardholder Fingerprints and the
+//! Cardholder Facial Image.
+//!
+//! See NIST SP 800-73-4 Part 1, sections 3.1.3 and 3.1.5. Both objects hold a
+//! CBEFF-wrapped biometric record, which is stored and retrieved as-is without
+//! parsing the biometric payload. Reading them requires the PIN to be
+//! verified first.
+
+use crate::{error::Error, serialization::*, yubikey::YubiKey, Buffer, ObjectId, CB_OBJ_MAX};
+use log::error;
+
+/// Tag of the CBEFF record within a biometric object
+const TAG_BIOMETRIC_DATA: u8 = 0xbc;
+
+/// Tag of the (empty) Error Detection Code
+const TAG_ERROR_DETECTION_CODE: u8 = 0xfe;
+
+/// PIV biometric data objects
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BiometricObject {
+    /// Cardholder Fingerprints
+    Fingerprints,
+
+    /// Cardholder Facial Image
+    FacialImage,
+}
+
+impl BiometricObject {
+    /// Object ID of this biometric object
+    pub fn object_id(self) -> ObjectId {
+        match self {
+            BiometricObject::Fingerprints => 0x005f_c103,
+            BiometricObject::FacialImage => 0x005f_c108,
+        }
+    }
+}
+
+/// Read the CBEFF record stored in the given biometric object.
+///
+/// Returns [`Error::NotFound`] if the object is empty. Fails if the PIN
+/// hasn't been verified.
+pub fn read(yubikey: &mut YubiKey, object: BiometricObject) -> Result<Buffer, Error> {
+    let txn = yubikey.begin_transaction()?;
+    let response = txn.fetch_object(object.object_id())?;
+    let mut data = response.as_slice();
+
+    while !data.is_empty() {
+        let (remaining, tlv) = Tlv::parse(data)?;
+        data = remaining;
+
+        if tlv.tag == TAG_BIOMETRIC_DATA {
+            return Ok(Buffer::new(tlv.value.to_vec()));
+        }
+    }
+
+    error!("{:?} object contains no biometric data", object);
+    Err(Error::ParseError)
+}
+
+/// Store a CBEFF record in the given biometric object.
+///
+/// Requires prior authentication with the management key.
+pub fn write(yubikey: &mut YubiKey, object: BiometricObject, cbeff: &[u8]) -> Result<(), Error> {
+    let mut buf = Buffer::new(vec![0u8; CB_OBJ_MAX]);
+    let mut len = Tlv::write(&mut buf, TAG_BIOMETRIC_DATA, cbeff)?;
+    len += Tlv::write(&mut buf[len..], TAG_ERROR_DETECTION_CODE, &[])?;
+
+    let txn = yubikey.begin_transaction()?;
+    txn.save_object(object.object_id(), &buf[..len])
+}
@@ -104,14 +104,21 @@ impl CCC {
     /// Get Cardholder Capability Container (CCC) ID
     #[cfg(feature = "untested")]
     pub fn set(&self, yubikey: &mut YubiKey) -> Result<(), Error> {
-        let mut buf = CCC_TMPL.to_vec();
-        buf[0..self.0.len()].copy_from_slice(&self.0);
+        let buf = serialize_ccc(self);
 
         let txn = yubikey.begin_transaction()?;
         txn.save_object(OBJ_CAPABILITY, &buf)
     }
 }
 
+/// Serialize a CCC into the contents of the Card Capability Container data
+/// object, exactly as written to the card by `CCC::set`.
+pub fn serialize_ccc(ccc: &CCC) -> Vec<u8> {
+    let mut buf = CCC_TMPL.to_vec();
+    buf[0..ccc.0.len()].copy_from_slice(&ccc.0);
+    buf
+}
+
 impl Debug for CCC {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "CCC({:?})", &self.0[..])
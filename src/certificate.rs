@@ -32,15 +32,19 @@
 
 use crate::{
     error::Error,
-    key::{sign_data, AlgorithmId, SlotId},
+    key::{self, sign_data, AlgorithmId, HashAlgorithm, SlotId},
+    mgm::MgmKey,
+    pem,
+    policy::{PinPolicy, TouchPolicy},
     serialization::*,
     transaction::Transaction,
     yubikey::YubiKey,
     Buffer,
 };
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, TimeZone, Utc};
 use elliptic_curve::sec1::EncodedPoint as EcPublicKey;
-use log::error;
+use flate2::{write::GzEncoder, Compression};
+use log::{debug, error};
 use num_bigint_dig::BigUint;
 use p256::NistP256;
 use p384::NistP384;
@@ -48,8 +52,10 @@ use rsa::{PublicKeyParts, RSAPublicKey};
 use sha2::{Digest, Sha256};
 use std::convert::TryFrom;
 use std::fmt;
+use std::io::Write;
 use std::ops::DerefMut;
 use x509::{der::Oid, RelativeDistinguishedName};
+use x509_builder as x509;
 use x509_parser::{parse_x509_certificate, x509::SubjectPublicKeyInfo};
 use zeroize::Zeroizing;
 
@@ -66,7 +72,7 @@ const TAG_CERT_COMPRESS: u8 = 0x71;
 const TAG_CERT_LRC: u8 = 0xFE;
 
 /// A serial number for a [`Certificate`].
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Serial(BigUint);
 
 impl From<BigUint> for Serial {
@@ -99,6 +105,19 @@ impl Serial {
     }
 }
 
+/// Outcome of writing a certificate with [`Certificate::write`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WriteResult {
+    /// Size of the certificate data stored on the card
+    pub stored_bytes: usize,
+
+    /// Size of the uncompressed DER certificate
+    pub original_bytes: usize,
+
+    /// Whether the certificate was stored compressed
+    pub certinfo: CertInfo,
+}
+
 /// Information about how a [`Certificate`] is stored within a YubiKey.
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum CertInfo {
@@ -234,6 +253,111 @@ impl PublicKeyInfo {
             PublicKeyInfo::EcP384(_) => AlgorithmId::EccP384,
         }
     }
+
+    /// Verify a signature over `message` made by the corresponding private key.
+    ///
+    /// The message is hashed with `hash`. RSA signatures are checked as
+    /// PKCS#1 v1.5, and EC signatures as ASN.1 DER-encoded ECDSA, like those
+    /// produced by [`Certificate::generate_self_signed`].
+    ///
+    /// Returns `Ok(false)` if the signature is invalid, and
+    /// [`Error::NotSupported`] for P-384 keys, as the `p384` crate doesn't
+    /// implement ECDSA verification yet.
+    pub fn verify(
+        &self,
+        message: &[u8],
+        signature: &[u8],
+        hash: HashAlgorithm,
+    ) -> Result<bool, Error> {
+        let digest = hash.digest(message);
+
+        match self {
+            PublicKeyInfo::Rsa { pubkey, .. } => {
+                use rsa::{hash::Hash, PaddingScheme, PublicKey};
+
+                let hash = match hash {
+                    HashAlgorithm::Sha1 => Hash::SHA1,
+                    HashAlgorithm::Sha256 => Hash::SHA2_256,
+                    HashAlgorithm::Sha384 => Hash::SHA2_384,
+                    HashAlgorithm::Sha512 => Hash::SHA2_512,
+                };
+
+                Ok(pubkey
+                    .verify(
+                        PaddingScheme::PKCS1v15Sign { hash: Some(hash) },
+                        &digest,
+                        signature,
+                    )
+                    .is_ok())
+            }
+            PublicKeyInfo::EcP256(pubkey) => {
+                use ecdsa::hazmat::VerifyPrimitive;
+                use p256::{ecdsa::Signature, FieldBytes, PublicKey};
+
+                let pubkey = PublicKey::from_sec1_bytes(pubkey.as_bytes())
+                    .map_err(|_| Error::InvalidObject)?;
+
+                let signature = match Signature::from_der(signature) {
+                    Ok(signature) => signature,
+                    Err(_) => return Ok(false),
+                };
+
+                // ECDSA uses the leftmost bits of the digest (see `sign_ecdsa`)
+                let len = digest.len().min(32);
+                let mut hashed = FieldBytes::default();
+                hashed[32 - len..].copy_from_slice(&digest[..len]);
+
+                Ok(pubkey
+                    .as_affine()
+                    .verify_prehashed(&hashed, &signature)
+                    .is_ok())
+            }
+            PublicKeyInfo::EcP384(_) => {
+                error!("verifying P-384 signatures is not supported");
+                Err(Error::NotSupported)
+            }
+        }
+    }
+
+    /// DER encoding of this key as an X.509 SubjectPublicKeyInfo (RFC 5280,
+    /// RFC 3279 and RFC 5480).
+    pub fn to_spki_der(&self) -> Vec<u8> {
+        let algorithm_identifier: &[u8] = match self.algorithm() {
+            // rsaEncryption with NULL parameters
+            AlgorithmId::Rsa1024 | AlgorithmId::Rsa2048 => &[
+                0x30, 0x0d, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01, 0x05,
+                0x00,
+            ],
+            // id-ecPublicKey with the secp256r1 named curve
+            AlgorithmId::EccP256 => &[
+                0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a,
+                0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07,
+            ],
+            // id-ecPublicKey with the secp384r1 named curve
+            AlgorithmId::EccP384 => &[
+                0x30, 0x10, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x05, 0x2b,
+                0x81, 0x04, 0x00, 0x22,
+            ],
+        };
+
+        let mut subject_public_key = vec![0x00]; // no unused bits
+        subject_public_key.extend_from_slice(&x509::SubjectPublicKeyInfo::public_key(self));
+
+        let mut content = algorithm_identifier.to_vec();
+        // public keys are far smaller than the longest DER element we encode
+        content.extend_from_slice(
+            &der::encode(der::BIT_STRING, &subject_public_key).expect("public key fits"),
+        );
+        der::encode(der::SEQUENCE, &content).expect("public key fits")
+    }
+
+    /// SHA-256 hash of the DER-encoded SubjectPublicKeyInfo (see
+    /// [`PublicKeyInfo::to_spki_der`]), which identifies the key.
+    pub fn thumbprint_sha256(&self) -> [u8; 32] {
+        let mut thumbprint = [0u8; 32];
+        thumbprint.copy_from_slice(&Sha256::digest(&self.to_spki_der()));
+        thumbprint
+    }
 }
 
 impl x509::SubjectPublicKeyInfo for PublicKeyInfo {
@@ -298,6 +422,16 @@ enum SignatureId {
     EcdsaWithSha256,
 }
 
+impl SignatureId {
+    /// Signature algorithm used for signing with keys of the given algorithm
+    fn for_key(algorithm: AlgorithmId) -> Self {
+        match algorithm {
+            AlgorithmId::Rsa1024 | AlgorithmId::Rsa2048 => SignatureId::Sha256WithRsaEncryption,
+            AlgorithmId::EccP256 | AlgorithmId::EccP384 => SignatureId::EcdsaWithSha256,
+        }
+    }
+}
+
 impl x509::AlgorithmIdentifier for SignatureId {
     type AlgorithmOid = &'static [u64];
 
@@ -323,6 +457,8 @@ pub struct Certificate {
     serial: Serial,
     issuer: String,
     subject: String,
+    not_before: DateTime<Utc>,
+    not_after: DateTime<Utc>,
     subject_pki: PublicKeyInfo,
     data: Buffer,
 }
@@ -355,10 +491,7 @@ impl Certificate {
 
         let mut tbs_cert = Buffer::new(Vec::with_capacity(CB_OBJ_MAX));
 
-        let signature_algorithm = match subject_pki.algorithm() {
-            AlgorithmId::Rsa1024 | AlgorithmId::Rsa2048 => SignatureId::Sha256WithRsaEncryption,
-            AlgorithmId::EccP256 | AlgorithmId::EccP384 => SignatureId::EcdsaWithSha256,
-        };
+        let signature_algorithm = SignatureId::for_key(subject_pki.algorithm());
 
         cookie_factory::gen(
             x509::write::tbs_certificate(
@@ -376,51 +509,7 @@ impl Certificate {
         )
         .expect("can serialize to Vec");
 
-        let signature = match signature_algorithm {
-            SignatureId::Sha256WithRsaEncryption => {
-                use cookie_factory::{combinator::slice, sequence::tuple};
-                use x509::{
-                    der::write::{der_octet_string, der_sequence},
-                    write::algorithm_identifier,
-                };
-
-                let em_len = if let AlgorithmId::Rsa1024 = subject_pki.algorithm() {
-                    128
-                } else {
-                    256
-                };
-
-                let h = Sha256::digest(&tbs_cert);
-
-                let t = cookie_factory::gen_simple(
-                    der_sequence((
-                        algorithm_identifier(&DigestId::Sha256),
-                        der_octet_string(&h),
-                    )),
-                    vec![],
-                )
-                .expect("can serialize into Vec");
-
-                let em = cookie_factory::gen_simple(
-                    tuple((
-                        slice(&[0x00, 0x01]),
-                        slice(&vec![0xff; em_len - t.len() - 3]),
-                        slice(&[0x00]),
-                        slice(t),
-                    )),
-                    vec![],
-                )
-                .expect("can serialize to Vec");
-
-                sign_data(yubikey, &em, subject_pki.algorithm(), key)
-            }
-            SignatureId::EcdsaWithSha256 => sign_data(
-                yubikey,
-                &Sha256::digest(&tbs_cert),
-                subject_pki.algorithm(),
-                key,
-            ),
-        }?;
+        let signature = sign(yubikey, key, subject_pki.algorithm(), &tbs_cert)?;
 
         let mut data = Buffer::new(Vec::with_capacity(CB_OBJ_MAX));
 
@@ -430,11 +519,15 @@ impl Certificate {
         )
         .expect("can serialize to Vec");
 
-        let (issuer, subject) = parse_x509_certificate(&data)
+        let (issuer, subject, not_before, not_after) = parse_x509_certificate(&data)
             .map(|(_, cert)| {
+                let validity = &cert.tbs_certificate.validity;
+
                 (
                     cert.tbs_certificate.issuer.to_string(),
                     cert.tbs_certificate.subject.to_string(),
+                    Utc.timestamp(validity.not_before.timestamp(), 0),
+                    Utc.timestamp(validity.not_after.timestamp(), 0),
                 )
             })
             .expect("We just serialized this correctly");
@@ -443,6 +536,8 @@ impl Certificate {
             serial,
             issuer,
             subject,
+            not_before,
+            not_after,
             subject_pki,
             data,
         };
@@ -452,6 +547,53 @@ impl Certificate {
         Ok(cert)
     }
 
+    /// Enroll a new key: generate it, have a CA sign a certificate for it,
+    /// and write that certificate to the slot.
+    ///
+    /// This authenticates with `mgm_key` (see
+    /// [`YubiKey::ensure_authenticated`]), generates a key in `slot`, verifies
+    /// `pin`, and builds a CSR for `subject` signed by the new key (see
+    /// [`generate_csr`]). `sign_csr` receives the DER-encoded CSR and returns
+    /// the DER-encoded certificate issued for it, which must be for the new
+    /// key ([`Error::KeyError`] otherwise).
+    ///
+    /// NOTE: the previous contents of `slot` are overwritten as soon as the
+    /// key is generated, even if a later step fails.
+    #[allow(clippy::too_many_arguments)]
+    pub fn enroll<F, E>(
+        yubikey: &mut YubiKey,
+        mgm_key: MgmKey,
+        pin: &[u8],
+        slot: SlotId,
+        algorithm: AlgorithmId,
+        pin_policy: PinPolicy,
+        touch_policy: TouchPolicy,
+        subject: &[RelativeDistinguishedName<'_>],
+        sign_csr: F,
+    ) -> Result<Self, E>
+    where
+        F: FnOnce(&[u8]) -> Result<Vec<u8>, E>,
+        E: From<Error>,
+    {
+        yubikey.ensure_authenticated(mgm_key)?;
+
+        let subject_pki = key::generate(yubikey, slot, algorithm, pin_policy, touch_policy)?;
+
+        yubikey.verify_pin(pin)?;
+        let csr = generate_csr(yubikey, slot, subject, &subject_pki)?;
+
+        let cert = Certificate::from_bytes(sign_csr(&csr)?)?;
+
+        if cert.subject_pki() != &subject_pki {
+            error!("issued certificate is not for the enrolled key");
+            return Err(Error::KeyError.into());
+        }
+
+        cert.write(yubikey, slot, CertInfo::Uncompressed)?;
+
+        Ok(cert)
+    }
+
     /// Read a certificate from the given slot in the YubiKey
     pub fn read(yubikey: &mut YubiKey, slot: SlotId) -> Result<Self, Error> {
         let txn = yubikey.begin_transaction()?;
@@ -464,20 +606,69 @@ impl Certificate {
         Certificate::from_bytes(buf)
     }
 
-    /// Write this certificate into the YubiKey in the given slot
+    /// Write this certificate into the YubiKey in the given slot.
+    ///
+    /// With [`CertInfo::Gzip`] the certificate is gzip-compressed before it
+    /// is stored, unless that wouldn't make it smaller (as is usual for very
+    /// small certificates, given the gzip header and trailer): it is then
+    /// stored uncompressed and the compression flag is cleared.
+    ///
+    /// Returns how many certificate bytes were stored and in which form.
     pub fn write(
         &self,
         yubikey: &mut YubiKey,
         slot: SlotId,
         certinfo: CertInfo,
-    ) -> Result<(), Error> {
+    ) -> Result<WriteResult, Error> {
+        yubikey.note_public_key(slot, None);
+
+        let txn = yubikey.begin_transaction()?;
+        write_compressed(&txn, slot, &self.data, certinfo)
+    }
+
+    /// Write a DER or PEM encoded certificate into the YubiKey in the given
+    /// slot.
+    ///
+    /// PEM input is detected by its `-----BEGIN CERTIFICATE-----` line and
+    /// converted to DER before it is stored. The DER is compressed according
+    /// to `certinfo` like with [`Certificate::write`].
+    ///
+    /// Input containing more than one PEM certificate is rejected unless
+    /// `chain` is set, in which case only the first (leaf) certificate is
+    /// written.
+    pub fn write_encoded(
+        yubikey: &mut YubiKey,
+        slot: SlotId,
+        cert: &[u8],
+        certinfo: CertInfo,
+        chain: bool,
+    ) -> Result<WriteResult, Error> {
+        yubikey.note_public_key(slot, None);
+
+        if !pem::is_pem(cert, pem::CERTIFICATE) {
+            let txn = yubikey.begin_transaction()?;
+            return write_compressed(&txn, slot, cert, certinfo);
+        }
+
+        let certs = pem::decode(cert, pem::CERTIFICATE)?;
+
+        if certs.len() > 1 && !chain {
+            error!(
+                "expected a single PEM certificate, found {} (set `chain` to write the first)",
+                certs.len()
+            );
+            return Err(Error::ArgumentError);
+        }
+
         let txn = yubikey.begin_transaction()?;
-        write_certificate(&txn, slot, Some(&self.data), certinfo)
+        write_compressed(&txn, slot, &certs[0], certinfo)
     }
 
     /// Delete a certificate located at the given slot of the given YubiKey
     #[cfg(feature = "untested")]
     pub fn delete(yubikey: &mut YubiKey, slot: SlotId) -> Result<(), Error> {
+        yubikey.note_public_key(slot, None);
+
         let txn = yubikey.begin_transaction()?;
         write_certificate(&txn, slot, None, CertInfo::Uncompressed)
     }
@@ -500,12 +691,17 @@ impl Certificate {
             .map_err(|_| Error::InvalidObject)?;
         let issuer = parsed_cert.tbs_certificate.issuer.to_string();
         let subject = parsed_cert.tbs_certificate.subject.to_string();
+        let validity = &parsed_cert.tbs_certificate.validity;
+        let not_before = Utc.timestamp(validity.not_before.timestamp(), 0);
+        let not_after = Utc.timestamp(validity.not_after.timestamp(), 0);
         let subject_pki = PublicKeyInfo::parse(&parsed_cert.tbs_certificate.subject_pki)?;
 
         Ok(Certificate {
             serial,
             issuer,
             subject,
+            not_before,
+            not_after,
             subject_pki,
             data: cert,
         })
@@ -518,7 +714,7 @@ impl Certificate {
 
     /// Returns the Issuer field of the certificate.
     pub fn issuer(&self) -> &str {
-        &self.subject
+        &self.issuer
     }
 
     /// Returns the SubjectName field of the certificate.
@@ -526,6 +722,16 @@ impl Certificate {
         &self.subject
     }
 
+    /// Returns the start of the certificate's validity period.
+    pub fn not_before(&self) -> DateTime<Utc> {
+        self.not_before
+    }
+
+    /// Returns the end of the certificate's validity period.
+    pub fn not_after(&self) -> DateTime<Utc> {
+        self.not_after
+    }
+
     /// Returns the SubjectPublicKeyInfo field of the certificate.
     pub fn subject_pki(&self) -> &PublicKeyInfo {
         &self.subject_pki
@@ -543,9 +749,287 @@ impl AsRef<[u8]> for Certificate {
     }
 }
 
+/// Summary of a certificate's X.509 fields, e.g. for display.
+#[cfg(feature = "x509")]
+#[derive(Clone, Debug)]
+pub struct CertificateInfo {
+    /// Subject distinguished name
+    pub subject: String,
+
+    /// Issuer distinguished name
+    pub issuer: String,
+
+    /// Start of the validity period
+    pub not_before: DateTime<Utc>,
+
+    /// End of the validity period
+    pub not_after: DateTime<Utc>,
+
+    /// Serial number
+    pub serial: Serial,
+}
+
+#[cfg(feature = "x509")]
+impl CertificateInfo {
+    /// Read and parse the certificate in the given slot.
+    ///
+    /// Returns [`Error::NotFound`] if the slot has no certificate, and
+    /// [`Error::ParseError`] if it isn't a valid X.509 certificate.
+    pub fn read(yubikey: &mut YubiKey, slot: SlotId) -> Result<Self, Error> {
+        let txn = yubikey.begin_transaction()?;
+        txn.read_certificate_info(slot)
+    }
+}
+
+#[cfg(feature = "x509")]
+impl From<&Certificate> for CertificateInfo {
+    fn from(cert: &Certificate) -> Self {
+        CertificateInfo {
+            subject: cert.subject.clone(),
+            issuer: cert.issuer.clone(),
+            not_before: cert.not_before,
+            not_after: cert.not_after,
+            serial: cert.serial.clone(),
+        }
+    }
+}
+
+/// Sign `tbs` with the key in the given slot, using the signature algorithm
+/// given by [`SignatureId::for_key`].
+fn sign(
+    yubikey: &mut YubiKey,
+    key: SlotId,
+    algorithm: AlgorithmId,
+    tbs: &[u8],
+) -> Result<Buffer, Error> {
+    match SignatureId::for_key(algorithm) {
+        SignatureId::Sha256WithRsaEncryption => {
+            use cookie_factory::{combinator::slice, sequence::tuple};
+            use x509::{
+                der::write::{der_octet_string, der_sequence},
+                write::algorithm_identifier,
+            };
+
+            let em_len = if let AlgorithmId::Rsa1024 = algorithm {
+                128
+            } else {
+                256
+            };
+
+            let h = Sha256::digest(tbs);
+
+            let t = cookie_factory::gen_simple(
+                der_sequence((
+                    algorithm_identifier(&DigestId::Sha256),
+                    der_octet_string(&h),
+                )),
+                vec![],
+            )
+            .expect("can serialize into Vec");
+
+            let em = cookie_factory::gen_simple(
+                tuple((
+                    slice(&[0x00, 0x01]),
+                    slice(&vec![0xff; em_len - t.len() - 3]),
+                    slice(&[0x00]),
+                    slice(t),
+                )),
+                vec![],
+            )
+            .expect("can serialize to Vec");
+
+            sign_data(yubikey, &em, algorithm, key)
+        }
+        SignatureId::EcdsaWithSha256 => sign_data(yubikey, &Sha256::digest(tbs), algorithm, key),
+    }
+}
+
+/// Generate a PKCS#10 certificate signing request (CSR) for the key in the
+/// given slot, signed by that key. Returns the DER-encoded CSR.
+///
+/// `subject_pki` must be the public key for the key in the slot (e.g. as
+/// returned by [`key::generate`][`crate::key::generate`]). Signing may
+/// require the PIN to have been verified, depending on the key's PIN policy.
+pub fn generate_csr(
+    yubikey: &mut YubiKey,
+    key: SlotId,
+    subject: &[RelativeDistinguishedName<'_>],
+    subject_pki: &PublicKeyInfo,
+) -> Result<Vec<u8>, Error> {
+    let signature_algorithm = SignatureId::for_key(subject_pki.algorithm());
+
+    // Serialize a throwaway TBSCertificate to obtain the DER encoding of the
+    // subject name and public key, which are shared with CSRs.
+    let extensions: &[x509::Extension<'_, &[u64]>] = &[];
+    let tbs_cert = cookie_factory::gen_simple(
+        x509::write::tbs_certificate(
+            &[0x01],
+            &signature_algorithm,
+            subject,
+            Utc::now(),
+            None,
+            subject,
+            subject_pki,
+            extensions,
+        ),
+        vec![],
+    )
+    .expect("can serialize to Vec");
+
+    let (tbs_fields, _) = der::next(&tbs_cert)?;
+
+    // version, serialNumber, signature, issuer, validity, subject, subjectPublicKeyInfo
+    let mut fields = tbs_fields.content;
+    let mut elements = Vec::with_capacity(7);
+    while elements.len() < 7 {
+        let (element, rest) = der::next(fields)?;
+        elements.push(element.encoded);
+        fields = rest;
+    }
+
+    // CertificationRequestInfo ::= SEQUENCE {
+    //   version       INTEGER { v1(0) },
+    //   subject       Name,
+    //   subjectPKInfo SubjectPublicKeyInfo,
+    //   attributes    [0] Attributes }
+    let mut cri_fields = vec![0x02, 0x01, 0x00];
+    cri_fields.extend_from_slice(elements[5]);
+    cri_fields.extend_from_slice(elements[6]);
+    cri_fields.extend_from_slice(&[0xa0, 0x00]);
+    let cri = der::encode(der::SEQUENCE, &cri_fields)?;
+
+    let signature = sign(yubikey, key, subject_pki.algorithm(), &cri)?;
+
+    // A CertificationRequest has the same structure as a Certificate
+    Ok(cookie_factory::gen_simple(
+        x509::write::certificate(&cri, &signature_algorithm, &signature),
+        vec![],
+    )
+    .expect("can serialize to Vec"))
+}
+
+/// Minimal DER encoding helpers
+mod der {
+    use crate::error::Error;
+    use log::error;
+
+    /// BIT STRING tag
+    pub(super) const BIT_STRING: u8 = 0x03;
+
+    /// SEQUENCE tag
+    pub(super) const SEQUENCE: u8 = 0x30;
+
+    /// A DER element
+    pub(super) struct Element<'a> {
+        /// Complete encoding of the element
+        pub(super) encoded: &'a [u8],
+
+        /// Contents of the element
+        pub(super) content: &'a [u8],
+    }
+
+    /// Split the first DER element (with a single-byte tag) off `data`
+    pub(super) fn next(data: &[u8]) -> Result<(Element<'_>, &[u8]), Error> {
+        if data.len() < 2 {
+            error!("truncated DER element");
+            return Err(Error::ParseError);
+        }
+
+        let (header_len, len) = match data[1] {
+            len if len < 0x80 => (2, len as usize),
+            0x81..=0x84 => {
+                let n = (data[1] & 0x7f) as usize;
+
+                if data.len() < 2 + n {
+                    error!("truncated DER length");
+                    return Err(Error::ParseError);
+                }
+
+                let len = data[2..2 + n]
+                    .iter()
+                    .fold(0usize, |len, &b| (len << 8) | b as usize);
+                (2 + n, len)
+            }
+            _ => {
+                error!("unsupported DER length encoding: {:02x}", data[1]);
+                return Err(Error::ParseError);
+            }
+        };
+
+        if data.len() < header_len + len {
+            error!("truncated DER element");
+            return Err(Error::ParseError);
+        }
+
+        let (encoded, rest) = data.split_at(header_len + len);
+
+        Ok((
+            Element {
+                encoded,
+                content: &encoded[header_len..],
+            },
+            rest,
+        ))
+    }
+
+    /// Encode a DER element with the given tag and contents.
+    ///
+    /// Returns [`Error::SizeError`] if the contents are longer than 65535
+    /// bytes.
+    pub(super) fn encode(tag: u8, content: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut out = vec![tag];
+
+        match content.len() {
+            len if len < 0x80 => out.push(len as u8),
+            len if len <= 0xff => out.extend_from_slice(&[0x81, len as u8]),
+            len if len <= 0xffff => out.extend_from_slice(&[0x82, (len >> 8) as u8, len as u8]),
+            len => {
+                error!("DER element too long: {} bytes", len);
+                return Err(Error::SizeError);
+            }
+        }
+
+        out.extend_from_slice(content);
+        Ok(out)
+    }
+}
+
+/// Read the certificate in every populated slot of the given YubiKey,
+/// exactly as stored along with its [`CertInfo`] (i.e. still compressed if it
+/// was written compressed).
+///
+/// Only certificates can be backed up this way: private keys never leave
+/// the device. Slots without a certificate are skipped, while any other
+/// error reading a slot is returned rather than leaving it out of the
+/// backup.
+pub fn export_certificates(
+    yubikey: &mut YubiKey,
+) -> Result<Vec<(SlotId, Buffer, CertInfo)>, Error> {
+    let txn = yubikey.begin_transaction()?;
+    txn.export_certificates()
+}
+
+/// Write certificates returned by [`export_certificates`] to the given
+/// YubiKey, e.g. to migrate them to a replacement device.
+///
+/// The keys the certificates were issued for must be generated or imported
+/// separately. Certificates are written as they were stored, compressed or
+/// not. Requires management key authentication.
+pub fn import_certificates(
+    yubikey: &mut YubiKey,
+    certs: &[(SlotId, Buffer, CertInfo)],
+) -> Result<(), Error> {
+    for (slot, _, _) in certs {
+        yubikey.note_public_key(*slot, None);
+    }
+
+    let txn = yubikey.begin_transaction()?;
+    txn.import_certificates(certs)
+}
+
 /// Read certificate
 pub(crate) fn read_certificate(txn: &Transaction<'_>, slot: SlotId) -> Result<Buffer, Error> {
-    let object_id = slot.object_id();
+    let object_id = slot.object_id()?;
 
     let buf = match txn.fetch_object(object_id) {
         Ok(b) => b,
@@ -566,6 +1050,82 @@ pub(crate) fn read_certificate(txn: &Transaction<'_>, slot: SlotId) -> Result<Bu
     }
 }
 
+/// Parse the contents of a slot's certificate data object (as serialized by
+/// [`serialize_certificate_object`]) into the stored certificate and how it
+/// is stored.
+pub(crate) fn parse_certificate_object(mut data: &[u8]) -> Result<(Buffer, CertInfo), Error> {
+    let mut cert = None;
+    let mut certinfo = CertInfo::Uncompressed;
+
+    while !data.is_empty() {
+        let (remaining, tlv) = Tlv::parse(data)?;
+        data = remaining;
+
+        match (tlv.tag, tlv.value) {
+            (TAG_CERT, value) => cert = Some(Zeroizing::new(value.to_vec())),
+            (TAG_CERT_COMPRESS, [info]) => certinfo = CertInfo::try_from(*info)?,
+            (TAG_CERT_LRC, _) => (),
+            (tag, _) => debug!("ignoring unknown certificate object tag {:02x}", tag),
+        }
+    }
+
+    match cert {
+        Some(cert) => Ok((cert, certinfo)),
+        None => {
+            error!("certificate object has no certificate");
+            Err(Error::InvalidObject)
+        }
+    }
+}
+
+/// Write the DER certificate `cert` to the given slot, gzip-compressing it
+/// first if `certinfo` asks for it and that makes it smaller.
+fn write_compressed(
+    txn: &Transaction<'_>,
+    slot: SlotId,
+    cert: &[u8],
+    certinfo: CertInfo,
+) -> Result<WriteResult, Error> {
+    let gzipped = match certinfo {
+        CertInfo::Gzip => Some(gzip(cert)?),
+        CertInfo::Uncompressed => None,
+    };
+
+    let (data, certinfo) = match &gzipped {
+        Some(gzipped) if gzipped.len() < cert.len() => (&gzipped[..], CertInfo::Gzip),
+        Some(gzipped) => {
+            debug!(
+                "gzip would grow the certificate from {} to {} bytes; storing uncompressed",
+                cert.len(),
+                gzipped.len()
+            );
+            (cert, CertInfo::Uncompressed)
+        }
+        None => (cert, CertInfo::Uncompressed),
+    };
+
+    write_certificate(txn, slot, Some(data), certinfo)?;
+
+    Ok(WriteResult {
+        stored_bytes: data.len(),
+        original_bytes: cert.len(),
+        certinfo,
+    })
+}
+
+/// Gzip-compress a certificate for storage
+fn gzip(cert: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut encoder = GzEncoder::new(Vec::with_capacity(cert.len()), Compression::best());
+
+    encoder
+        .write_all(cert)
+        .and_then(|()| encoder.finish())
+        .map_err(|e| {
+            error!("failed to compress certificate: {}", e);
+            Error::GenericError
+        })
+}
+
 /// Write certificate
 pub(crate) fn write_certificate(
     txn: &Transaction<'_>,
@@ -573,22 +1133,123 @@ pub(crate) fn write_certificate(
     data: Option<&[u8]>,
     certinfo: CertInfo,
 ) -> Result<(), Error> {
-    let object_id = slot.object_id();
+    let object_id = slot.object_id()?;
 
     if data.is_none() {
         return txn.save_object(object_id, &[]);
     }
 
-    let data = data.unwrap();
+    let buf = serialize_certificate_object(data.unwrap(), certinfo)?;
+    txn.save_object(object_id, &buf)
+}
 
+/// Serialize a DER (or compressed, see [`CertInfo`]) certificate into the
+/// contents of a slot's certificate data object, exactly as written to the
+/// card by [`Certificate::write`].
+///
+/// Returns [`Error::SizeError`] if the certificate doesn't fit in an object.
+pub fn serialize_certificate_object(cert: &[u8], certinfo: CertInfo) -> Result<Vec<u8>, Error> {
     let mut buf = [0u8; CB_OBJ_MAX];
-    let mut offset = Tlv::write(&mut buf, TAG_CERT, data)?;
+    let mut offset = Tlv::write(&mut buf, TAG_CERT, cert)?;
 
     // write compression info and LRC trailer
     offset += Tlv::write(&mut buf[offset..], TAG_CERT_COMPRESS, &[certinfo.into()])?;
     offset += Tlv::write(&mut buf[offset..], TAG_CERT_LRC, &[])?;
 
-    txn.save_object(object_id, &buf[..offset])
+    Ok(buf[..offset].to_vec())
+}
+
+/// Microsoft Smart Card Logon extended key usage (1.3.6.1.4.1.311.20.2.2),
+/// used instead of client authentication for Windows logon
+#[cfg(feature = "x509")]
+const OID_MS_SMART_CARD_LOGON: &str = "1.3.6.1.4.1.311.20.2.2";
+
+/// Mismatch between the key usage of a certificate and the conventional
+/// purpose of the slot it is stored in, found by [`validate_cert_key_usage`].
+#[cfg(feature = "x509")]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum KeyUsageWarning {
+    /// The Key Usage extension doesn't allow the operation the slot is used
+    /// for: signing for the authentication and signature slots, key
+    /// encipherment or key agreement for the key management slots.
+    MissingKeyUsage,
+
+    /// The certificate is for non-repudiable digital signatures, which
+    /// belongs in the [`SlotId::Signature`] slot.
+    SigningCertificate,
+
+    /// The Extended Key Usage extension allows neither client authentication
+    /// nor smart card logon, which the [`SlotId::Authentication`] slot is
+    /// used for.
+    MissingClientAuth,
+}
+
+#[cfg(feature = "x509")]
+impl fmt::Display for KeyUsageWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            KeyUsageWarning::MissingKeyUsage => "key usage doesn't match the slot's purpose",
+            KeyUsageWarning::SigningCertificate => "digital signature certificate",
+            KeyUsageWarning::MissingClientAuth => "extended key usage lacks client authentication",
+        })
+    }
+}
+
+/// Check the Key Usage and Extended Key Usage extensions of a DER
+/// certificate against the conventional purpose of `slot` (SP 800-78-4,
+/// section 3.1), e.g. before writing it there when provisioning.
+///
+/// Returns the mismatches found, if any: missing extensions don't restrict
+/// usage and aren't reported. Slots without a conventional purpose (e.g. the
+/// attestation slot) never have warnings. Returns [`Error::InvalidObject`]
+/// if the certificate can't be parsed.
+#[cfg(feature = "x509")]
+pub fn validate_cert_key_usage(slot: SlotId, cert: &[u8]) -> Result<Vec<KeyUsageWarning>, Error> {
+    let tbs = match parse_x509_certificate(cert) {
+        Ok((_, cert)) => cert.tbs_certificate,
+        _ => return Err(Error::InvalidObject),
+    };
+
+    let authentication = match slot {
+        SlotId::Authentication | SlotId::CardAuthentication => true,
+        SlotId::Signature | SlotId::KeyManagement | SlotId::Retired(_) => false,
+        _ => return Ok(vec![]),
+    };
+
+    let mut warnings = vec![];
+
+    if let Some((_, usage)) = tbs.key_usage() {
+        let allowed = match slot {
+            SlotId::Signature => usage.digital_signature() || usage.non_repudiation(),
+            SlotId::KeyManagement | SlotId::Retired(_) => {
+                usage.key_encipherment() || usage.key_agreement()
+            }
+            _ => usage.digital_signature(),
+        };
+
+        if !allowed {
+            warnings.push(KeyUsageWarning::MissingKeyUsage);
+        }
+
+        if authentication && usage.non_repudiation() {
+            warnings.push(KeyUsageWarning::SigningCertificate);
+        }
+    }
+
+    if let Some((_, usage)) = tbs.extended_key_usage() {
+        let client_auth = usage.any
+            || usage.client_auth
+            || usage
+                .other
+                .iter()
+                .any(|oid| oid.to_id_string() == OID_MS_SMART_CARD_LOGON);
+
+        if slot == SlotId::Authentication && !client_auth {
+            warnings.push(KeyUsageWarning::MissingClientAuth);
+        }
+    }
+
+    Ok(warnings)
 }
 
 mod read_pki {
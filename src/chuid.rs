@@ -32,6 +32,7 @@
 
 use crate::{error::Error, yubikey::YubiKey};
 use getrandom::getrandom;
+use log::error;
 use std::fmt::{self, Debug, Display};
 use subtle_encoding::hex;
 
@@ -50,6 +51,18 @@ pub const EXPIRATION_SIZE: usize = 8;
 /// FASC-N offset
 const CHUID_FASCN_OFFS: usize = 2;
 
+/// Number of 5-bit characters in a FASC-N, including the LRC
+const FASCN_CHARS: usize = 40;
+
+/// FASC-N start sentinel
+const FASCN_SS: u8 = 0x0b;
+
+/// FASC-N field separator
+const FASCN_FS: u8 = 0x0d;
+
+/// FASC-N end sentinel
+const FASCN_ES: u8 = 0x0f;
+
 /// GUID offset
 const CHUID_GUID_OFFS: usize = 29;
 
@@ -94,6 +107,164 @@ impl Uuid {
     }
 }
 
+/// Federal Agency Smart Credential Number (FASC-N)
+///
+/// Format defined in the "Technical Implementation Guidance: Smart Card
+/// Enabled Physical Access Control Systems" (TIG SCEPACS), section 6: 40
+/// characters (sentinels, field separators, the fields below and an LRC),
+/// each encoded as 4-bit BCD, least significant bit first, followed by an
+/// odd parity bit.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FascN {
+    /// Agency Code (4 digits)
+    pub agency_code: u16,
+
+    /// System Code (4 digits)
+    pub system_code: u16,
+
+    /// Credential Number (6 digits)
+    pub credential_number: u32,
+
+    /// Credential Series (1 digit)
+    pub credential_series: u8,
+
+    /// Individual Credential Issue (1 digit)
+    pub individual_credential_issue: u8,
+
+    /// Person Identifier (10 digits)
+    pub person_identifier: u64,
+
+    /// Organizational Category (1 digit)
+    pub organizational_category: u8,
+
+    /// Organizational Identifier (4 digits)
+    pub organizational_identifier: u16,
+
+    /// Person/Organization Association Category (1 digit)
+    pub association_category: u8,
+}
+
+impl FascN {
+    /// Decode a FASC-N, checking the parity bits, sentinels, field
+    /// separators and LRC.
+    pub fn from_bytes(bytes: &[u8; FASCN_SIZE]) -> Result<Self, Error> {
+        let mut chars = [0u8; FASCN_CHARS];
+
+        for (i, c) in chars.iter_mut().enumerate() {
+            let mut ones = 0;
+
+            for j in 0..5 {
+                let bit = (bytes[(i * 5 + j) / 8] >> (7 - (i * 5 + j) % 8)) & 1;
+                ones += bit;
+
+                // the fifth bit is the parity bit
+                if j < 4 {
+                    *c |= bit << j;
+                }
+            }
+
+            if ones % 2 != 1 {
+                error!("FASC-N character {} has a bad parity bit", i);
+                return Err(Error::ParseError);
+            }
+        }
+
+        let lrc = chars[..FASCN_CHARS - 1].iter().fold(0, |lrc, c| lrc ^ c);
+
+        if lrc != chars[FASCN_CHARS - 1] {
+            error!("FASC-N LRC mismatch");
+            return Err(Error::ParseError);
+        }
+
+        if chars[0] != FASCN_SS
+            || [5, 10, 17, 19, 21].iter().any(|&i| chars[i] != FASCN_FS)
+            || chars[38] != FASCN_ES
+        {
+            error!("malformed FASC-N: missing sentinel or field separator");
+            return Err(Error::ParseError);
+        }
+
+        Ok(FascN {
+            agency_code: fascn_digits(&chars[1..5])? as u16,
+            system_code: fascn_digits(&chars[6..10])? as u16,
+            credential_number: fascn_digits(&chars[11..17])? as u32,
+            credential_series: fascn_digits(&chars[18..19])? as u8,
+            individual_credential_issue: fascn_digits(&chars[20..21])? as u8,
+            person_identifier: fascn_digits(&chars[22..32])?,
+            organizational_category: fascn_digits(&chars[32..33])? as u8,
+            organizational_identifier: fascn_digits(&chars[33..37])? as u16,
+            association_category: fascn_digits(&chars[37..38])? as u8,
+        })
+    }
+
+    /// Encode this FASC-N.
+    ///
+    /// Returns [`Error::RangeError`] if a field has more digits than the
+    /// FASC-N allows for it.
+    pub fn to_bytes(&self) -> Result<[u8; FASCN_SIZE], Error> {
+        let mut chars = Vec::with_capacity(FASCN_CHARS);
+
+        chars.push(FASCN_SS);
+        push_fascn_digits(&mut chars, self.agency_code.into(), 4)?;
+        chars.push(FASCN_FS);
+        push_fascn_digits(&mut chars, self.system_code.into(), 4)?;
+        chars.push(FASCN_FS);
+        push_fascn_digits(&mut chars, self.credential_number.into(), 6)?;
+        chars.push(FASCN_FS);
+        push_fascn_digits(&mut chars, self.credential_series.into(), 1)?;
+        chars.push(FASCN_FS);
+        push_fascn_digits(&mut chars, self.individual_credential_issue.into(), 1)?;
+        chars.push(FASCN_FS);
+        push_fascn_digits(&mut chars, self.person_identifier, 10)?;
+        push_fascn_digits(&mut chars, self.organizational_category.into(), 1)?;
+        push_fascn_digits(&mut chars, self.organizational_identifier.into(), 4)?;
+        push_fascn_digits(&mut chars, self.association_category.into(), 1)?;
+        chars.push(FASCN_ES);
+
+        let lrc = chars.iter().fold(0, |lrc, c| lrc ^ c);
+        chars.push(lrc);
+
+        let mut bytes = [0u8; FASCN_SIZE];
+
+        for (i, c) in chars.iter().enumerate() {
+            let parity = (c.count_ones() + 1) % 2;
+            let bits = (0..4).map(|j| (c >> j) & 1).chain(Some(parity as u8));
+
+            for (j, bit) in bits.enumerate() {
+                bytes[(i * 5 + j) / 8] |= bit << (7 - (i * 5 + j) % 8);
+            }
+        }
+
+        Ok(bytes)
+    }
+}
+
+/// Parse a field of FASC-N BCD digits
+fn fascn_digits(chars: &[u8]) -> Result<u64, Error> {
+    chars.iter().try_fold(0, |value, &c| {
+        if c > 9 {
+            error!("malformed FASC-N: expected a digit, found {:#x}", c);
+            return Err(Error::ParseError);
+        }
+
+        Ok(value * 10 + u64::from(c))
+    })
+}
+
+/// Append `value` as a FASC-N field of `len` BCD digits
+fn push_fascn_digits(chars: &mut Vec<u8>, value: u64, len: u32) -> Result<(), Error> {
+    if value >= 10u64.pow(len) {
+        error!("FASC-N field value {} exceeds {} digits", value, len);
+        return Err(Error::RangeError);
+    }
+
+    for i in (0..len).rev() {
+        chars.push((value / 10u64.pow(i) % 10) as u8);
+    }
+
+    Ok(())
+}
+
 /// Cardholder Unique Identifier (CHUID)
 #[derive(Copy, Clone)]
 pub struct CHUID(pub [u8; CHUID_SIZE]);
@@ -106,6 +277,18 @@ impl CHUID {
         Ok(fascn)
     }
 
+    /// Decode the FASC-N component of CHUID into its fields
+    pub fn fascn_fields(&self) -> Result<FascN, Error> {
+        FascN::from_bytes(&self.fascn()?)
+    }
+
+    /// Replace the FASC-N component of CHUID
+    pub fn set_fascn(&mut self, fascn: &FascN) -> Result<(), Error> {
+        let bytes = fascn.to_bytes()?;
+        self.0[CHUID_FASCN_OFFS..(CHUID_FASCN_OFFS + FASCN_SIZE)].copy_from_slice(&bytes);
+        Ok(())
+    }
+
     /// Return Card UUID/GUID component of CHUID
     pub fn uuid(&self) -> Result<[u8; CARDID_SIZE], Error> {
         let mut uuid = [0u8; CARDID_SIZE];
@@ -142,14 +325,21 @@ impl CHUID {
     #[cfg(feature = "untested")]
 
     pub fn set(&self, yubikey: &mut YubiKey) -> Result<(), Error> {
-        let mut buf = CHUID_TMPL.to_vec();
-        buf[0..self.0.len()].copy_from_slice(&self.0);
+        let buf = serialize_chuid(self);
 
         let txn = yubikey.begin_transaction()?;
         txn.save_object(OBJ_CHUID, &buf)
     }
 }
 
+/// Serialize a CHUID into the contents of the CHUID data object, exactly as
+/// written to the card by `CHUID::set`.
+pub fn serialize_chuid(chuid: &CHUID) -> Vec<u8> {
+    let mut buf = CHUID_TMPL.to_vec();
+    buf[0..chuid.0.len()].copy_from_slice(&chuid.0);
+    buf
+}
+
 impl Display for CHUID {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
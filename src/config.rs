@@ -44,6 +44,7 @@ use std::{
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+const CB_ADMIN_SALT: usize = 16;
 const CB_ADMIN_TIMESTAMP: usize = 0x04;
 const PROTECTED_FLAGS_1_PUK_NOBLOCK: u8 = 0x01;
 
@@ -143,3 +144,116 @@ impl Config {
         Ok(config)
     }
 }
+
+/// Yubico administrative metadata (stored in object `0x5fff00`).
+///
+/// This is the same data written by `ykman` and `yubico-piv-tool`, and records
+/// e.g. whether the PUK was intentionally blocked after protecting the
+/// management key. Writing it requires authenticating with the management key.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct AdminMetadata {
+    /// Admin flags byte (see [`AdminMetadata::puk_blocked`] and
+    /// [`AdminMetadata::mgm_key_protected`])
+    pub flags_1: u8,
+
+    /// Salt used to derive the management key from the PIN (deprecated)
+    pub salt: Option<[u8; CB_ADMIN_SALT]>,
+
+    /// Time the PIN was last changed
+    pub pin_last_changed: Option<SystemTime>,
+}
+
+impl AdminMetadata {
+    /// Read administrative metadata from the YubiKey.
+    ///
+    /// Returns the default (empty) metadata if the object is not present.
+    pub fn read(yubikey: &mut YubiKey) -> Result<Self, Error> {
+        let txn = yubikey.begin_transaction()?;
+
+        let admin_data = match AdminData::read(&txn) {
+            Ok(admin_data) => admin_data,
+            Err(Error::NotFound) => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+
+        let mut metadata = Self::default();
+
+        if let Ok(item) = admin_data.get_item(TAG_ADMIN_FLAGS_1) {
+            match item.first() {
+                Some(flags_1) => metadata.flags_1 = *flags_1,
+                None => error!("empty response for admin flags metadata item! ignoring"),
+            }
+        }
+
+        if let Ok(item) = admin_data.get_item(TAG_ADMIN_SALT) {
+            match item.try_into() {
+                Ok(salt) => metadata.salt = Some(salt),
+                Err(_) => error!(
+                    "derived MGM salt exists, but is incorrect size: {} (expected {})",
+                    item.len(),
+                    CB_ADMIN_SALT
+                ),
+            }
+        }
+
+        if let Ok(item) = admin_data.get_item(TAG_ADMIN_TIMESTAMP) {
+            match item.try_into() {
+                Ok(timestamp) => {
+                    let timestamp = u32::from_le_bytes(timestamp);
+
+                    if timestamp != 0 {
+                        metadata.pin_last_changed =
+                            Some(UNIX_EPOCH + Duration::from_secs(timestamp as u64));
+                    }
+                }
+                Err(_) => error!("pin timestamp in admin metadata is an invalid size"),
+            }
+        }
+
+        Ok(metadata)
+    }
+
+    /// Write administrative metadata to the YubiKey.
+    ///
+    /// Items not represented by this type are preserved.
+    pub fn write(&self, yubikey: &mut YubiKey) -> Result<(), Error> {
+        let txn = yubikey.begin_transaction()?;
+
+        let mut admin_data = match AdminData::read(&txn) {
+            Ok(admin_data) => admin_data,
+            Err(Error::NotFound) => AdminData::default(),
+            Err(e) => return Err(e),
+        };
+
+        admin_data.set_item(TAG_ADMIN_FLAGS_1, &[self.flags_1])?;
+        admin_data.set_item(
+            TAG_ADMIN_SALT,
+            self.salt.as_ref().map(|salt| &salt[..]).unwrap_or(&[]),
+        )?;
+
+        match self.pin_last_changed {
+            Some(time) => {
+                let secs = time
+                    .duration_since(UNIX_EPOCH)
+                    .map_err(|_| Error::RangeError)?
+                    .as_secs();
+
+                let secs: u32 = secs.try_into().map_err(|_| Error::RangeError)?;
+                admin_data.set_item(TAG_ADMIN_TIMESTAMP, &secs.to_le_bytes())?;
+            }
+            None => admin_data.set_item(TAG_ADMIN_TIMESTAMP, &[])?,
+        }
+
+        admin_data.write(&txn)
+    }
+
+    /// Was the PUK intentionally blocked (e.g. after protecting the management key)?
+    pub fn puk_blocked(&self) -> bool {
+        self.flags_1 & ADMIN_FLAGS_1_PUK_BLOCKED != 0
+    }
+
+    /// Is the management key stored in PIN-protected data?
+    pub fn mgm_key_protected(&self) -> bool {
+        self.flags_1 & ADMIN_FLAGS_1_PROTECTED_MGM != 0
+    }
+}
@@ -0,0 +1,149 @@
+//! PIV Discovery Object
+//!
+//! See NIST SP 800-73-4 Part 1, section 3.3.2.
+
+use crate::{error::Error, serialization::*, yubikey::YubiKey};
+use log::error;
+
+/// Tag of the PIV Card Application AID within the Discovery Object
+const TAG_AID: u8 = 0x4f;
+
+/// Tag of the PIN Usage Policy within the Discovery Object
+const TAG_PIN_USAGE_POLICY: [u8; 2] = [0x5f, 0x2f];
+
+/// PIV Card Application PIN satisfies the PIV access control rules
+const POLICY_APPLICATION_PIN: u8 = 0x40;
+
+/// Global PIN satisfies the PIV access control rules
+const POLICY_GLOBAL_PIN: u8 = 0x20;
+
+/// PIV Card Application PIN is the primary PIN
+const PRIMARY_APPLICATION_PIN: u8 = 0x10;
+
+/// Global PIN is the primary PIN
+const PRIMARY_GLOBAL_PIN: u8 = 0x20;
+
+/// PIN which can be used to satisfy the PIV access control rules
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PrimaryPin {
+    /// PIV Card Application PIN
+    Application,
+
+    /// Global PIN
+    Global,
+}
+
+/// PIN Usage Policy: which PIN(s) satisfy the PIV access control rules
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PinUsage {
+    /// Only the PIV Card Application PIN
+    ApplicationOnly,
+
+    /// Only the Global PIN
+    GlobalOnly,
+
+    /// Both PINs, with the given one to be used by default
+    Both {
+        /// PIN which should be prompted for
+        primary: PrimaryPin,
+    },
+}
+
+impl PinUsage {
+    /// Interpret the two bytes of a PIN Usage Policy
+    pub fn from_policy(policy: [u8; 2]) -> Result<Self, Error> {
+        let application = policy[0] & POLICY_APPLICATION_PIN != 0;
+        let global = policy[0] & POLICY_GLOBAL_PIN != 0;
+
+        match (application, global) {
+            (true, false) => Ok(PinUsage::ApplicationOnly),
+            (false, true) => Ok(PinUsage::GlobalOnly),
+            (true, true) => match policy[1] {
+                PRIMARY_APPLICATION_PIN => Ok(PinUsage::Both {
+                    primary: PrimaryPin::Application,
+                }),
+                PRIMARY_GLOBAL_PIN => Ok(PinUsage::Both {
+                    primary: PrimaryPin::Global,
+                }),
+                other => {
+                    error!("invalid primary PIN in PIN usage policy: {:02x}", other);
+                    Err(Error::ParseError)
+                }
+            },
+            (false, false) => {
+                error!("PIN usage policy allows no PIN: {:02x?}", policy);
+                Err(Error::ParseError)
+            }
+        }
+    }
+}
+
+/// PIV Discovery Object.
+///
+/// This object is read-only.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Discovery {
+    aid: Vec<u8>,
+    pin_usage_policy: Option<[u8; 2]>,
+}
+
+impl Discovery {
+    /// Read the Discovery Object from the YubiKey
+    pub fn get(yubikey: &mut YubiKey) -> Result<Self, Error> {
+        let txn = yubikey.begin_transaction()?;
+        let response = txn.fetch_object(OBJ_DISCOVERY)?;
+        Self::parse(&response)
+    }
+
+    /// Parse the contents of the Discovery Object (i.e. the value of its
+    /// `0x7e` template)
+    pub fn parse(mut data: &[u8]) -> Result<Self, Error> {
+        let mut aid = None;
+        let mut pin_usage_policy = None;
+
+        while !data.is_empty() {
+            if data.starts_with(&TAG_PIN_USAGE_POLICY) {
+                // Two-byte tag: skip its first byte and parse the rest as a TLV
+                let (remaining, tlv) = Tlv::parse(&data[1..])?;
+                data = remaining;
+
+                if tlv.value.len() != 2 {
+                    error!("invalid PIN usage policy length: {}", tlv.value.len());
+                    return Err(Error::ParseError);
+                }
+
+                pin_usage_policy = Some([tlv.value[0], tlv.value[1]]);
+            } else {
+                let (remaining, tlv) = Tlv::parse(data)?;
+                data = remaining;
+
+                if tlv.tag == TAG_AID {
+                    aid = Some(tlv.value.to_vec());
+                }
+            }
+        }
+
+        Ok(Self {
+            aid: aid.ok_or_else(|| {
+                error!("Discovery Object is missing the PIV application AID");
+                Error::ParseError
+            })?,
+            pin_usage_policy,
+        })
+    }
+
+    /// PIV Card Application AID
+    pub fn aid(&self) -> &[u8] {
+        &self.aid
+    }
+
+    /// Raw PIN Usage Policy bytes, if present
+    pub fn pin_usage_policy(&self) -> Option<[u8; 2]> {
+        self.pin_usage_policy
+    }
+
+    /// Interpreted PIN Usage Policy, if present
+    pub fn pin_usage(&self) -> Option<Result<PinUsage, Error>> {
+        self.pin_usage_policy.map(PinUsage::from_policy)
+    }
+}
@@ -30,6 +30,7 @@
 // (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
 // OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+use crate::yubikey::Version;
 use std::fmt::{self, Display};
 
 /// Kinds of errors
@@ -71,6 +72,12 @@ pub enum Error {
         tries: u8,
     },
 
+    /// Wrong PUK
+    WrongPuk {
+        /// Number of tries remaining
+        tries: u8,
+    },
+
     /// Invalid object
     InvalidObject,
 
@@ -91,6 +98,49 @@ pub enum Error {
 
     /// Not found
     NotFound,
+
+    /// Attempt refused because a wrong PIN would block it
+    WouldBlockPin,
+
+    /// The key's PIN policy requires the PIN to be verified first
+    PinRequired,
+
+    /// Resetting the PIV application is disabled by the device configuration
+    ResetBlocked,
+
+    /// Timed out waiting for a device
+    Timeout,
+
+    /// The private key is encrypted and must be decrypted first
+    EncryptedKey,
+
+    /// The PIN doesn't meet the device's PIN complexity requirements
+    PinComplexity,
+
+    /// The device's firmware is older than required
+    FirmwareTooOld {
+        /// Firmware version of the device
+        found: Version,
+
+        /// Minimum firmware version required
+        required: Version,
+    },
+
+    /// The card doesn't support secure messaging (e.g. SCP03)
+    SecureMessagingUnsupported,
+
+    /// The card is in use by another application (`SCARD_E_SHARING_VIOLATION`)
+    Busy,
+
+    /// An intermediate chunk of a chained write was rejected after earlier
+    /// chunks were accepted, which may leave the object (or key) partially
+    /// written on some cards.
+    ///
+    /// Writing the full data again is safe: PUT DATA replaces the object.
+    PartialWrite {
+        /// Number of bytes the card accepted before the write failed
+        bytes_written: usize,
+    },
 }
 
 impl Error {
@@ -110,6 +160,7 @@ impl Error {
             Error::KeyError => "YKPIV_KEY_ERROR",
             Error::ParseError => "YKPIV_PARSE_ERROR",
             Error::WrongPin { .. } => "YKPIV_WRONG_PIN",
+            Error::WrongPuk { .. } => "YKPIV_WRONG_PIN",
             Error::InvalidObject => "YKPIV_INVALID_OBJECT",
             Error::AlgorithmError => "YKPIV_ALGORITHM_ERROR",
             Error::PinLocked => "YKPIV_PIN_LOCKED",
@@ -117,6 +168,16 @@ impl Error {
             Error::RangeError => "YKPIV_RANGE_ERROR",
             Error::NotSupported => "YKPIV_NOT_SUPPORTED",
             Error::NotFound => "<not found>",
+            Error::WouldBlockPin => "<would block pin>",
+            Error::PinRequired => "<pin required>",
+            Error::ResetBlocked => "<reset blocked>",
+            Error::Timeout => "<timeout>",
+            Error::EncryptedKey => "<encrypted key>",
+            Error::PinComplexity => "<pin complexity>",
+            Error::FirmwareTooOld { .. } => "<firmware too old>",
+            Error::SecureMessagingUnsupported => "<secure messaging unsupported>",
+            Error::Busy => "<busy>",
+            Error::PartialWrite { .. } => "<partial write>",
         }
     }
 
@@ -133,6 +194,7 @@ impl Error {
             Error::KeyError => "key error",
             Error::ParseError => "parse error",
             Error::WrongPin { .. } => "wrong pin",
+            Error::WrongPuk { .. } => "wrong puk",
             Error::InvalidObject => "invalid object",
             Error::AlgorithmError => "algorithm error",
             Error::PinLocked => "PIN locked",
@@ -140,6 +202,16 @@ impl Error {
             Error::RangeError => "range error",
             Error::NotSupported => "not supported",
             Error::NotFound => "not found",
+            Error::WouldBlockPin => "only one PIN attempt remains",
+            Error::PinRequired => "PIN verification required",
+            Error::ResetBlocked => "PIV reset is disabled by the device configuration",
+            Error::Timeout => "timed out",
+            Error::EncryptedKey => "private key is encrypted, decrypt it before importing",
+            Error::PinComplexity => "PIN does not meet the complexity requirements",
+            Error::FirmwareTooOld { .. } => "firmware version is too old",
+            Error::SecureMessagingUnsupported => "secure messaging not supported by the card",
+            Error::Busy => "card is in use by another application (e.g. a browser or middleware)",
+            Error::PartialWrite { .. } => "write failed part way through; write the data again",
         }
     }
 }
@@ -152,7 +224,10 @@ impl Display for Error {
 
 impl From<pcsc::Error> for Error {
     fn from(err: pcsc::Error) -> Error {
-        Error::PcscError { inner: Some(err) }
+        match err {
+            pcsc::Error::SharingViolation => Error::Busy,
+            _ => Error::PcscError { inner: Some(err) },
+        }
     }
 }
 
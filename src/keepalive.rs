@@ -0,0 +1,191 @@
+//! Background keep-alive for PC/SC connections.
+//!
+//! Some readers and smart card middleware drop idle cards after a while. The
+//! keep-alive thread opens its own shared connection to the reader and
+//! periodically sends GET VERSION, which doesn't change any card state. If
+//! the card is reset, the thread reconnects its connection and carries on. It
+//! never sees the PIN, management key or any other secret held by the
+//! [`YubiKey`][`crate::YubiKey`] handle.
+
+use crate::{
+    apdu::{ApduTemplate, Ins},
+    error::Error,
+};
+use log::{debug, error, warn};
+use std::{
+    ffi::CString,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+/// Handle to a keep-alive thread. Dropping it stops the thread.
+pub(crate) struct Keepalive {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Keepalive {
+    /// Start pinging the card in the named reader every `interval`.
+    pub(crate) fn start(reader: &str, interval: Duration) -> Result<Self, Error> {
+        let ctx = pcsc::Context::establish(pcsc::Scope::System)?;
+        let name = CString::new(reader).map_err(|_| Error::ArgumentError)?;
+        let card = ctx.connect(&name, pcsc::ShareMode::Shared, pcsc::Protocols::T1)?;
+        let (stop, stopped) = mpsc::channel();
+
+        let thread = thread::Builder::new()
+            .name(String::from("yubikey-keepalive"))
+            .spawn(move || run(card, interval, &stopped))
+            .map_err(|e| {
+                error!("couldn't spawn keep-alive thread: {}", e);
+                Error::GenericError
+            })?;
+
+        Ok(Keepalive {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for Keepalive {
+    fn drop(&mut self) {
+        // Disconnecting the channel wakes the thread up immediately
+        drop(self.stop.take());
+
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                warn!("keep-alive thread panicked");
+            }
+        }
+    }
+}
+
+/// Connection the keep-alive thread pings
+trait Ping {
+    /// Send GET VERSION, waiting for any transaction in progress
+    fn ping(&mut self, apdu: &[u8], response: &mut [u8]) -> Result<(), pcsc::Error>;
+
+    /// Reconnect after the card was reset, leaving its state alone
+    fn reconnect(&mut self) -> Result<(), pcsc::Error>;
+}
+
+impl Ping for pcsc::Card {
+    fn ping(&mut self, apdu: &[u8], response: &mut [u8]) -> Result<(), pcsc::Error> {
+        self.transaction()
+            .and_then(|tx| tx.transmit(apdu, response).map(|_| ()))
+    }
+
+    fn reconnect(&mut self) -> Result<(), pcsc::Error> {
+        pcsc::Card::reconnect(
+            self,
+            pcsc::ShareMode::Shared,
+            pcsc::Protocols::T1,
+            pcsc::Disposition::LeaveCard,
+        )
+    }
+}
+
+/// Keep-alive thread body
+fn run(mut card: impl Ping, interval: Duration, stopped: &mpsc::Receiver<()>) {
+    let get_version = ApduTemplate::new(Ins::GetVersion, 0, 0).to_bytes();
+    let mut response = [0u8; 8];
+
+    while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+        if !keep_alive(&mut card, &get_version, &mut response) {
+            return;
+        }
+    }
+}
+
+/// Ping the card once. Returns `false` if the keep-alive should stop.
+fn keep_alive(card: &mut impl Ping, apdu: &[u8], response: &mut [u8]) -> bool {
+    match card.ping(apdu, response) {
+        Ok(()) => debug!("keep-alive sent"),
+        Err(pcsc::Error::RemovedCard) | Err(pcsc::Error::NoSmartcard) => {
+            warn!("card removed, stopping keep-alive");
+            return false;
+        }
+        Err(pcsc::Error::ResetCard) => {
+            // Our handle stays unusable until it is reconnected, so try again
+            // with a fresh one
+            debug!("card was reset; reconnecting keep-alive");
+
+            match card.reconnect().and_then(|()| card.ping(apdu, response)) {
+                Ok(()) => debug!("keep-alive sent"),
+                Err(pcsc::Error::RemovedCard) | Err(pcsc::Error::NoSmartcard) => {
+                    warn!("card removed, stopping keep-alive");
+                    return false;
+                }
+                Err(e) => debug!("keep-alive failed after reconnecting: {}", e),
+            }
+        }
+        Err(e) => debug!("keep-alive failed: {}", e),
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{keep_alive, Ping};
+    use std::collections::VecDeque;
+
+    /// Card which returns the scripted results for each ping
+    #[derive(Default)]
+    struct ScriptedCard {
+        pings: VecDeque<Result<(), pcsc::Error>>,
+        reconnects: usize,
+    }
+
+    impl Ping for ScriptedCard {
+        fn ping(&mut self, _apdu: &[u8], _response: &mut [u8]) -> Result<(), pcsc::Error> {
+            self.pings.pop_front().expect("unexpected ping")
+        }
+
+        fn reconnect(&mut self) -> Result<(), pcsc::Error> {
+            self.reconnects += 1;
+            Ok(())
+        }
+    }
+
+    fn ping(card: &mut ScriptedCard) -> bool {
+        keep_alive(card, &[0x00, 0xfd, 0x00, 0x00, 0x00], &mut [0u8; 8])
+    }
+
+    #[test]
+    fn reconnects_after_reset() {
+        let mut card = ScriptedCard::default();
+        card.pings
+            .extend(vec![Ok(()), Err(pcsc::Error::ResetCard), Ok(()), Ok(())]);
+
+        assert!(ping(&mut card));
+        assert_eq!(card.reconnects, 0);
+
+        // The failed ping is retried on the new connection
+        assert!(ping(&mut card));
+        assert_eq!(card.reconnects, 1);
+        assert_eq!(card.pings.len(), 1);
+
+        assert!(ping(&mut card));
+        assert_eq!(card.reconnects, 1);
+        assert!(card.pings.is_empty());
+    }
+
+    #[test]
+    fn stops_when_removed() {
+        let mut card = ScriptedCard::default();
+        card.pings.extend(vec![
+            Err(pcsc::Error::Timeout),
+            Err(pcsc::Error::ResetCard),
+            Err(pcsc::Error::RemovedCard),
+        ]);
+
+        // Other errors are only logged
+        assert!(ping(&mut card));
+
+        // Removed while reconnecting
+        assert!(!ping(&mut card));
+        assert_eq!(card.reconnects, 1);
+    }
+}
@@ -38,16 +38,16 @@
 // OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use crate::{
-    apdu::{Ins, StatusWords},
+    apdu::{ApduTemplate, Ins, StatusWords},
     certificate::{self, Certificate},
     error::Error,
     serialization::*,
     settings,
-    yubikey::YubiKey,
+    yubikey::{Serial, Version, YubiKey},
     ObjectId,
 };
 use log::debug;
-use std::convert::TryFrom;
+use std::{convert::TryFrom, time::Instant};
 
 use crate::CB_OBJ_MAX;
 use crate::{
@@ -55,11 +55,18 @@ use crate::{
     policy::{PinPolicy, TouchPolicy},
     Buffer,
 };
+#[cfg(feature = "pkcs8")]
+use crate::{pem, pkcs8};
 use elliptic_curve::sec1::EncodedPoint as EcPublicKey;
+use getrandom::getrandom;
 use log::{error, warn};
 use num_integer::Integer;
 use num_traits::{FromPrimitive, One};
-use rsa::{BigUint, RSAPublicKey};
+use rsa::{BigUint, PublicKeyParts, RSAPublicKey};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use subtle::{Choice, ConditionallySelectable, ConstantTimeEq, ConstantTimeLess};
+use x509_parser::{certificate::TbsCertificate, parse_x509_certificate};
 use zeroize::Zeroizing;
 
 const CB_ECC_POINTP256: usize = 65;
@@ -69,6 +76,13 @@ const TAG_RSA_MODULUS: u8 = 0x81;
 const TAG_RSA_EXP: u8 = 0x82;
 const TAG_ECC_POINT: u8 = 0x86;
 
+const TAG_METADATA_ALGO: u8 = 0x01;
+const TAG_METADATA_POLICY: u8 = 0x02;
+const TAG_METADATA_ORIGIN: u8 = 0x03;
+const TAG_METADATA_PUBLIC_KEY: u8 = 0x04;
+const TAG_METADATA_DEFAULT: u8 = 0x05;
+const TAG_METADATA_RETRIES: u8 = 0x06;
+
 const KEYDATA_LEN: usize = 1024;
 
 const KEYDATA_RSA_EXP: u64 = 65537;
@@ -113,6 +127,11 @@ pub enum SlotId {
     /// attestation of other keys generated on device with instruction `f9`. This slot is
     /// not cleared on reset, but can be overwritten.
     Attestation,
+
+    /// The card management key (`9b`) used to authenticate administrative operations.
+    /// This is a symmetric key: it cannot be used for signing or decryption, and has no
+    /// associated certificate.
+    CardManagement,
 }
 
 impl TryFrom<u8> for SlotId {
@@ -125,6 +144,7 @@ impl TryFrom<u8> for SlotId {
             0x9d => Ok(SlotId::KeyManagement),
             0x9e => Ok(SlotId::CardAuthentication),
             0xf9 => Ok(SlotId::Attestation),
+            0x9b => Ok(SlotId::CardManagement),
             _ => RetiredSlotId::try_from(value).map(SlotId::Retired),
         }
     }
@@ -139,6 +159,7 @@ impl From<SlotId> for u8 {
             SlotId::CardAuthentication => 0x9e,
             SlotId::Retired(retired) => retired.into(),
             SlotId::Attestation => 0xf9,
+            SlotId::CardManagement => 0x9b,
         }
     }
 }
@@ -153,21 +174,68 @@ impl TryFrom<String> for SlotId {
             "9d" => Ok(SlotId::KeyManagement),
             "9e" => Ok(SlotId::CardAuthentication),
             "f9" => Ok(SlotId::Attestation),
+            "9b" => Ok(SlotId::CardManagement),
             _ => RetiredSlotId::try_from(s).map(SlotId::Retired),
         }
     }
 }
 
 impl SlotId {
-    /// Returns the [`ObjectId`] that corresponds to a given [`SlotId`].
-    pub(crate) fn object_id(self) -> ObjectId {
+    /// Returns the certificate [`ObjectId`] that corresponds to a given [`SlotId`].
+    ///
+    /// Errors for [`SlotId::CardManagement`], which has no certificate.
+    pub(crate) fn object_id(self) -> Result<ObjectId, Error> {
         match self {
-            SlotId::Authentication => 0x005f_c105,
-            SlotId::Signature => 0x005f_c10a,
-            SlotId::KeyManagement => 0x005f_c10b,
-            SlotId::CardAuthentication => 0x005f_c101,
-            SlotId::Retired(retired) => retired.object_id(),
-            SlotId::Attestation => 0x005f_ff01,
+            SlotId::Authentication => Ok(0x005f_c105),
+            SlotId::Signature => Ok(0x005f_c10a),
+            SlotId::KeyManagement => Ok(0x005f_c10b),
+            SlotId::CardAuthentication => Ok(0x005f_c101),
+            SlotId::Retired(retired) => Ok(retired.object_id()),
+            SlotId::Attestation => Ok(0x005f_ff01),
+            SlotId::CardManagement => Err(Error::InvalidObject),
+        }
+    }
+}
+
+/// What a key is going to be used for, to pick a slot and algorithm without
+/// knowing the PIV slot model.
+/// <https://developers.yubico.com/PIV/Introduction/Certificate_slots.html>
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SlotPurpose {
+    /// Authenticating the cardholder, e.g. SSH, TLS client certificates or
+    /// system login
+    Authentication,
+
+    /// Signing documents, e-mail, files or executables
+    DigitalSignature,
+
+    /// Encryption for confidentiality, e.g. e-mail or file encryption
+    KeyManagement,
+
+    /// Authenticating the card itself without the PIN, e.g. physical access
+    CardAuthentication,
+}
+
+impl SlotPurpose {
+    /// The slot conventionally used for this purpose.
+    pub fn default_slot(self) -> SlotId {
+        match self {
+            SlotPurpose::Authentication => SlotId::Authentication,
+            SlotPurpose::DigitalSignature => SlotId::Signature,
+            SlotPurpose::KeyManagement => SlotId::KeyManagement,
+            SlotPurpose::CardAuthentication => SlotId::CardAuthentication,
+        }
+    }
+
+    /// A sensible algorithm for this purpose.
+    ///
+    /// This is P-256, which all supported YubiKeys implement, except for key
+    /// management where RSA 2048 is the most widely supported by e-mail and
+    /// file encryption software.
+    pub fn recommended_algorithm(self) -> AlgorithmId {
+        match self {
+            SlotPurpose::KeyManagement => AlgorithmId::Rsa2048,
+            _ => AlgorithmId::EccP256,
         }
     }
 }
@@ -341,7 +409,19 @@ pub const SLOTS: [SlotId; 24] = [
     SlotId::CardAuthentication,
 ];
 
+/// Personal Identity Verification (PIV) key algorithms supported by this crate
+pub const ALGORITHMS: [AlgorithmId; 4] = [
+    AlgorithmId::Rsa1024,
+    AlgorithmId::Rsa2048,
+    AlgorithmId::EccP256,
+    AlgorithmId::EccP384,
+];
+
 /// Algorithm identifiers
+///
+/// NOTE: P-521 is not supported. SP 800-78-4 assigns no PIV algorithm
+/// identifier for it, and no YubiKey firmware implements it, so there is
+/// nothing to gate it on.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum AlgorithmId {
     /// 1024-bit RSA.
@@ -385,7 +465,25 @@ impl AlgorithmId {
         Tlv::write(buf, 0x80, &[self.into()])
     }
 
-    fn get_elem_len(self) -> usize {
+    /// Algorithms supported by the PIV application of a YubiKey with the
+    /// given firmware version.
+    ///
+    /// YubiKey firmware doesn't report its supported algorithms, so this is
+    /// derived from a table of firmware versions.
+    pub fn supported_by(version: Version) -> Vec<AlgorithmId> {
+        if version.at_least(4, 0, 0) {
+            ALGORITHMS.to_vec()
+        } else {
+            // YubiKey NEO lacks P-384
+            vec![
+                AlgorithmId::Rsa1024,
+                AlgorithmId::Rsa2048,
+                AlgorithmId::EccP256,
+            ]
+        }
+    }
+
+    pub(crate) fn get_elem_len(self) -> usize {
         match self {
             AlgorithmId::Rsa1024 => 64,
             AlgorithmId::Rsa2048 => 128,
@@ -471,10 +569,7 @@ pub fn generate(
 
     match algorithm {
         AlgorithmId::Rsa1024 | AlgorithmId::Rsa2048 => {
-            if yubikey.version.major == 4
-                && (yubikey.version.minor < 3
-                    || yubikey.version.minor == 3 && (yubikey.version.patch < 5))
-            {
+            if yubikey.version.major == 4 && !yubikey.version.at_least(4, 3, 5) {
                 setting_roca = settings::BoolValue::get(SZ_SETTING_ROCA, true);
 
                 let psz_msg = match setting_roca.source {
@@ -511,9 +606,12 @@ pub fn generate(
         _ => (),
     }
 
+    // the slot's previous key is gone once generation is attempted
+    yubikey.note_public_key(slot, None);
+
     let txn = yubikey.begin_transaction()?;
 
-    let templ = [0, Ins::GenerateAsymmetric.code(), 0, slot.into()];
+    let templ = ApduTemplate::new(Ins::GenerateAsymmetric, 0, slot.into());
 
     let mut in_data = [0u8; 11];
     let mut offset = Tlv::write_as(&mut in_data, 0xac, 3, |buf| {
@@ -528,7 +626,7 @@ pub fn generate(
     in_data[1] += touch_len as u8;
     offset += touch_len;
 
-    let response = txn.transfer_data(&templ, &in_data[..offset], 1024)?;
+    let response = txn.transfer_data(templ, &in_data[..offset], 1024)?;
 
     if !response.is_success() {
         let err_msg = "failed to generate new key";
@@ -563,7 +661,7 @@ pub fn generate(
     // TODO(str4d): Response is wrapped in an ASN.1 TLV:
     //
     //    0x7f 0x49 -> Application | Constructed | 0x49
-    match algorithm {
+    let data = match algorithm {
         AlgorithmId::Rsa1024 | AlgorithmId::Rsa2048 => {
             // It appears that the inner application-specific value returned by the
             // YubiKey is constructed such that RSA pubkeys can be parsed in two ways:
@@ -592,8 +690,96 @@ pub fn generate(
             //
             // Because of the above, treat this for now as a 2-byte ASN.1 tag with a
             // 3-byte length.
-            let data = &response.data()[5..];
+            &response.data()[5..]
+        }
+        AlgorithmId::EccP256 | AlgorithmId::EccP384 => {
+            // 2-byte ASN.1 tag, 1-byte length (because all supported EC pubkey lengths
+            // are shorter than 128 bytes, fitting into a definite short ASN.1 length).
+            &response.data()[3..]
+        }
+    };
+
+    let public_key = read_public_key(algorithm, data)?;
+
+    drop(txn);
+    yubikey.note_touch_policy(slot, touch_policy);
+    yubikey.note_public_key(slot, Some(public_key.clone()));
+
+    Ok(public_key)
+}
+
+/// Generate a key for the given purpose in its default slot, using the
+/// recommended algorithm (see [`SlotPurpose`]).
+///
+/// The slot's default PIN and touch policies are used, which already suit
+/// its purpose: e.g. the PIN is required for every signature in the
+/// [`SlotId::Signature`] slot, and never for [`SlotId::CardAuthentication`].
+pub fn generate_for_purpose(
+    yubikey: &mut YubiKey,
+    purpose: SlotPurpose,
+) -> Result<PublicKeyInfo, Error> {
+    generate(
+        yubikey,
+        purpose.default_slot(),
+        purpose.recommended_algorithm(),
+        PinPolicy::Default,
+        TouchPolicy::Default,
+    )
+}
+
+/// Public exponent of RSA keys generated on YubiKeys: F4 (65537)
+pub const RSA_EXPONENT_F4: u32 = 65537;
+
+/// Generate an RSA key with the given public exponent (F4 if `None`).
+///
+/// PIV's GENERATE ASYMMETRIC KEY PAIR command has no way to request a public
+/// exponent, and YubiKeys always generate keys with F4: no firmware version
+/// (YubiKey 4 and 5 alike) allows a different exponent. Every other exponent
+/// is therefore rejected with [`Error::NotSupported`].
+pub fn generate_rsa(
+    yubikey: &mut YubiKey,
+    slot: SlotId,
+    algorithm: AlgorithmId,
+    pin_policy: PinPolicy,
+    touch_policy: TouchPolicy,
+    exponent: Option<u32>,
+) -> Result<PublicKeyInfo, Error> {
+    if !matches!(algorithm, AlgorithmId::Rsa1024 | AlgorithmId::Rsa2048) {
+        error!("{:?} is not an RSA algorithm", algorithm);
+        return Err(Error::AlgorithmError);
+    }
+
+    let exponent = exponent.unwrap_or(RSA_EXPONENT_F4);
 
+    if exponent != RSA_EXPONENT_F4 {
+        error!(
+            "YubiKey firmware {} can't generate RSA keys with public exponent {}",
+            yubikey.version, exponent
+        );
+        return Err(Error::NotSupported);
+    }
+
+    let public_key = generate(yubikey, slot, algorithm, pin_policy, touch_policy)?;
+
+    if let PublicKeyInfo::Rsa { pubkey, .. } = &public_key {
+        if *pubkey.e() != BigUint::from(exponent) {
+            error!(
+                "generated RSA key has unexpected public exponent {}",
+                pubkey.e()
+            );
+            return Err(Error::InvalidObject);
+        }
+    }
+
+    Ok(public_key)
+}
+
+/// Parse a public key from the TLV-encoded form the YubiKey uses in key
+/// generation responses and key metadata (i.e. `0x81` modulus / `0x82`
+/// exponent for RSA, `0x86` point for ECC).
+fn read_public_key(algorithm: AlgorithmId, data: &[u8]) -> Result<PublicKeyInfo, Error> {
+    match algorithm {
+        AlgorithmId::Rsa1024 | AlgorithmId::Rsa2048 => {
             let (data, modulus_tlv) = Tlv::parse(data)?;
             if modulus_tlv.tag != TAG_RSA_MODULUS {
                 error!("Failed to parse public key structure (modulus)");
@@ -618,10 +804,6 @@ pub fn generate(
             })
         }
         AlgorithmId::EccP256 | AlgorithmId::EccP384 => {
-            // 2-byte ASN.1 tag, 1-byte length (because all supported EC pubkey lengths
-            // are shorter than 128 bytes, fitting into a definite short ASN.1 length).
-            let data = &response.data()[3..];
-
             let len = if let AlgorithmId::EccP256 = algorithm {
                 CB_ECC_POINTP256
             } else {
@@ -653,6 +835,124 @@ pub fn generate(
     }
 }
 
+/// Metadata about a key (or PIN) as reported by the YubiKey.
+///
+/// Requires YubiKey firmware 5.3 or newer.
+#[derive(Clone, Debug)]
+pub struct SlotMetadata {
+    /// Raw algorithm identifier. This is not necessarily an [`AlgorithmId`],
+    /// e.g. it is `0x03` (3DES) for [`SlotId::CardManagement`].
+    pub algorithm: u8,
+
+    /// PIN policy of the key
+    pub pin_policy: Option<PinPolicy>,
+
+    /// Touch policy of the key
+    pub touch_policy: Option<TouchPolicy>,
+
+    /// Was the key generated on the device (as opposed to imported)?
+    pub generated: Option<bool>,
+
+    /// Public key
+    pub public_key: Option<PublicKeyInfo>,
+
+    /// Is this the factory default value? (PIN, PUK and management key only)
+    pub is_default: Option<bool>,
+
+    /// Total and remaining attempts, respectively (PIN and PUK only)
+    pub retries: Option<(u8, u8)>,
+}
+
+impl SlotMetadata {
+    /// Parse the response to a GET METADATA command
+    pub(crate) fn parse(mut data: &[u8]) -> Result<Self, Error> {
+        let mut algorithm = None;
+        let mut metadata = SlotMetadata {
+            algorithm: 0,
+            pin_policy: None,
+            touch_policy: None,
+            generated: None,
+            public_key: None,
+            is_default: None,
+            retries: None,
+        };
+        let mut public_key = None;
+
+        while !data.is_empty() {
+            let (remaining, tlv) = Tlv::parse(data)?;
+            data = remaining;
+
+            match (tlv.tag, tlv.value) {
+                (TAG_METADATA_ALGO, [algo]) => algorithm = Some(*algo),
+                (TAG_METADATA_POLICY, [pin, touch]) => {
+                    metadata.pin_policy = Some(PinPolicy::try_from(*pin)?);
+                    metadata.touch_policy = Some(TouchPolicy::try_from(*touch)?);
+                }
+                (TAG_METADATA_ORIGIN, [origin]) => metadata.generated = Some(*origin == 1),
+                (TAG_METADATA_PUBLIC_KEY, value) => public_key = Some(value),
+                (TAG_METADATA_DEFAULT, [default]) => metadata.is_default = Some(*default != 0),
+                (TAG_METADATA_RETRIES, [total, remaining]) => {
+                    metadata.retries = Some((*total, *remaining))
+                }
+                (tag, _) => debug!("ignoring unknown metadata tag {:02x}", tag),
+            }
+        }
+
+        metadata.algorithm = algorithm.ok_or_else(|| {
+            error!("key metadata is missing the algorithm");
+            Error::ParseError
+        })?;
+
+        if let Some(public_key) = public_key {
+            let algorithm = AlgorithmId::try_from(metadata.algorithm)?;
+            metadata.public_key = Some(read_public_key(algorithm, public_key)?);
+        }
+
+        Ok(metadata)
+    }
+}
+
+/// Get metadata about the key in the given slot.
+///
+/// Requires YubiKey firmware 5.3 or newer; returns [`Error::NotSupported`] on
+/// older devices and [`Error::NotFound`] if the slot is empty.
+pub fn metadata(yubikey: &mut YubiKey, slot: SlotId) -> Result<SlotMetadata, Error> {
+    let metadata = {
+        let txn = yubikey.begin_transaction()?;
+        SlotMetadata::parse(&txn.get_metadata(slot.into())?)?
+    };
+
+    if let Some(touch_policy) = metadata.touch_policy {
+        yubikey.note_touch_policy(slot, touch_policy);
+    }
+
+    Ok(metadata)
+}
+
+/// Get the public key for the key in the given slot, without regenerating it.
+///
+/// This uses key metadata where supported (YubiKey 5.3+), and otherwise falls
+/// back to the public key in the slot's certificate. Returns
+/// [`Error::NotFound`] if neither is available.
+///
+/// The public key is cached on the [`YubiKey`] until the slot's key is
+/// replaced with [`generate`] or [`import_key`], its certificate is written
+/// or deleted, or the device is reset, so repeated lookups for the same slot
+/// don't go back to the device.
+pub fn get_public_key(yubikey: &mut YubiKey, slot: SlotId) -> Result<PublicKeyInfo, Error> {
+    if let Some(public_key) = yubikey.cached_public_key(slot) {
+        return Ok(public_key.clone());
+    }
+
+    let public_key = {
+        let txn = yubikey.begin_transaction()?;
+        txn.get_public_key(slot)?
+    };
+
+    yubikey.note_public_key(slot, Some(public_key.clone()));
+    Ok(public_key)
+}
+
 fn write_key(
     yubikey: &mut YubiKey,
     slot: SlotId,
@@ -662,7 +962,7 @@ fn write_key(
     algorithm: AlgorithmId,
 ) -> Result<(), Error> {
     let mut key_data = Buffer::new(vec![0u8; KEYDATA_LEN]);
-    let templ = [0, Ins::ImportKey.code(), algorithm.into(), slot.into()];
+    let templ = ApduTemplate::new(Ins::ImportKey, algorithm.into(), slot.into());
     let mut offset = 0;
 
     let elem_len = algorithm.get_elem_len();
@@ -686,16 +986,22 @@ fn write_key(
     offset += pin_policy.write(&mut key_data[offset..])?;
     offset += touch_policy.write(&mut key_data[offset..])?;
 
-    let txn = yubikey.begin_transaction()?;
+    // even a failed import may have replaced the slot's previous key
+    yubikey.note_public_key(slot, None);
 
-    let status_words = txn
-        .transfer_data(&templ, &key_data[..offset], 256)?
-        .status_words();
+    let status_words = {
+        let txn = yubikey.begin_transaction()?;
+        txn.transfer_data(templ, &key_data[..offset], 256)?
+            .status_words()
+    };
 
     match status_words {
-        StatusWords::Success => Ok(()),
+        StatusWords::Success => {
+            yubikey.note_touch_policy(slot, touch_policy);
+            Ok(())
+        }
         StatusWords::SecurityStatusError => Err(Error::AuthenticationError),
-        _ => Err(Error::GenericError),
+        other => Err(other.to_error()),
     }
 }
 
@@ -714,9 +1020,8 @@ pub struct RsaKeyData {
     qinv: Buffer,
 }
 
-use num_bigint_dig::ToBigUint;
 use num_bigint_dig::traits::ModInverse;
-
+use num_bigint_dig::ToBigUint;
 
 impl RsaKeyData {
     /// Generates a new RSA key data set from two randomly generated, secret, primes.
@@ -733,8 +1038,8 @@ impl RsaKeyData {
             p_t.lcm(&q_t)
         };
 
-        let exp : BigUint = BigUint::from_u64(KEYDATA_RSA_EXP).unwrap();  
-        
+        let exp: BigUint = BigUint::from_u64(KEYDATA_RSA_EXP).unwrap();
+
         let d = exp.mod_inverse(&totient).unwrap();
         let d = d.to_biguint().unwrap();
 
@@ -756,6 +1061,11 @@ impl RsaKeyData {
         }
     }
 
+    /// Create a key data set from already computed CRT parameters.
+    pub(crate) fn from_crt(p: Buffer, q: Buffer, dp: Buffer, dq: Buffer, qinv: Buffer) -> Self {
+        RsaKeyData { p, q, dp, dq, qinv }
+    }
+
     fn total_len(&self) -> usize {
         self.p.len() + self.q.len() + self.dp.len() + self.qinv.len()
     }
@@ -764,7 +1074,11 @@ impl RsaKeyData {
 /// Imports a private RSA encryption or signing key into the YubiKey.
 ///
 /// Errors if `algorithm` isn't `AlgorithmId::Rsa1024` or `AlgorithmId::Rsa2048`.
-
+///
+/// NOTE: the private key is sent to the YubiKey in plaintext. The PIV
+/// application has no import of wrapped (encrypted) keys on any firmware
+/// version, including 5.7; protecting the key in transit would require a
+/// secure channel (SCP03/SCP11), which this crate doesn't implement.
 pub fn import_rsa_key(
     yubikey: &mut YubiKey,
     slot: SlotId,
@@ -797,8 +1111,10 @@ pub fn import_rsa_key(
 
 /// Imports a private ECC encryption or signing key into the YubiKey.
 ///
-/// Errors if `algorithm` isn't `AlgorithmId::EccP256` or ` AlgorithmId::EccP384`.
-#[cfg(feature = "untested")]
+/// Errors if `algorithm` isn't `AlgorithmId::EccP256` or ` AlgorithmId::EccP384`,
+/// or if `key_data` is longer than the curve's private scalar.
+///
+/// NOTE: the private key is sent in plaintext; see [`import_rsa_key`].
 pub fn import_ecc_key(
     yubikey: &mut YubiKey,
     slot: SlotId,
@@ -812,7 +1128,7 @@ pub fn import_ecc_key(
         _ => return Err(Error::AlgorithmError),
     }
 
-    if key_data.len() > KEYDATA_LEN {
+    if key_data.len() > algorithm.get_elem_len() {
         return Err(Error::SizeError);
     }
 
@@ -823,20 +1139,136 @@ pub fn import_ecc_key(
     Ok(())
 }
 
+/// A private key to import with [`import_key`].
+pub enum PrivateKeyData {
+    /// RSA private key
+    Rsa {
+        /// [`AlgorithmId::Rsa1024`] or [`AlgorithmId::Rsa2048`]
+        algorithm: AlgorithmId,
+
+        /// CRT parameters of the key
+        key_data: RsaKeyData,
+    },
+
+    /// ECC private key
+    Ecc {
+        /// [`AlgorithmId::EccP256`] or [`AlgorithmId::EccP384`]
+        algorithm: AlgorithmId,
+
+        /// Big-endian private scalar
+        scalar: Buffer,
+    },
+}
+
+impl PrivateKeyData {
+    /// Parse a DER-encoded PKCS#8 private key (e.g. a `.key` file after
+    /// base64 decoding), containing an RSA-1024, RSA-2048, P-256 or P-384
+    /// key.
+    ///
+    /// Returns [`Error::AlgorithmError`] for any other type or size of key,
+    /// [`Error::NotSupported`] for RSA keys whose public exponent isn't
+    /// 65537, and [`Error::ParseError`] if the DER is malformed. Encrypted
+    /// PKCS#8 keys aren't supported.
+    #[cfg(feature = "pkcs8")]
+    pub fn from_pkcs8_der(bytes: &[u8]) -> Result<Self, Error> {
+        pkcs8::parse_pkcs8(bytes)
+    }
+
+    /// Parse a PEM-encoded private key: either PKCS#8 (`PRIVATE KEY`),
+    /// SEC1 (`EC PRIVATE KEY`) or PKCS#1 (`RSA PRIVATE KEY`). The input may
+    /// contain other text (e.g. a certificate), but only one private key.
+    ///
+    /// Returns [`Error::EncryptedKey`] for password protected keys, which
+    /// must be decrypted first. See [`PrivateKeyData::from_pkcs8_der`] for
+    /// the other errors.
+    #[cfg(feature = "pkcs8")]
+    pub fn from_pem(s: &str) -> Result<Self, Error> {
+        let input = s.as_bytes();
+
+        if pem::is_pem(input, pem::ENCRYPTED_PRIVATE_KEY) || pem::is_encrypted(input) {
+            error!("encrypted private keys can't be imported, decrypt the key first");
+            return Err(Error::EncryptedKey);
+        }
+
+        if pem::is_pem(input, pem::PRIVATE_KEY) {
+            pkcs8::parse_pkcs8(&decode_pem_key(input, pem::PRIVATE_KEY)?)
+        } else if pem::is_pem(input, pem::EC_PRIVATE_KEY) {
+            pkcs8::parse_sec1(&decode_pem_key(input, pem::EC_PRIVATE_KEY)?)
+        } else if pem::is_pem(input, pem::RSA_PRIVATE_KEY) {
+            pkcs8::parse_pkcs1(&decode_pem_key(input, pem::RSA_PRIVATE_KEY)?)
+        } else {
+            error!("no PEM private key found");
+            Err(Error::ParseError)
+        }
+    }
+
+    /// Algorithm of the key
+    pub fn algorithm(&self) -> AlgorithmId {
+        match self {
+            PrivateKeyData::Rsa { algorithm, .. } | PrivateKeyData::Ecc { algorithm, .. } => {
+                *algorithm
+            }
+        }
+    }
+}
+
+/// Decode the single PEM private key block with the given label
+#[cfg(feature = "pkcs8")]
+fn decode_pem_key(input: &[u8], label: &str) -> Result<Buffer, Error> {
+    let mut blocks: Vec<_> = pem::decode(input, label)?
+        .into_iter()
+        .map(Zeroizing::new)
+        .collect();
+
+    if blocks.len() != 1 {
+        error!(
+            "expected a single PEM {} block, found {}",
+            label,
+            blocks.len()
+        );
+        return Err(Error::ArgumentError);
+    }
+
+    Ok(blocks.remove(0))
+}
+
+/// Imports a private key into the YubiKey.
+///
+/// NOTE: the private key is sent in plaintext; see [`import_rsa_key`].
+///
+/// A failed import leaves the slot's previous key in place, except for
+/// [`Error::PartialWrite`]: RSA keys are sent in several chunks, and if an
+/// intermediate one is rejected after the first the slot is left in an
+/// unknown state. Import the key again (or generate a new one) before using
+/// the slot.
+pub fn import_key(
+    yubikey: &mut YubiKey,
+    slot: SlotId,
+    key: PrivateKeyData,
+    touch_policy: TouchPolicy,
+    pin_policy: PinPolicy,
+) -> Result<(), Error> {
+    match key {
+        PrivateKeyData::Rsa {
+            algorithm,
+            key_data,
+        } => import_rsa_key(yubikey, slot, algorithm, key_data, touch_policy, pin_policy),
+        PrivateKeyData::Ecc { algorithm, scalar } => {
+            import_ecc_key(yubikey, slot, algorithm, &scalar, touch_policy, pin_policy)
+        }
+    }
+}
+
 /// Generate an attestation certificate for a stored key.
 /// <https://developers.yubico.com/PIV/Introduction/PIV_attestation.html>
 
 pub fn attest(yubikey: &mut YubiKey, key: SlotId) -> Result<Buffer, Error> {
-    let templ = [0, Ins::Attest.code(), key.into(), 0];
+    let templ = ApduTemplate::new(Ins::Attest, key.into(), 0);
     let txn = yubikey.begin_transaction()?;
-    let response = txn.transfer_data(&templ, &[], CB_OBJ_MAX)?;
+    let response = txn.transfer_data(templ, &[], CB_OBJ_MAX)?;
 
     if !response.is_success() {
-        if response.status_words() == StatusWords::NotSupportedError {
-            return Err(Error::NotSupported);
-        } else {
-            return Err(Error::GenericError);
-        }
+        return Err(response.status_words().to_error());
     }
 
     if response.data()[0] != 0x30 {
@@ -846,17 +1278,784 @@ pub fn attest(yubikey: &mut YubiKey, key: SlotId) -> Result<Buffer, Error> {
     Ok(Buffer::new(response.data().into()))
 }
 
-/// Sign data using a PIV key
+/// Yubico attestation extension OID arc
+const YUBICO_ATTESTATION_ARC: &str = "1.3.6.1.4.1.41482.3";
+
+/// Attestation extension holding the firmware version (1.3.6.1.4.1.41482.3.3)
+const EXT_FIRMWARE_VERSION: u8 = 0x03;
+
+/// Attestation extension holding the serial number (1.3.6.1.4.1.41482.3.7)
+const EXT_SERIAL: u8 = 0x07;
+
+/// Attestation extension holding the PIN and touch policies (1.3.6.1.4.1.41482.3.8)
+const EXT_POLICIES: u8 = 0x08;
+
+/// Attestation extension holding the form factor (1.3.6.1.4.1.41482.3.9)
+const EXT_FORM_FACTOR: u8 = 0x09;
+
+/// Key properties vouched for by an attestation certificate.
+///
+/// Each field is `None` if the corresponding extension is absent, which is
+/// the case for properties older firmware doesn't report.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AttestedProperties {
+    /// Firmware version of the YubiKey which generated the key
+    pub firmware_version: Option<Version>,
+
+    /// Serial number of the YubiKey which generated the key
+    pub serial: Option<Serial>,
+
+    /// PIN policy of the attested key
+    pub pin_policy: Option<PinPolicy>,
+
+    /// Touch policy of the attested key
+    pub touch_policy: Option<TouchPolicy>,
+
+    /// Form factor of the YubiKey, as the raw byte reported by the device
+    pub form_factor: Option<u8>,
+}
+
+impl AttestedProperties {
+    /// Extract the Yubico attestation extensions from a certificate.
+    pub fn parse(cert: &Certificate) -> Result<Self, Error> {
+        let tbs = match parse_x509_certificate(cert.as_ref()) {
+            Ok((_, cert)) => cert.tbs_certificate,
+            _ => return Err(Error::ParseError),
+        };
+
+        let firmware_version = match attestation_extension(&tbs, EXT_FIRMWARE_VERSION) {
+            Some(&[major, minor, patch]) => Some(Version {
+                major,
+                minor,
+                patch,
+            }),
+            Some(_) => return Err(Error::ParseError),
+            None => None,
+        };
+
+        let serial = match attestation_extension(&tbs, EXT_SERIAL) {
+            Some(value) => {
+                let (_, tlv) = Tlv::parse(value)?;
+
+                if tlv.tag != 0x02 || tlv.value.is_empty() || tlv.value.len() > 5 {
+                    return Err(Error::ParseError);
+                }
+
+                let serial = tlv
+                    .value
+                    .iter()
+                    .fold(0u64, |acc, &b| (acc << 8) | u64::from(b));
+
+                Some(Serial(
+                    u32::try_from(serial).map_err(|_| Error::ParseError)?,
+                ))
+            }
+            None => None,
+        };
+
+        let (pin_policy, touch_policy) = match attestation_extension(&tbs, EXT_POLICIES) {
+            Some(&[pin, touch]) => (
+                Some(PinPolicy::try_from(pin)?),
+                Some(TouchPolicy::try_from(touch)?),
+            ),
+            Some(_) => return Err(Error::ParseError),
+            None => (None, None),
+        };
+
+        let form_factor = match attestation_extension(&tbs, EXT_FORM_FACTOR) {
+            Some(&[form_factor]) => Some(form_factor),
+            Some(_) => return Err(Error::ParseError),
+            None => None,
+        };
+
+        Ok(AttestedProperties {
+            firmware_version,
+            serial,
+            pin_policy,
+            touch_policy,
+            form_factor,
+        })
+    }
+}
+
+/// Find the value of the Yubico attestation extension `arc` in a certificate,
+/// i.e. the contents of its `extnValue` OCTET STRING.
+fn attestation_extension<'a>(tbs: &TbsCertificate<'a>, arc: u8) -> Option<&'a [u8]> {
+    let oid = format!("{}.{}", YUBICO_ATTESTATION_ARC, arc);
+
+    tbs.extensions()
+        .iter()
+        .find(|(ext_oid, _)| ext_oid.to_id_string() == oid)
+        .map(|(_, ext)| ext.value)
+}
+
+/// Proof that a key was generated on a YubiKey, see [`prove_hardware_backed`].
+#[derive(Clone, Debug)]
+pub struct AttestationProof {
+    /// Attestation certificate for the key, signed by the attestation key
+    pub attestation: Certificate,
+
+    /// Certificate of the attestation key, signed by the Yubico PIV CA
+    pub intermediate: Certificate,
+
+    /// Key properties attested to by `attestation`
+    pub properties: AttestedProperties,
+}
+
+/// Attest the key in `slot` and collect everything needed to prove it is
+/// hardware backed: the attestation certificate, the intermediate certificate
+/// of the attestation key, and the properties the device attests to.
+///
+/// The chain still has to be verified against the Yubico PIV root CA by the
+/// relying party. Returns [`Error::NotSupported`] on devices without
+/// attestation support (firmware older than 4.3).
+pub fn prove_hardware_backed(
+    yubikey: &mut YubiKey,
+    slot: SlotId,
+) -> Result<AttestationProof, Error> {
+    if !yubikey.is_yubikey() || !yubikey.version.at_least(4, 3, 0) {
+        error!("attestation requires YubiKey firmware 4.3 or newer");
+        return Err(Error::NotSupported);
+    }
+
+    let attestation = Certificate::from_bytes(attest(yubikey, slot)?)?;
+    let intermediate = Certificate::read(yubikey, SlotId::Attestation)?;
+    let properties = AttestedProperties::parse(&attestation)?;
+
+    Ok(AttestationProof {
+        attestation,
+        intermediate,
+        properties,
+    })
+}
+
+/// Sign data using a PIV key.
+///
+/// For RSA keys `raw_in` must be a complete, already padded signature block
+/// the size of the modulus. For ECC keys it is the digest to sign, which may
+/// be at most the size of the curve (32 bytes for P-256, 48 bytes for P-384):
+/// ECDSA only signs the leftmost bits of larger digests, so e.g. a SHA-512
+/// digest must be truncated before signing it with a P-256 key. Use
+/// [`sign_ecdsa`] to have this done automatically.
 pub fn sign_data(
     yubikey: &mut YubiKey,
     raw_in: &[u8],
     algorithm: AlgorithmId,
     key: SlotId,
 ) -> Result<Buffer, Error> {
+    let started = Instant::now();
+
+    let signature = {
+        let txn = yubikey.begin_transaction()?;
+
+        // don't attempt to reselect in crypt operations to avoid problems with PIN_ALWAYS
+        txn.authenticated_command(raw_in, algorithm, key, false)?
+    };
+
+    yubikey.note_key_operation(key, started);
+    Ok(signature)
+}
+
+/// Sign several inputs with the same PIV key, like calling [`sign_data`] on
+/// each of them, in a single transaction.
+///
+/// Besides saving a transaction per signature, the buffers used to exchange
+/// APDUs with the card are reused for the whole batch, which matters when
+/// signing at a high rate. Stops at the first input which fails to sign.
+///
+/// Keys with [`PinPolicy::Always`] require the PIN to be verified before
+/// every signature, so they can't sign more than one input per batch: this
+/// is checked up front with [`metadata`], returning [`Error::NotSupported`].
+/// Firmware older than 5.3 doesn't report the PIN policy, in which case the
+/// batch stops at the second input instead.
+pub fn sign_batch(
+    yubikey: &mut YubiKey,
+    inputs: &[&[u8]],
+    algorithm: AlgorithmId,
+    key: SlotId,
+) -> Result<Vec<Buffer>, Error> {
+    if inputs.len() > 1 {
+        match metadata(yubikey, key) {
+            Ok(SlotMetadata {
+                pin_policy: Some(PinPolicy::Always),
+                ..
+            }) => {
+                error!("keys with PIN policy \"always\" can't sign in batches");
+                return Err(Error::NotSupported);
+            }
+            Ok(_) | Err(Error::NotSupported) => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    let mut signatures = Vec::with_capacity(inputs.len());
+    let started = Instant::now();
+
+    {
+        let txn = yubikey.begin_transaction()?;
+        let mut response = Zeroizing::new(Vec::new());
+
+        for raw_in in inputs {
+            signatures.push(txn.authenticated_command_with(
+                raw_in,
+                algorithm,
+                key,
+                false,
+                &mut response,
+            )?);
+        }
+    }
+
+    if !signatures.is_empty() {
+        yubikey.note_key_operation(key, started);
+    }
+
+    Ok(signatures)
+}
+
+/// Sign data like [`sign_data`], recovering once from a lost PIN
+/// verification, e.g. in a long-running service whose card gets reset.
+///
+/// If signing fails because the card was reset, or with
+/// [`Error::PinRequired`] or [`Error::AuthenticationError`], the PIN cached
+/// by [`YubiKey::verify_pin`] is verified again (after reconnecting if the
+/// card was reset) and signing is retried exactly once. The error of the
+/// retry is returned as-is. If no PIN is cached, e.g. because it was only
+/// verified with [`YubiKey::verify_pin_ref`], the original error is returned
+/// for the caller to handle.
+pub fn sign_data_reverifying(
+    yubikey: &mut YubiKey,
+    raw_in: &[u8],
+    algorithm: AlgorithmId,
+    key: SlotId,
+) -> Result<Buffer, Error> {
+    match sign_data(yubikey, raw_in, algorithm, key) {
+        Err(e @ Error::PinRequired)
+        | Err(e @ Error::AuthenticationError)
+        | Err(
+            e @ Error::PcscError {
+                inner: Some(pcsc::Error::ResetCard),
+            },
+        ) => {
+            if !yubikey.reverify_cached_pin()? {
+                return Err(e);
+            }
+
+            warn!(
+                "PIN verification lost ({}); retrying with the cached PIN",
+                e
+            );
+            sign_data(yubikey, raw_in, algorithm, key)
+        }
+        result => result,
+    }
+}
+
+/// Sign a digest of any size with an ECC key.
+///
+/// As specified by ECDSA, digests larger than the curve size are truncated
+/// to their leftmost bits, i.e. their first 32 bytes for P-256 or 48 bytes
+/// for P-384.
+pub fn sign_ecdsa(
+    yubikey: &mut YubiKey,
+    digest: &[u8],
+    algorithm: AlgorithmId,
+    key: SlotId,
+) -> Result<Buffer, Error> {
+    let key_len = match algorithm {
+        AlgorithmId::EccP256 => 32,
+        AlgorithmId::EccP384 => 48,
+        _ => {
+            error!("ECDSA requires an ECC key (got {:?})", algorithm);
+            return Err(Error::AlgorithmError);
+        }
+    };
+
+    sign_data(
+        yubikey,
+        &digest[..digest.len().min(key_len)],
+        algorithm,
+        key,
+    )
+}
+
+/// Setting which allows [`sign_prehashed`] to sign SHA-1 digests
+const SZ_SETTING_SHA1: &str = "Enable_Unsafe_Sign_SHA1";
+
+/// Hash algorithms of digests signed with [`sign_prehashed`]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum HashAlgorithm {
+    /// SHA-1 (legacy: only allowed when the `Enable_Unsafe_Sign_SHA1` setting
+    /// is enabled)
+    Sha1,
+
+    /// SHA-256
+    Sha256,
+
+    /// SHA-384
+    Sha384,
+
+    /// SHA-512
+    Sha512,
+}
+
+impl HashAlgorithm {
+    /// Size of a digest in bytes
+    pub fn output_size(self) -> usize {
+        match self {
+            HashAlgorithm::Sha1 => 20,
+            HashAlgorithm::Sha256 => 32,
+            HashAlgorithm::Sha384 => 48,
+            HashAlgorithm::Sha512 => 64,
+        }
+    }
+
+    /// Compute the digest of `data`
+    pub fn digest(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            HashAlgorithm::Sha1 => Sha1::digest(data).to_vec(),
+            HashAlgorithm::Sha256 => Sha256::digest(data).to_vec(),
+            HashAlgorithm::Sha384 => Sha384::digest(data).to_vec(),
+            HashAlgorithm::Sha512 => Sha512::digest(data).to_vec(),
+        }
+    }
+
+    /// DER encoding of the PKCS#1 `DigestInfo` structure up to (but not
+    /// including) the digest itself. See RFC 8017, section 9.2, note 1.
+    pub fn digest_info_prefix(self) -> &'static [u8] {
+        match self {
+            HashAlgorithm::Sha1 => &[
+                0x30, 0x21, 0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a, 0x05, 0x00, 0x04,
+                0x14,
+            ],
+            HashAlgorithm::Sha256 => &[
+                0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02,
+                0x01, 0x05, 0x00, 0x04, 0x20,
+            ],
+            HashAlgorithm::Sha384 => &[
+                0x30, 0x41, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02,
+                0x02, 0x05, 0x00, 0x04, 0x30,
+            ],
+            HashAlgorithm::Sha512 => &[
+                0x30, 0x51, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02,
+                0x03, 0x05, 0x00, 0x04, 0x40,
+            ],
+        }
+    }
+}
+
+/// Sign a digest computed with `hash` using the key in `slot`.
+///
+/// For RSA keys this is [`sign_rsa`]. For ECC keys the digest is truncated
+/// to its leftmost bits per the curve size, as specified by ECDSA.
+///
+/// SHA-1 digests are rejected with [`Error::AlgorithmError`] unless the
+/// `Enable_Unsafe_Sign_SHA1` setting is enabled, either in the configuration
+/// file or with the `YUBIKEY_PIV_Enable_Unsafe_Sign_SHA1` environment
+/// variable.
+pub fn sign_prehashed(
+    yubikey: &mut YubiKey,
+    slot: SlotId,
+    algorithm: AlgorithmId,
+    digest: &[u8],
+    hash: HashAlgorithm,
+) -> Result<Buffer, Error> {
+    match algorithm {
+        AlgorithmId::Rsa1024 | AlgorithmId::Rsa2048 => {
+            sign_rsa(yubikey, slot, algorithm, digest, hash)
+        }
+        AlgorithmId::EccP256 | AlgorithmId::EccP384 => {
+            check_digest(digest, hash)?;
+            sign_ecdsa(yubikey, digest, algorithm, slot)
+        }
+    }
+}
+
+/// Sign a digest computed with `hash` with the RSA key in `slot`, using
+/// RSASSA-PKCS1-v1_5 (RFC 8017, section 8.2).
+///
+/// The YubiKey only performs the raw RSA operation: the digest is wrapped in
+/// a `DigestInfo` and padded into a signature block the size of the modulus
+/// on the host. Returns [`Error::AlgorithmError`] if `algorithm` isn't RSA,
+/// and [`Error::SizeError`] if the digest doesn't have the size of `hash`.
+///
+/// SHA-1 digests are rejected like in [`sign_prehashed`].
+pub fn sign_rsa(
+    yubikey: &mut YubiKey,
+    slot: SlotId,
+    algorithm: AlgorithmId,
+    digest: &[u8],
+    hash: HashAlgorithm,
+) -> Result<Buffer, Error> {
+    rsa_key_len(algorithm)?;
+    check_digest(digest, hash)?;
+    sign_pkcs1v15(yubikey, slot, algorithm, hash.digest_info_prefix(), digest)
+}
+
+/// Pad `prefix || digest` (normally a `DigestInfo`) into an EMSA-PKCS1-v1_5
+/// signature block and sign it with the RSA key in `slot`.
+///
+/// Returns [`Error::SizeError`] if there isn't room for at least 8 bytes of
+/// padding, as required by RFC 8017.
+pub(crate) fn sign_pkcs1v15(
+    yubikey: &mut YubiKey,
+    slot: SlotId,
+    algorithm: AlgorithmId,
+    prefix: &[u8],
+    digest: &[u8],
+) -> Result<Buffer, Error> {
+    let key_len = rsa_key_len(algorithm)?;
+
+    if prefix.len() + digest.len() + 11 > key_len {
+        error!(
+            "{} bytes are too long to sign with {:?}",
+            prefix.len() + digest.len(),
+            algorithm
+        );
+        return Err(Error::SizeError);
+    }
+
+    let padding_len = key_len - 3 - prefix.len() - digest.len();
+
+    // EMSA-PKCS1-v1_5: 0x00 || 0x01 || 0xff... || 0x00 || DigestInfo
+    let mut block = Zeroizing::new(Vec::with_capacity(key_len));
+    block.extend_from_slice(&[0x00, 0x01]);
+    block.resize(2 + padding_len, 0xff);
+    block.push(0x00);
+    block.extend_from_slice(prefix);
+    block.extend_from_slice(digest);
+
+    sign_data(yubikey, &block, algorithm, slot)
+}
+
+/// Sign a digest computed with `hash` with the RSA key in `slot`, using
+/// RSASSA-PSS (RFC 8017, section 8.1) with MGF1 over `hash` and a random salt
+/// as long as the digest, as required by TLS 1.3.
+///
+/// The YubiKey only performs the raw RSA operation: the digest is encoded
+/// with EMSA-PSS on the host. Returns [`Error::SizeError`] if the encoding
+/// doesn't fit the modulus (SHA-512 with RSA 1024), and otherwise fails like
+/// [`sign_rsa`].
+pub fn sign_rsa_pss(
+    yubikey: &mut YubiKey,
+    slot: SlotId,
+    algorithm: AlgorithmId,
+    digest: &[u8],
+    hash: HashAlgorithm,
+) -> Result<Buffer, Error> {
+    let key_len = rsa_key_len(algorithm)?;
+    check_digest(digest, hash)?;
+
+    let h_len = hash.output_size();
+
+    if key_len < 2 * h_len + 2 {
+        error!(
+            "{:?} digests are too long for RSA-PSS with {:?}",
+            hash, algorithm
+        );
+        return Err(Error::SizeError);
+    }
+
+    let mut salt = vec![0u8; h_len];
+    getrandom(&mut salt).map_err(|_| Error::RandomnessError)?;
+
+    // H = Hash(0x00 * 8 || mHash || salt)
+    let mut m_prime = Vec::with_capacity(8 + 2 * h_len);
+    m_prime.extend_from_slice(&[0u8; 8]);
+    m_prime.extend_from_slice(digest);
+    m_prime.extend_from_slice(&salt);
+    let h = hash.digest(&m_prime);
+
+    // EM = maskedDB || H || 0xbc, where DB = PS (zero bytes) || 0x01 || salt.
+    // The modulus is a whole number of bytes, so only its top bit is cleared.
+    let db_len = key_len - h_len - 1;
+    let mut block = Zeroizing::new(vec![0u8; db_len - h_len - 1]);
+    block.push(0x01);
+    block.extend_from_slice(&salt);
+    mgf1_xor(hash, &h, &mut block);
+    block[0] &= 0x7f;
+    block.extend_from_slice(&h);
+    block.push(0xbc);
+
+    sign_data(yubikey, &block, algorithm, slot)
+}
+
+/// Sign a DER-encoded `TBSCertificate` (or any other to-be-signed structure,
+/// e.g. a CSR's `CertificationRequestInfo`) built by an external X.509
+/// library, hashing it with `hash`.
+///
+/// Returns the signature as it is embedded in the certificate's
+/// `signatureValue`: an RSASSA-PKCS1-v1_5 signature for RSA keys, and a
+/// DER-encoded `ECDSA-Sig-Value` for ECC keys. The certificate's
+/// `signatureAlgorithm` must match `algorithm` and `hash`.
+pub fn sign_tbs(
+    yubikey: &mut YubiKey,
+    slot: SlotId,
+    tbs_der: &[u8],
+    algorithm: AlgorithmId,
+    hash: HashAlgorithm,
+) -> Result<Buffer, Error> {
+    sign_prehashed(yubikey, slot, algorithm, &hash.digest(tbs_der), hash)
+}
+
+/// Check that a digest to sign has the size of `hash`, and that `hash` is
+/// allowed for signing
+fn check_digest(digest: &[u8], hash: HashAlgorithm) -> Result<(), Error> {
+    if digest.len() != hash.output_size() {
+        error!(
+            "{:?} digest must be {} bytes (got {})",
+            hash,
+            hash.output_size(),
+            digest.len()
+        );
+        return Err(Error::SizeError);
+    }
+
+    if hash == HashAlgorithm::Sha1 && !settings::BoolValue::get(SZ_SETTING_SHA1, false).value {
+        error!(
+            "signing SHA-1 digests is disabled (see the {} setting)",
+            SZ_SETTING_SHA1
+        );
+        return Err(Error::AlgorithmError);
+    }
+
+    Ok(())
+}
+
+/// Size of the modulus of an RSA key in bytes
+fn rsa_key_len(algorithm: AlgorithmId) -> Result<usize, Error> {
+    match algorithm {
+        AlgorithmId::Rsa1024 => Ok(128),
+        AlgorithmId::Rsa2048 => Ok(256),
+        _ => {
+            error!("expected an RSA key (got {:?})", algorithm);
+            Err(Error::AlgorithmError)
+        }
+    }
+}
+
+/// Get the key algorithms supported by the connected device.
+///
+/// For YubiKeys this is derived from the firmware version queried when the
+/// device was opened (see [`AlgorithmId::supported_by`]); other PIV cards are
+/// assumed to support all algorithms.
+pub fn supported_algorithms(yubikey: &YubiKey) -> Vec<AlgorithmId> {
+    match yubikey.version() {
+        Some(version) => AlgorithmId::supported_by(version),
+        None => ALGORITHMS.to_vec(),
+    }
+}
+
+/// Does the given slot contain a key?
+///
+/// This can be used to avoid overwriting an existing key, e.g. before
+/// calling [`generate`].
+pub fn slot_has_key(yubikey: &mut YubiKey, slot: SlotId) -> Result<bool, Error> {
+    let txn = yubikey.begin_transaction()?;
+    txn.slot_has_key(slot)
+}
+
+/// Get the SHA-256 thumbprint of the public key in the given slot.
+///
+/// See [`PublicKeyInfo::thumbprint_sha256`].
+pub fn slot_thumbprint(yubikey: &mut YubiKey, slot: SlotId) -> Result<[u8; 32], Error> {
+    Ok(get_public_key(yubikey, slot)?.thumbprint_sha256())
+}
+
+/// Does the certificate stored in the given slot match the slot's key?
+///
+/// Use this after provisioning to catch certificates written to the wrong
+/// slot. Requires a YubiKey 5.3+ ([`Error::NotSupported`] otherwise), and
+/// returns [`Error::NotFound`] if the slot has no key or no certificate.
+pub fn verify_cert_matches_key(yubikey: &mut YubiKey, slot: SlotId) -> Result<bool, Error> {
     let txn = yubikey.begin_transaction()?;
+    txn.verify_cert_matches_key(slot)
+}
+
+/// Verify a signature made by the key in the given slot.
+///
+/// The slot's public key is looked up with [`get_public_key`], and the
+/// signature over `message` hashed with `hash` is checked on the host with
+/// [`PublicKeyInfo::verify`]. Returns `Ok(false)` if the signature doesn't
+/// verify, and [`Error::AlgorithmError`] if `algorithm` doesn't match the key
+/// in the slot.
+pub fn verify_signature(
+    yubikey: &mut YubiKey,
+    slot: SlotId,
+    message: &[u8],
+    signature: &[u8],
+    algorithm: AlgorithmId,
+    hash: HashAlgorithm,
+) -> Result<bool, Error> {
+    let public_key = get_public_key(yubikey, slot)?;
+
+    if public_key.algorithm() != algorithm {
+        error!(
+            "algorithm mismatch: slot {:?} contains a {:?} key, not {:?}",
+            slot,
+            public_key.algorithm(),
+            algorithm
+        );
+        return Err(Error::AlgorithmError);
+    }
+
+    public_key.verify(message, signature, hash)
+}
+
+/// Uncompressed elliptic curve point (`0x04 || X || Y`) on the curve of an
+/// ECC [`AlgorithmId`], e.g. a peer's public key for [`ecdh`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EcPoint {
+    algorithm: AlgorithmId,
+    bytes: Vec<u8>,
+}
+
+impl EcPoint {
+    /// Assemble the uncompressed encoding of a point from its coordinates,
+    /// without any validation. See [`EcPoint::new`] for a validated point.
+    pub fn uncompressed(x: &[u8], y: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(1 + x.len() + y.len());
+        bytes.push(0x04);
+        bytes.extend_from_slice(x);
+        bytes.extend_from_slice(y);
+        bytes
+    }
+
+    /// Create a point from its coordinates, which must be exactly the size
+    /// of the curve's field elements (32 bytes for P-256, 48 for P-384).
+    pub fn new(algorithm: AlgorithmId, x: &[u8], y: &[u8]) -> Result<Self, Error> {
+        let len = Self::coordinate_len(algorithm)?;
+
+        if x.len() != len || y.len() != len {
+            error!(
+                "invalid {:?} point: coordinates must be {} bytes (got {} and {})",
+                algorithm,
+                len,
+                x.len(),
+                y.len()
+            );
+            return Err(Error::SizeError);
+        }
+
+        Ok(EcPoint {
+            algorithm,
+            bytes: Self::uncompressed(x, y),
+        })
+    }
+
+    /// Parse an uncompressed point encoding (`0x04 || X || Y`).
+    ///
+    /// With the `ec-decompress` feature, compressed encodings are accepted
+    /// too (see [`EcPoint::decompress`]).
+    pub fn from_bytes(algorithm: AlgorithmId, bytes: &[u8]) -> Result<Self, Error> {
+        let len = Self::coordinate_len(algorithm)?;
+
+        #[cfg(feature = "ec-decompress")]
+        {
+            if bytes.first() == Some(&0x02) || bytes.first() == Some(&0x03) {
+                return Self::decompress(algorithm, bytes);
+            }
+        }
+
+        if bytes.len() != 1 + 2 * len {
+            error!(
+                "invalid {:?} point: expected {} bytes, got {}",
+                algorithm,
+                1 + 2 * len,
+                bytes.len()
+            );
+            return Err(Error::SizeError);
+        }
+
+        if bytes[0] != 0x04 {
+            error!(
+                "expected an uncompressed point, found tag {:#04x}",
+                bytes[0]
+            );
+            return Err(Error::ParseError);
+        }
+
+        Self::new(algorithm, &bytes[1..=len], &bytes[(1 + len)..])
+    }
+
+    /// Parse a compressed point encoding (`0x02 || X` or `0x03 || X`, where
+    /// the tag gives the parity of Y) by recovering the Y coordinate on the
+    /// host with the `p256` crate, as the YubiKey only accepts uncompressed
+    /// points.
+    ///
+    /// Returns [`Error::ParseError`] if X isn't the coordinate of a point on
+    /// the curve, and [`Error::NotSupported`] for P-384 points, as the `p384`
+    /// crate doesn't implement curve arithmetic yet.
+    #[cfg(feature = "ec-decompress")]
+    pub fn decompress(algorithm: AlgorithmId, bytes: &[u8]) -> Result<Self, Error> {
+        let len = Self::coordinate_len(algorithm)?;
+
+        if bytes.len() != 1 + len {
+            error!(
+                "invalid compressed {:?} point: expected {} bytes, got {}",
+                algorithm,
+                1 + len,
+                bytes.len()
+            );
+            return Err(Error::SizeError);
+        }
+
+        if bytes[0] != 0x02 && bytes[0] != 0x03 {
+            error!("expected a compressed point, found tag {:#04x}", bytes[0]);
+            return Err(Error::ParseError);
+        }
+
+        match algorithm {
+            AlgorithmId::EccP256 => {
+                use p256::elliptic_curve::sec1::{FromEncodedPoint, ToEncodedPoint};
+
+                let encoded =
+                    p256::EncodedPoint::from_bytes(bytes).map_err(|_| Error::ParseError)?;
+                let point = Option::<p256::AffinePoint>::from(
+                    p256::AffinePoint::from_encoded_point(&encoded),
+                )
+                .ok_or_else(|| {
+                    error!("invalid compressed point: X isn't on the curve");
+                    Error::ParseError
+                })?;
+
+                Self::from_bytes(algorithm, point.to_encoded_point(false).as_bytes())
+            }
+            _ => {
+                error!("decompressing {:?} points is not supported", algorithm);
+                Err(Error::NotSupported)
+            }
+        }
+    }
+
+    /// Algorithm (curve) of this point
+    pub fn algorithm(&self) -> AlgorithmId {
+        self.algorithm
+    }
+
+    /// Uncompressed encoding of this point
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    fn coordinate_len(algorithm: AlgorithmId) -> Result<usize, Error> {
+        match algorithm {
+            AlgorithmId::EccP256 | AlgorithmId::EccP384 => Ok(algorithm.get_elem_len()),
+            _ => {
+                error!("{:?} is not an elliptic curve algorithm", algorithm);
+                Err(Error::AlgorithmError)
+            }
+        }
+    }
+}
 
-    // don't attempt to reselect in crypt operations to avoid problems with PIN_ALWAYS
-    txn.authenticated_command(raw_in, algorithm, key, false)
+/// Perform ECDH key agreement between the key in the given slot and a
+/// peer's public key, returning the shared secret (the X coordinate of the
+/// shared point).
+///
+/// The peer's key must be on the same curve as the key in the slot.
+pub fn ecdh(yubikey: &mut YubiKey, peer: &EcPoint, key: SlotId) -> Result<Buffer, Error> {
+    decrypt_data(yubikey, peer.as_bytes(), peer.algorithm(), key)
 }
 
 /// Decrypt data using a PIV key
@@ -867,8 +2066,160 @@ pub fn decrypt_data(
     algorithm: AlgorithmId,
     key: SlotId,
 ) -> Result<Buffer, Error> {
-    let txn = yubikey.begin_transaction()?;
+    let started = Instant::now();
 
-    // don't attempt to reselect in crypt operations to avoid problems with PIN_ALWAYS
-    txn.authenticated_command(input, algorithm, key, true)
+    let output = {
+        let txn = yubikey.begin_transaction()?;
+
+        // don't attempt to reselect in crypt operations to avoid problems with PIN_ALWAYS
+        txn.authenticated_command(input, algorithm, key, true)?
+    };
+
+    yubikey.note_key_operation(key, started);
+    Ok(output)
+}
+
+/// Decrypt an RSAES-PKCS1-v1_5 (RFC 8017, section 7.2) ciphertext with the
+/// RSA key in the given slot.
+///
+/// The ciphertext must be the size of the modulus, otherwise
+/// [`Error::SizeError`] is returned. The YubiKey only performs the raw RSA
+/// operation: the padding is checked and removed on the host, and
+/// [`Error::ParseError`] is returned if it is invalid. In particular,
+/// "decrypting" a signature block fails this way.
+///
+/// NOTE: revealing whether PKCS#1 v1.5 padding was valid to an attacker
+/// enables Bleichenbacher's attack. Prefer [`decrypt_rsa_oaep`] for new
+/// protocols.
+pub fn decrypt_rsa(
+    yubikey: &mut YubiKey,
+    slot: SlotId,
+    algorithm: AlgorithmId,
+    ciphertext: &[u8],
+) -> Result<Buffer, Error> {
+    let key_len = rsa_key_len(algorithm)?;
+
+    if ciphertext.len() != key_len {
+        error!(
+            "{:?} ciphertext must be {} bytes (got {})",
+            algorithm,
+            key_len,
+            ciphertext.len()
+        );
+        return Err(Error::SizeError);
+    }
+
+    let encoded = decrypt_data(yubikey, ciphertext, algorithm, slot)?;
+
+    if encoded.len() != key_len {
+        error!("unexpected RSA decryption result length: {}", encoded.len());
+        return Err(Error::ParseError);
+    }
+
+    pkcs1v15_decode(&encoded)
+}
+
+/// EME-PKCS1-v1_5 decoding (RFC 8017, section 7.2.2, step 3), without
+/// revealing through timing which check failed.
+fn pkcs1v15_decode(encoded: &[u8]) -> Result<Buffer, Error> {
+    // EM = 0x00 || 0x02 || PS (at least 8 non-zero bytes) || 0x00 || M
+    let mut valid = encoded[0].ct_eq(&0) & encoded[1].ct_eq(&2);
+    let mut looking = Choice::from(1);
+    let mut separator = 0u32;
+
+    for (i, byte) in encoded[2..].iter().enumerate() {
+        let is_zero = byte.ct_eq(&0);
+        separator.conditional_assign(&(i as u32), looking & is_zero);
+        looking &= !is_zero;
+    }
+
+    valid &= !looking & !separator.ct_lt(&8);
+
+    if valid.unwrap_u8() != 1 {
+        error!("RSA PKCS#1 v1.5 decoding failed");
+        return Err(Error::ParseError);
+    }
+
+    Ok(Buffer::new(encoded[2 + separator as usize + 1..].to_vec()))
+}
+
+/// Decrypt an RSAES-OAEP (RFC 8017, section 7.1) ciphertext with the RSA key
+/// in the given slot.
+///
+/// The YubiKey only performs the raw RSA operation: the OAEP padding is
+/// checked and removed on the host. `hash` is used both for hashing the label
+/// and for MGF1. `label` is the optional associated label, which defaults to
+/// the empty label used by nearly all protocols.
+///
+/// Returns [`Error::ParseError`] if the padding is invalid, including when
+/// the label doesn't match.
+pub fn decrypt_rsa_oaep(
+    yubikey: &mut YubiKey,
+    slot: SlotId,
+    algorithm: AlgorithmId,
+    ciphertext: &[u8],
+    hash: HashAlgorithm,
+    label: Option<&[u8]>,
+) -> Result<Buffer, Error> {
+    if !matches!(algorithm, AlgorithmId::Rsa1024 | AlgorithmId::Rsa2048) {
+        error!("RSA-OAEP requires an RSA key (got {:?})", algorithm);
+        return Err(Error::AlgorithmError);
+    }
+
+    let encoded = decrypt_data(yubikey, ciphertext, algorithm, slot)?;
+    oaep_decode(&encoded, hash, label.unwrap_or(&[]))
+}
+
+/// EME-OAEP decoding (RFC 8017, section 7.1.2, step 3), without revealing
+/// through timing which check failed.
+fn oaep_decode(encoded: &[u8], hash: HashAlgorithm, label: &[u8]) -> Result<Buffer, Error> {
+    let h_len = hash.output_size();
+
+    if encoded.len() < 2 * h_len + 2 {
+        error!("RSA-OAEP decoding failed");
+        return Err(Error::ParseError);
+    }
+
+    let (masked_seed, masked_db) = encoded[1..].split_at(h_len);
+
+    let mut seed = Zeroizing::new(masked_seed.to_vec());
+    mgf1_xor(hash, masked_db, &mut seed);
+
+    let mut db = Zeroizing::new(masked_db.to_vec());
+    mgf1_xor(hash, &seed, &mut db);
+
+    // DB = lHash || PS (zero bytes) || 0x01 || M
+    let mut valid = encoded[0].ct_eq(&0) & db[..h_len].ct_eq(&hash.digest(label)[..]);
+    let mut looking = Choice::from(1);
+    let mut separator = 0u32;
+
+    for (i, byte) in db[h_len..].iter().enumerate() {
+        let is_one = byte.ct_eq(&1);
+        separator.conditional_assign(&(i as u32), looking & is_one);
+        valid &= !looking | is_one | byte.ct_eq(&0);
+        looking &= !is_one;
+    }
+
+    if (valid & !looking).unwrap_u8() != 1 {
+        error!("RSA-OAEP decoding failed");
+        return Err(Error::ParseError);
+    }
+
+    Ok(Buffer::new(db[h_len + separator as usize + 1..].to_vec()))
+}
+
+/// XOR the MGF1 (RFC 8017, appendix B.2.1) mask generated from `seed` into
+/// `out`
+fn mgf1_xor(hash: HashAlgorithm, seed: &[u8], out: &mut [u8]) {
+    let mut input = Zeroizing::new(Vec::with_capacity(seed.len() + 4));
+
+    for (counter, chunk) in out.chunks_mut(hash.output_size()).enumerate() {
+        input.clear();
+        input.extend_from_slice(seed);
+        input.extend_from_slice(&(counter as u32).to_be_bytes());
+
+        for (byte, mask) in chunk.iter_mut().zip(hash.digest(&input).iter()) {
+            *byte ^= mask;
+        }
+    }
 }
@@ -136,23 +136,36 @@
 )]
 
 mod apdu;
+pub mod biometrics;
 pub mod cccid;
 pub mod certificate;
 pub mod chuid;
 pub mod config;
+pub mod discovery;
 pub mod error;
+mod keepalive;
 pub mod key;
 mod metadata;
 pub mod mgm;
+pub mod middleware;
 #[cfg(feature = "untested")]
 pub mod mscmap;
 #[cfg(feature = "untested")]
 pub mod msroots;
+mod pem;
+#[cfg(feature = "pkcs11")]
+pub mod pkcs11;
+#[cfg(feature = "pkcs8")]
+mod pkcs8;
 pub mod policy;
 pub mod readers;
 mod serialization;
 pub mod settings;
+pub mod snapshot;
+#[cfg(feature = "rustls")]
+pub mod tls;
 mod transaction;
+pub mod transport;
 pub mod yubikey;
 
 pub use self::{
@@ -174,6 +187,9 @@ pub(crate) const CB_BUF_MAX: usize = 3072;
 
 /// YubiKey max object size
 pub(crate) const CB_OBJ_MAX: usize = CB_BUF_MAX - 9;
+
+/// YubiKey NEO max object size, whose buffer is only 2048 bytes
+pub(crate) const CB_OBJ_MAX_NEO: usize = 2048 - 9;
 pub(crate) const CB_OBJ_TAG_MIN: usize = 2; // 1 byte tag + 1 byte len
 
 pub(crate) const CB_OBJ_TAG_MAX: usize = CB_OBJ_TAG_MIN + 2; // 1 byte tag + 3 bytes len
@@ -184,9 +200,15 @@ pub(crate) const TAG_ADMIN_TIMESTAMP: u8 = 0x83;
 pub(crate) const TAG_PROTECTED_FLAGS_1: u8 = 0x81;
 pub(crate) const TAG_PROTECTED_MGM: u8 = 0x89;
 
-/// PIV Applet ID
+/// PIV Applet ID (truncated to the NIST RID, as accepted by most cards)
 pub(crate) const PIV_AID: [u8; 5] = [0xa0, 0x00, 0x00, 0x03, 0x08];
 
+/// Full PIV Applet ID (NIST RID + PIX including the version).
+/// Some cards only respond to this form of the AID.
+pub(crate) const PIV_AID_FULL: [u8; 11] = [
+    0xa0, 0x00, 0x00, 0x03, 0x08, 0x00, 0x00, 0x10, 0x00, 0x01, 0x00,
+];
+
 /// MGMT Applet ID.
 /// <https://developers.yubico.com/PIV/Introduction/Admin_access.html>
 
@@ -30,7 +30,12 @@
 // (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
 // OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
-use crate::{TAG_ADMIN_FLAGS_1, TAG_ADMIN_SALT, TAG_PROTECTED_MGM, YubiKey, error::Error, metadata::{AdminData, ProtectedData}};
+use crate::{
+    error::Error,
+    metadata::{AdminData, ProtectedData},
+    transaction::Transaction,
+    YubiKey, TAG_ADMIN_FLAGS_1, TAG_ADMIN_SALT, TAG_PROTECTED_MGM,
+};
 use getrandom::getrandom;
 use hmac::Hmac;
 use log::{error, info};
@@ -133,7 +138,7 @@ impl MgmKey {
     }
 
     /// Get derived management key (MGM)
-   ///#[cfg(feature = "untested")]
+    ///#[cfg(feature = "untested")]
     pub fn get_derived(yubikey: &mut YubiKey, pin: &[u8]) -> Result<Self, Error> {
         let txn = yubikey.begin_transaction()?;
 
@@ -173,28 +178,7 @@ impl MgmKey {
     //#[cfg(feature = "untested")]
     pub fn get_protected(yubikey: &mut YubiKey) -> Result<Self, Error> {
         let txn = yubikey.begin_transaction()?;
-
-        let protected_data = ProtectedData::read(&txn).map_err(|e| {
-            error!("could not read protected data (err: {:?})", e);
-            e
-        })?;
-
-        let item = protected_data.get_item(TAG_PROTECTED_MGM).map_err(|e| {
-            error!("could not read protected MGM from metadata (err: {:?})", e);
-            e
-        })?;
-
-        if item.len() != DES_LEN_3DES {
-            error!(
-                "protected data contains MGM, but is the wrong size: {} (expected {})",
-                item.len(),
-                DES_LEN_3DES
-            );
-
-            return Err(Error::AuthenticationError);
-        }
-
-        MgmKey::from_bytes(item)
+        MgmKey::read_protected(&txn)
     }
 
     /// Resets the management key for the given YubiKey to the default value.
@@ -273,7 +257,39 @@ impl MgmKey {
     //#[cfg(feature = "untested")]
     pub fn set_protected(&self, yubikey: &mut YubiKey) -> Result<(), Error> {
         let txn = yubikey.begin_transaction()?;
+        self.write_protected(&txn)
+    }
+
+    /// Read the management key from PIN-protected data.
+    ///
+    /// The PIN must already be verified within the given transaction.
+    pub(crate) fn read_protected(txn: &Transaction<'_>) -> Result<Self, Error> {
+        let protected_data = ProtectedData::read(txn).map_err(|e| {
+            error!("could not read protected data (err: {:?})", e);
+            e
+        })?;
+
+        let item = protected_data.get_item(TAG_PROTECTED_MGM).map_err(|e| {
+            error!("could not read protected MGM from metadata (err: {:?})", e);
+            e
+        })?;
 
+        if item.len() != DES_LEN_3DES {
+            error!(
+                "protected data contains MGM, but is the wrong size: {} (expected {})",
+                item.len(),
+                DES_LEN_3DES
+            );
+
+            return Err(Error::AuthenticationError);
+        }
+
+        MgmKey::from_bytes(item)
+    }
+
+    /// Set this management key on the device and store it in PIN-protected
+    /// data, flagging it as protected in the admin data.
+    pub(crate) fn write_protected(&self, txn: &Transaction<'_>) -> Result<(), Error> {
         txn.set_mgm_key(self, false).map_err(|e| {
             // log a warning, since the device mgm key is corrupt or we're in
             // a state where we can't set the mgm key
@@ -284,14 +300,22 @@ impl MgmKey {
         // after this point, we've set the mgm key, so the function should
         // succeed, regardless of being able to set the metadata
 
-        // Fetch the current protected data, or start a blank metadata blob.
-        let mut protected_data = ProtectedData::read(&txn).unwrap_or_default();
+        // Fetch the current protected data, or start a blank metadata blob if
+        // there is none yet.
+        let mut protected_data = match ProtectedData::read(txn) {
+            Ok(protected_data) => protected_data,
+            Err(Error::NotFound) => ProtectedData::default(),
+            Err(e) => {
+                error!("could not read protected data, err = {:?}", e);
+                return Err(e);
+            }
+        };
 
         // Set the new mgm key in protected data.
         if let Err(e) = protected_data.set_item(TAG_PROTECTED_MGM, self.as_ref()) {
             error!("could not set protected mgm item, err = {:?}", e);
         } else {
-            protected_data.write(&txn).map_err(|e| {
+            protected_data.write(txn).map_err(|e| {
                 error!("could not write protected data, err = {:?}", e);
                 e
             })?;
@@ -301,7 +325,7 @@ impl MgmKey {
 
         let mut flags_1 = [0u8; 1];
 
-        let mut admin_data = if let Ok(mut admin_data) = AdminData::read(&txn) {
+        let mut admin_data = if let Ok(mut admin_data) = AdminData::read(txn) {
             if let Ok(item) = admin_data.get_item(TAG_ADMIN_FLAGS_1) {
                 if item.len() == flags_1.len() {
                     flags_1.copy_from_slice(item);
@@ -331,13 +355,27 @@ impl MgmKey {
 
         if let Err(e) = admin_data.set_item(TAG_ADMIN_FLAGS_1, &flags_1) {
             error!("could not set admin flags item, err = {}", e);
-        } else if let Err(e) = admin_data.write(&txn) {
+        } else if let Err(e) = admin_data.write(txn) {
             error!("could not write admin data, err = {}", e);
         }
 
         Ok(())
     }
 
+    /// Key Check Value (KCV) of this key: the first 3 bytes of an all-zero
+    /// block encrypted with it.
+    ///
+    /// The KCV reveals nothing useful about the key, so it can be logged or
+    /// compared to confirm that two devices share a management key.
+    ///
+    /// This is always the 3DES KCV, as [`MgmKey`] only holds 3DES keys. AES
+    /// management keys (YubiKey 5.4+) can't be represented, so there is no
+    /// AES KCV either.
+    pub fn kcv(&self) -> [u8; 3] {
+        let block = self.encrypt(&[0u8; DES_LEN_DES]);
+        [block[0], block[1], block[2]]
+    }
+
     /// Encrypt with 3DES key
     #[allow(clippy::trivially_copy_pass_by_ref)]
     pub(crate) fn encrypt(&self, input: &[u8; DES_LEN_DES]) -> [u8; DES_LEN_DES] {
@@ -0,0 +1,74 @@
+//! Hooks around every APDU exchanged with the card, e.g. for metrics,
+//! tracing or assertions in tests.
+//!
+//! Middleware is added to a [`YubiKey`][`crate::YubiKey`] handle with
+//! [`YubiKey::add_middleware`][`crate::YubiKey::add_middleware`].
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Hooks called around every APDU exchanged with the card.
+///
+/// Several middlewares can be layered on a handle: `before_transmit` hooks
+/// are called in the order the middlewares were added, and
+/// `after_transmit` hooks in the reverse order.
+///
+/// NOTE: the hooks see the raw bytes exchanged with the card, which include
+/// secrets such as PINs, management keys and imported private keys.
+pub trait Middleware: Send {
+    /// Called with each serialized command APDU before it is sent.
+    fn before_transmit(&self, _apdu: &[u8]) {}
+
+    /// Called with each response APDU, including the trailing status words.
+    /// Not called if the exchange fails.
+    fn after_transmit(&self, _response: &[u8]) {}
+}
+
+/// Middleware which does nothing.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NoOp;
+
+impl Middleware for NoOp {}
+
+/// Middleware reporting how long the card took to respond to each command.
+///
+/// The callback is called with the instruction byte of the command and the
+/// time until its response was received.
+pub struct Timing<F>
+where
+    F: Fn(u8, Duration) + Send,
+{
+    callback: F,
+    started: Mutex<Option<(u8, Instant)>>,
+}
+
+impl<F> Timing<F>
+where
+    F: Fn(u8, Duration) + Send,
+{
+    /// Create timing middleware reporting to the given callback
+    pub fn new(callback: F) -> Self {
+        Timing {
+            callback,
+            started: Mutex::new(None),
+        }
+    }
+}
+
+impl<F> Middleware for Timing<F>
+where
+    F: Fn(u8, Duration) + Send,
+{
+    fn before_transmit(&self, apdu: &[u8]) {
+        let ins = apdu.get(1).copied().unwrap_or_default();
+        *self.started.lock().unwrap() = Some((ins, Instant::now()));
+    }
+
+    fn after_transmit(&self, _response: &[u8]) {
+        if let Some((ins, started)) = self.started.lock().unwrap().take() {
+            (self.callback)(ins, started.elapsed());
+        }
+    }
+}
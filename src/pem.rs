@@ -0,0 +1,94 @@
+//! Minimal PEM (RFC 7468) decoding
+
+use crate::error::Error;
+use log::error;
+use std::str;
+use subtle_encoding::base64;
+
+/// Label of PEM-encoded X.509 certificates
+pub(crate) const CERTIFICATE: &str = "CERTIFICATE";
+
+/// Label of PEM-encoded PKCS#8 private keys
+#[cfg(feature = "pkcs8")]
+pub(crate) const PRIVATE_KEY: &str = "PRIVATE KEY";
+
+/// Label of PEM-encoded encrypted PKCS#8 private keys
+#[cfg(feature = "pkcs8")]
+pub(crate) const ENCRYPTED_PRIVATE_KEY: &str = "ENCRYPTED PRIVATE KEY";
+
+/// Label of PEM-encoded SEC1 EC private keys
+#[cfg(feature = "pkcs8")]
+pub(crate) const EC_PRIVATE_KEY: &str = "EC PRIVATE KEY";
+
+/// Label of PEM-encoded PKCS#1 RSA private keys
+#[cfg(feature = "pkcs8")]
+pub(crate) const RSA_PRIVATE_KEY: &str = "RSA PRIVATE KEY";
+
+/// Does `input` contain a PEM block with the given label?
+pub(crate) fn is_pem(input: &[u8], label: &str) -> bool {
+    let begin = format!("-----BEGIN {}-----", label);
+
+    input
+        .windows(begin.len())
+        .any(|window| window == begin.as_bytes())
+}
+
+/// Does `input` contain an RFC 1421 style encrypted PEM block, as written by
+/// OpenSSL for password protected "traditional" private keys?
+#[cfg(feature = "pkcs8")]
+pub(crate) fn is_encrypted(input: &[u8]) -> bool {
+    let proc_type = b"Proc-Type: 4,ENCRYPTED";
+
+    input
+        .windows(proc_type.len())
+        .any(|window| window == proc_type)
+}
+
+/// Decode every PEM block with the given label in `input`, in order.
+///
+/// Text outside the blocks (e.g. the "Bag Attributes" OpenSSL writes) and
+/// RFC 1421 style headers inside them are ignored. Returns
+/// [`Error::ParseError`] if a block is unterminated or its base64 body is
+/// malformed, or if there are no blocks at all.
+pub(crate) fn decode(input: &[u8], label: &str) -> Result<Vec<Vec<u8>>, Error> {
+    let input = str::from_utf8(input).map_err(|_| {
+        error!("PEM input is not valid UTF-8");
+        Error::ParseError
+    })?;
+
+    let begin = format!("-----BEGIN {}-----", label);
+    let end = format!("-----END {}-----", label);
+
+    let mut blocks = vec![];
+    let mut body: Option<String> = None;
+
+    for line in input.lines().map(str::trim) {
+        match body.as_mut() {
+            None if line == begin => body = Some(String::new()),
+            None => (),
+            Some(b64) if line == end => {
+                let der = base64::decode(b64.as_bytes()).map_err(|_| {
+                    error!("malformed base64 in PEM {} block", label);
+                    Error::ParseError
+                })?;
+
+                blocks.push(der);
+                body = None;
+            }
+            Some(_) if line.contains(':') => (),
+            Some(b64) => b64.push_str(line),
+        }
+    }
+
+    if body.is_some() {
+        error!("unterminated PEM {} block", label);
+        return Err(Error::ParseError);
+    }
+
+    if blocks.is_empty() {
+        error!("no PEM {} blocks found", label);
+        return Err(Error::ParseError);
+    }
+
+    Ok(blocks)
+}
@@ -0,0 +1,441 @@
+//! Building blocks for PKCS#11 modules backed by a YubiKey.
+//!
+//! This is not a PKCS#11 module itself: it maps PIV slots, keys and their
+//! operations onto the PKCS#11 object model (object handles, labels, IDs,
+//! key types and mechanisms), so that a crate implementing the PKCS#11 C API
+//! can wrap it. Labels and IDs follow Yubico's libykcs11, so objects keep
+//! their identity when switching between the two.
+//!
+//! Each [`KeyObject`] backs a private key object, which performs the
+//! operations, and a public key object. Certificate objects are left to the
+//! module, as certificates are read with
+//! [`Certificate::read`][`crate::certificate::Certificate::read`].
+
+use crate::{
+    certificate::PublicKeyInfo,
+    error::Error,
+    key::{self, AlgorithmId, HashAlgorithm, SlotId, SLOTS},
+    serialization::Tlv,
+    yubikey::YubiKey,
+    Buffer,
+};
+use log::error;
+use std::convert::TryFrom;
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_SEQUENCE: u8 = 0x30;
+
+/// PKCS#11 object class (`CKA_CLASS`)
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ObjectClass {
+    /// `CKO_CERTIFICATE`
+    Certificate,
+
+    /// `CKO_PUBLIC_KEY`
+    PublicKey,
+
+    /// `CKO_PRIVATE_KEY`
+    PrivateKey,
+}
+
+impl ObjectClass {
+    /// `CK_OBJECT_CLASS` value
+    pub fn code(self) -> u64 {
+        match self {
+            ObjectClass::Certificate => 0x01,
+            ObjectClass::PublicKey => 0x02,
+            ObjectClass::PrivateKey => 0x03,
+        }
+    }
+}
+
+impl TryFrom<u64> for ObjectClass {
+    type Error = Error;
+
+    fn try_from(value: u64) -> Result<Self, Error> {
+        match value {
+            0x01 => Ok(ObjectClass::Certificate),
+            0x02 => Ok(ObjectClass::PublicKey),
+            0x03 => Ok(ObjectClass::PrivateKey),
+            _ => Err(Error::InvalidObject),
+        }
+    }
+}
+
+/// PKCS#11 key type (`CKA_KEY_TYPE`)
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum KeyType {
+    /// `CKK_RSA`
+    Rsa,
+
+    /// `CKK_EC`
+    Ec,
+}
+
+impl KeyType {
+    /// `CK_KEY_TYPE` value
+    pub fn code(self) -> u64 {
+        match self {
+            KeyType::Rsa => 0x00,
+            KeyType::Ec => 0x03,
+        }
+    }
+}
+
+impl From<AlgorithmId> for KeyType {
+    fn from(algorithm: AlgorithmId) -> KeyType {
+        match algorithm {
+            AlgorithmId::Rsa1024 | AlgorithmId::Rsa2048 => KeyType::Rsa,
+            AlgorithmId::EccP256 | AlgorithmId::EccP384 => KeyType::Ec,
+        }
+    }
+}
+
+/// PKCS#11 mechanisms supported by keys stored in PIV slots
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Mechanism {
+    /// `CKM_RSA_PKCS`: PKCS#1 v1.5 signature of a `DigestInfo` supplied by
+    /// the caller, or PKCS#1 v1.5 decryption
+    RsaPkcs,
+
+    /// `CKM_SHA256_RSA_PKCS`
+    Sha256RsaPkcs,
+
+    /// `CKM_SHA384_RSA_PKCS`
+    Sha384RsaPkcs,
+
+    /// `CKM_SHA512_RSA_PKCS`
+    Sha512RsaPkcs,
+
+    /// `CKM_ECDSA`: ECDSA signature of a digest supplied by the caller
+    Ecdsa,
+
+    /// `CKM_ECDSA_SHA256`
+    EcdsaSha256,
+
+    /// `CKM_ECDSA_SHA384`
+    EcdsaSha384,
+
+    /// `CKM_ECDSA_SHA512`
+    EcdsaSha512,
+}
+
+/// Mechanisms supported by RSA keys
+const RSA_MECHANISMS: &[Mechanism] = &[
+    Mechanism::RsaPkcs,
+    Mechanism::Sha256RsaPkcs,
+    Mechanism::Sha384RsaPkcs,
+    Mechanism::Sha512RsaPkcs,
+];
+
+/// Mechanisms supported by ECC keys
+const EC_MECHANISMS: &[Mechanism] = &[
+    Mechanism::Ecdsa,
+    Mechanism::EcdsaSha256,
+    Mechanism::EcdsaSha384,
+    Mechanism::EcdsaSha512,
+];
+
+impl Mechanism {
+    /// `CK_MECHANISM_TYPE` value
+    pub fn code(self) -> u64 {
+        match self {
+            Mechanism::RsaPkcs => 0x0001,
+            Mechanism::Sha256RsaPkcs => 0x0040,
+            Mechanism::Sha384RsaPkcs => 0x0041,
+            Mechanism::Sha512RsaPkcs => 0x0042,
+            Mechanism::Ecdsa => 0x1041,
+            Mechanism::EcdsaSha256 => 0x1044,
+            Mechanism::EcdsaSha384 => 0x1045,
+            Mechanism::EcdsaSha512 => 0x1046,
+        }
+    }
+
+    /// Mechanisms supported by keys of the given algorithm
+    pub fn for_algorithm(algorithm: AlgorithmId) -> &'static [Mechanism] {
+        match KeyType::from(algorithm) {
+            KeyType::Rsa => RSA_MECHANISMS,
+            KeyType::Ec => EC_MECHANISMS,
+        }
+    }
+
+    /// Type of the keys this mechanism is used with
+    pub fn key_type(self) -> KeyType {
+        if RSA_MECHANISMS.contains(&self) {
+            KeyType::Rsa
+        } else {
+            KeyType::Ec
+        }
+    }
+
+    /// Hash computed over the input before signing, if any
+    fn hash(self) -> Option<HashAlgorithm> {
+        match self {
+            Mechanism::RsaPkcs | Mechanism::Ecdsa => None,
+            Mechanism::Sha256RsaPkcs | Mechanism::EcdsaSha256 => Some(HashAlgorithm::Sha256),
+            Mechanism::Sha384RsaPkcs | Mechanism::EcdsaSha384 => Some(HashAlgorithm::Sha384),
+            Mechanism::Sha512RsaPkcs | Mechanism::EcdsaSha512 => Some(HashAlgorithm::Sha512),
+        }
+    }
+}
+
+/// PKCS#11 object handle (`CK_OBJECT_HANDLE`) of an object of a slot.
+///
+/// Handles are derived from the slot and object class, so they are stable
+/// across sessions and devices, and never `CK_INVALID_HANDLE` (0).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ObjectHandle {
+    slot: SlotId,
+    class: ObjectClass,
+}
+
+impl ObjectHandle {
+    /// Handle of the object of the given class for `slot`
+    pub fn new(slot: SlotId, class: ObjectClass) -> Self {
+        ObjectHandle { slot, class }
+    }
+
+    /// Slot the object belongs to
+    pub fn slot(self) -> SlotId {
+        self.slot
+    }
+
+    /// Class of the object
+    pub fn class(self) -> ObjectClass {
+        self.class
+    }
+}
+
+impl From<ObjectHandle> for u64 {
+    fn from(handle: ObjectHandle) -> u64 {
+        (u64::from(u8::from(handle.slot)) << 8) | handle.class.code()
+    }
+}
+
+impl TryFrom<u64> for ObjectHandle {
+    type Error = Error;
+
+    fn try_from(value: u64) -> Result<Self, Error> {
+        if value > 0xffff {
+            return Err(Error::InvalidObject);
+        }
+
+        Ok(ObjectHandle {
+            slot: SlotId::try_from((value >> 8) as u8)?,
+            class: ObjectClass::try_from(value & 0xff)?,
+        })
+    }
+}
+
+/// A key stored in a slot, with the attributes of the PKCS#11 private and
+/// public key objects it backs, and their operations.
+#[derive(Clone, Debug)]
+pub struct KeyObject {
+    slot: SlotId,
+    algorithm: AlgorithmId,
+    public_key: PublicKeyInfo,
+}
+
+impl KeyObject {
+    /// List the keys stored in the YubiKey.
+    ///
+    /// Like [`DeviceSnapshot::capture`][`crate::snapshot::DeviceSnapshot::capture`],
+    /// this reads public keys from the slots' certificates on devices without
+    /// GET METADATA support (i.e. prior to YubiKey 5.3), so keys without a
+    /// certificate are only listed on newer devices.
+    pub fn list(yubikey: &mut YubiKey) -> Result<Vec<Self>, Error> {
+        let mut keys = vec![];
+
+        for &slot in SLOTS.iter() {
+            match key::get_public_key(yubikey, slot) {
+                Ok(public_key) => keys.push(KeyObject {
+                    slot,
+                    algorithm: public_key.algorithm(),
+                    public_key,
+                }),
+                Err(Error::NotFound) => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(keys)
+    }
+
+    /// Slot the key is stored in
+    pub fn slot(&self) -> SlotId {
+        self.slot
+    }
+
+    /// Handle of the object of the given class for this key's slot
+    pub fn handle(&self, class: ObjectClass) -> ObjectHandle {
+        ObjectHandle::new(self.slot, class)
+    }
+
+    /// `CKA_LABEL` of the object of the given class, e.g.
+    /// "Private key for PIV Authentication"
+    pub fn label(&self, class: ObjectClass) -> String {
+        let object = match class {
+            ObjectClass::Certificate => "X.509 Certificate",
+            ObjectClass::PublicKey => "Public key",
+            ObjectClass::PrivateKey => "Private key",
+        };
+
+        let slot = match self.slot {
+            SlotId::Authentication => String::from("PIV Authentication"),
+            SlotId::Signature => String::from("Digital Signature"),
+            SlotId::KeyManagement => String::from("Key Management"),
+            SlotId::CardAuthentication => String::from("Card Authentication"),
+            SlotId::Retired(retired) => format!("Retired Key {}", u8::from(retired) - 0x81),
+            SlotId::Attestation => String::from("PIV Attestation"),
+            SlotId::CardManagement => String::from("Card Management"),
+        };
+
+        format!("{} for {}", object, slot)
+    }
+
+    /// `CKA_ID` shared by the objects of this key's slot: 1 to 4 for the
+    /// authentication, signature, key management and card authentication
+    /// slots, followed by the retired slots
+    pub fn id(&self) -> u8 {
+        match self.slot {
+            SlotId::Authentication => 1,
+            SlotId::Signature => 2,
+            SlotId::KeyManagement => 3,
+            SlotId::CardAuthentication => 4,
+            SlotId::Retired(retired) => u8::from(retired) - 0x82 + 5,
+            SlotId::Attestation => 25,
+            // The management key is symmetric and never listed
+            SlotId::CardManagement => 0,
+        }
+    }
+
+    /// Algorithm of the key
+    pub fn algorithm(&self) -> AlgorithmId {
+        self.algorithm
+    }
+
+    /// `CKA_KEY_TYPE` of the key
+    pub fn key_type(&self) -> KeyType {
+        self.algorithm.into()
+    }
+
+    /// Public key, for the public key object's attributes
+    pub fn public_key(&self) -> &PublicKeyInfo {
+        &self.public_key
+    }
+
+    /// Mechanisms the key can be used with
+    pub fn mechanisms(&self) -> &'static [Mechanism] {
+        Mechanism::for_algorithm(self.algorithm)
+    }
+
+    /// `CKA_SIGN`: every PIV key can sign
+    pub fn can_sign(&self) -> bool {
+        true
+    }
+
+    /// `CKA_DECRYPT`: only RSA keys can decrypt
+    pub fn can_decrypt(&self) -> bool {
+        self.key_type() == KeyType::Rsa
+    }
+
+    /// Sign `data` with the given mechanism (`C_Sign`).
+    ///
+    /// ECDSA signatures are returned as `r || s`, each left-padded to the
+    /// size of the curve, as PKCS#11 specifies. Returns
+    /// [`Error::AlgorithmError`] if the mechanism doesn't match the key.
+    pub fn sign(
+        &self,
+        yubikey: &mut YubiKey,
+        mechanism: Mechanism,
+        data: &[u8],
+    ) -> Result<Buffer, Error> {
+        self.check_mechanism(mechanism)?;
+
+        let signature = match mechanism.hash() {
+            Some(hash) => {
+                let digest = hash.digest(data);
+                key::sign_prehashed(yubikey, self.slot, self.algorithm, &digest, hash)?
+            }
+            None if mechanism == Mechanism::RsaPkcs => {
+                key::sign_pkcs1v15(yubikey, self.slot, self.algorithm, &[], data)?
+            }
+            None => key::sign_ecdsa(yubikey, data, self.algorithm, self.slot)?,
+        };
+
+        match self.key_type() {
+            KeyType::Rsa => Ok(signature),
+            KeyType::Ec => ecdsa_signature_raw(&signature, self.algorithm),
+        }
+    }
+
+    /// Decrypt `ciphertext` with the given mechanism (`C_Decrypt`).
+    ///
+    /// Only [`Mechanism::RsaPkcs`] with RSA keys is supported; anything else
+    /// returns [`Error::AlgorithmError`].
+    pub fn decrypt(
+        &self,
+        yubikey: &mut YubiKey,
+        mechanism: Mechanism,
+        ciphertext: &[u8],
+    ) -> Result<Buffer, Error> {
+        self.check_mechanism(mechanism)?;
+
+        if mechanism != Mechanism::RsaPkcs {
+            error!("{:?} can't be used for decryption", mechanism);
+            return Err(Error::AlgorithmError);
+        }
+
+        key::decrypt_rsa(yubikey, self.slot, self.algorithm, ciphertext)
+    }
+
+    /// Check that `mechanism` can be used with this key
+    fn check_mechanism(&self, mechanism: Mechanism) -> Result<(), Error> {
+        if mechanism.key_type() != self.key_type() {
+            error!(
+                "{:?} can't be used with the {:?} key in slot {:?}",
+                mechanism, self.algorithm, self.slot
+            );
+            return Err(Error::AlgorithmError);
+        }
+
+        Ok(())
+    }
+}
+
+/// Convert a DER-encoded `ECDSA-Sig-Value` into the `r || s` encoding used
+/// by PKCS#11, each integer left-padded to the size of the curve.
+fn ecdsa_signature_raw(der: &[u8], algorithm: AlgorithmId) -> Result<Buffer, Error> {
+    let len = algorithm.get_elem_len();
+    let (_, sequence) = Tlv::parse(der)?;
+
+    if sequence.tag != TAG_SEQUENCE {
+        error!("ECDSA signature isn't a DER sequence");
+        return Err(Error::ParseError);
+    }
+
+    let mut raw = Buffer::new(vec![0u8; 2 * len]);
+    let mut fields = sequence.value;
+
+    for half in raw.chunks_mut(len) {
+        let (remaining, integer) = Tlv::parse(fields)?;
+        fields = remaining;
+
+        // Strip the sign byte (and any other leading zeroes)
+        let start = integer
+            .value
+            .iter()
+            .position(|&b| b != 0)
+            .unwrap_or(integer.value.len());
+        let value = &integer.value[start..];
+
+        if integer.tag != TAG_INTEGER || value.len() > len {
+            error!("malformed ECDSA signature");
+            return Err(Error::ParseError);
+        }
+
+        half[len - value.len()..].copy_from_slice(value);
+    }
+
+    Ok(raw)
+}
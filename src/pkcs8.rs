@@ -0,0 +1,204 @@
+//! Private key parsing for import: PKCS#8 (RFC 5208) `PrivateKeyInfo`, and
+//! the algorithm specific PKCS#1 (RFC 8017) `RSAPrivateKey` and SEC1
+//! (RFC 5915) `ECPrivateKey` structures it wraps.
+//!
+//! RSA and P-256 keys are parsed by the `rsa` and `p256` crates. The `p384`
+//! crate can't parse keys, so only the scalar is extracted from P-384 (and
+//! bare SEC1) keys.
+
+use crate::{
+    error::Error,
+    key::{AlgorithmId, PrivateKeyData, RsaKeyData, RSA_EXPONENT_F4},
+};
+use der_parser::{der::DerObject, parse_der};
+use log::error;
+use num_bigint_dig::{traits::ModInverse, BigUint, ToBigUint};
+use num_traits::One;
+use p256::pkcs8::FromPrivateKey;
+use rsa::{PublicKeyParts, RSAPrivateKey};
+use zeroize::Zeroizing;
+
+/// rsaEncryption
+const OID_RSA_ENCRYPTION: &str = "1.2.840.113549.1.1.1";
+
+/// id-ecPublicKey
+const OID_EC_PUBLIC_KEY: &str = "1.2.840.10045.2.1";
+
+/// secp256r1
+const OID_NIST_P256: &str = "1.2.840.10045.3.1.7";
+
+/// secp384r1
+const OID_NIST_P384: &str = "1.3.132.0.34";
+
+/// Parse a DER-encoded PKCS#8 `PrivateKeyInfo`.
+pub(crate) fn parse_pkcs8(der: &[u8]) -> Result<PrivateKeyData, Error> {
+    let info = parse_sequence(der)?;
+    let fields = info.as_sequence().map_err(|_| Error::ParseError)?;
+
+    let algorithm = fields
+        .get(1)
+        .and_then(|algorithm| algorithm.as_sequence().ok())
+        .ok_or(Error::ParseError)?;
+
+    let oid = algorithm
+        .get(0)
+        .and_then(|oid| oid.as_oid_val().ok())
+        .ok_or(Error::ParseError)?;
+
+    match oid.to_string().as_str() {
+        OID_RSA_ENCRYPTION => rsa_key(RSAPrivateKey::from_pkcs8(der).map_err(|e| {
+            error!("invalid RSA private key: {}", e);
+            Error::ParseError
+        })?),
+        OID_EC_PUBLIC_KEY => {
+            let curve = algorithm
+                .get(1)
+                .and_then(|curve| curve.as_oid_val().ok())
+                .ok_or(Error::ParseError)?;
+
+            match curve.to_string().as_str() {
+                OID_NIST_P256 => {
+                    let secret_key = p256::SecretKey::from_pkcs8_der(der).map_err(|_| {
+                        error!("invalid P-256 private key");
+                        Error::ParseError
+                    })?;
+
+                    Ok(PrivateKeyData::Ecc {
+                        algorithm: AlgorithmId::EccP256,
+                        scalar: Zeroizing::new(secret_key.to_bytes().to_vec()),
+                    })
+                }
+                OID_NIST_P384 => {
+                    let private_key = fields
+                        .get(2)
+                        .and_then(|private_key| private_key.as_slice().ok())
+                        .ok_or(Error::ParseError)?;
+
+                    ec_key(AlgorithmId::EccP384, sec1_scalar(private_key)?)
+                }
+                other => {
+                    error!("unsupported elliptic curve (OID {})", other);
+                    Err(Error::AlgorithmError)
+                }
+            }
+        }
+        other => {
+            error!("unsupported private key algorithm (OID {})", other);
+            Err(Error::AlgorithmError)
+        }
+    }
+}
+
+/// Parse a DER-encoded PKCS#1 `RSAPrivateKey`.
+pub(crate) fn parse_pkcs1(der: &[u8]) -> Result<PrivateKeyData, Error> {
+    rsa_key(RSAPrivateKey::from_pkcs1(der).map_err(|e| {
+        error!("invalid RSA private key: {}", e);
+        Error::ParseError
+    })?)
+}
+
+/// Parse a DER-encoded SEC1 `ECPrivateKey`.
+///
+/// The curve is identified by the size of the private key, which RFC 5915
+/// fixes to the size of the curve order.
+pub(crate) fn parse_sec1(der: &[u8]) -> Result<PrivateKeyData, Error> {
+    let scalar = sec1_scalar(der)?;
+
+    let algorithm = match scalar.len() {
+        32 => AlgorithmId::EccP256,
+        48 => AlgorithmId::EccP384,
+        len => {
+            error!("unsupported EC private key size: {} bytes", len);
+            return Err(Error::AlgorithmError);
+        }
+    };
+
+    ec_key(algorithm, scalar)
+}
+
+/// Get the CRT parameters of an RSA key for import.
+fn rsa_key(key: RSAPrivateKey) -> Result<PrivateKeyData, Error> {
+    let algorithm = match key.size() {
+        128 => AlgorithmId::Rsa1024,
+        256 => AlgorithmId::Rsa2048,
+        len => {
+            error!("unsupported RSA key size: {} bits", len * 8);
+            return Err(Error::AlgorithmError);
+        }
+    };
+
+    if key.e() != &BigUint::from(RSA_EXPONENT_F4) {
+        error!("only RSA keys with public exponent 65537 can be imported");
+        return Err(Error::NotSupported);
+    }
+
+    let (p, q) = match key.primes() {
+        [p, q] => (p, q),
+        primes => {
+            error!(
+                "multi-prime RSA keys ({} primes) can't be imported",
+                primes.len()
+            );
+            return Err(Error::NotSupported);
+        }
+    };
+
+    let dp = key.d() % (p - BigUint::one());
+    let dq = key.d() % (q - BigUint::one());
+    let qinv = q
+        .clone()
+        .mod_inverse(p)
+        .and_then(|qinv| qinv.to_biguint())
+        .ok_or(Error::ParseError)?;
+
+    Ok(PrivateKeyData::Rsa {
+        algorithm,
+        key_data: RsaKeyData::from_crt(
+            Zeroizing::new(p.to_bytes_be()),
+            Zeroizing::new(q.to_bytes_be()),
+            Zeroizing::new(dp.to_bytes_be()),
+            Zeroizing::new(dq.to_bytes_be()),
+            Zeroizing::new(qinv.to_bytes_be()),
+        ),
+    })
+}
+
+/// Check the size of an EC private scalar for import.
+fn ec_key(algorithm: AlgorithmId, scalar: &[u8]) -> Result<PrivateKeyData, Error> {
+    if scalar.len() != algorithm.get_elem_len() {
+        error!(
+            "invalid {:?} private key length: {}",
+            algorithm,
+            scalar.len()
+        );
+        return Err(Error::ParseError);
+    }
+
+    Ok(PrivateKeyData::Ecc {
+        algorithm,
+        scalar: Zeroizing::new(scalar.to_vec()),
+    })
+}
+
+/// Get the private key from a DER-encoded SEC1 `ECPrivateKey`.
+fn sec1_scalar(der: &[u8]) -> Result<&[u8], Error> {
+    let key = parse_sequence(der)?;
+
+    key.as_sequence()
+        .ok()
+        .and_then(|fields| fields.get(1))
+        .and_then(|private_key| private_key.as_slice().ok())
+        .ok_or(Error::ParseError)
+}
+
+/// Parse `der`, which must be a single SEQUENCE.
+fn parse_sequence(der: &[u8]) -> Result<DerObject<'_>, Error> {
+    match parse_der(der) {
+        Ok((remaining, object)) if remaining.is_empty() => Ok(object),
+        Ok(_) => {
+            error!("trailing data after private key");
+            Err(Error::ParseError)
+        }
+        Err(_) => Err(Error::ParseError),
+    }
+}
@@ -1,6 +1,7 @@
 //! Enums representing key policies.
 
 use crate::{error::Error, serialization::Tlv};
+use std::convert::TryFrom;
 
 /// Specifies how often the PIN needs to be entered for access to the credential in a
 /// given slot. This policy must be set upon key generation or importation, and cannot be
@@ -34,6 +35,20 @@ impl From<PinPolicy> for u8 {
     }
 }
 
+impl TryFrom<u8> for PinPolicy {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Error> {
+        match value {
+            0 => Ok(PinPolicy::Default),
+            1 => Ok(PinPolicy::Never),
+            2 => Ok(PinPolicy::Once),
+            3 => Ok(PinPolicy::Always),
+            _ => Err(Error::InvalidObject),
+        }
+    }
+}
+
 impl PinPolicy {
     /// Writes the `PinPolicy` in the format the YubiKey expects during key generation or
     /// importation.
@@ -48,6 +63,16 @@ impl PinPolicy {
 /// Specifies under what conditions a physical touch on the metal contact is required, in
 /// addition to the [`PinPolicy`]. This policy must be set upon key generation or
 /// importation, and cannot be changed later.
+///
+/// # Waiting for touch
+///
+/// An operation which requires touch blocks until the contact is touched, or until the
+/// YubiKey gives up after about 15 seconds and fails the operation. It can't be cancelled
+/// early: PC/SC's `SCardCancel` only interrupts waits for reader state changes, not a
+/// command which is already being processed by the card, and resetting or disconnecting
+/// the card from another thread while a command is in flight isn't supported by PC/SC
+/// either. Applications which need to stay responsive should run the operation on a
+/// background thread and ignore its result if the user backs out.
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum TouchPolicy {
     /// Use the default touch policy for the slot.
@@ -78,6 +103,20 @@ impl From<TouchPolicy> for u8 {
     }
 }
 
+impl TryFrom<u8> for TouchPolicy {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Error> {
+        match value {
+            0 => Ok(TouchPolicy::Default),
+            1 => Ok(TouchPolicy::Never),
+            2 => Ok(TouchPolicy::Always),
+            3 => Ok(TouchPolicy::Cached),
+            _ => Err(Error::InvalidObject),
+        }
+    }
+}
+
 impl TouchPolicy {
     /// Writes the `TouchPolicy` in the format the YubiKey expects during key generation
     /// or importation.
@@ -1,11 +1,17 @@
 //! Support for enumerating available readers
 
-use crate::{error::Error, yubikey::YubiKey};
+use crate::{
+    error::Error,
+    transport::{CardTransport, Connection},
+    yubikey::YubiKey,
+};
+use log::{debug, error, info};
 use std::{
     borrow::Cow,
     convert::TryInto,
     ffi::CStr,
-    sync::{Arc, Mutex},
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
 };
 
 /// Iterator over connected readers
@@ -37,7 +43,7 @@ impl Readers {
         let Self { ctx, reader_names } = self;
 
         let reader_cstrs: Vec<_> = {
-            let c = ctx.lock().unwrap();
+            let c = lock(ctx)?;
 
             // ensure PC/SC context is valid
             c.is_valid()?;
@@ -52,6 +58,52 @@ impl Readers {
 
         Ok(readers.into_iter())
     }
+
+    /// Iterate over the readers which contain a YubiKey.
+    ///
+    /// Cards are recognized as YubiKeys by their ATR (see
+    /// [`YubiKey::identify_from_atr`]), which is read for all readers at once
+    /// without connecting to them, so readers holding other cards (or none)
+    /// are skipped cheaply. If the reader status can't be read, all readers
+    /// are returned like [`Readers::iter`] does.
+    pub fn list(&mut self) -> Result<Iter<'_>, Error> {
+        let Self { ctx, reader_names } = self;
+
+        let (reader_cstrs, states) = {
+            let c = lock(ctx)?;
+
+            // ensure PC/SC context is valid
+            c.is_valid()?;
+
+            let reader_cstrs: Vec<_> = c.list_readers(reader_names)?.collect();
+            let mut states: Vec<_> = reader_cstrs
+                .iter()
+                .map(|name| pcsc::ReaderState::new(*name, pcsc::State::UNAWARE))
+                .collect();
+
+            if let Err(e) = c.get_status_change(Duration::from_secs(0), &mut states) {
+                debug!("couldn't get reader status, not filtering readers: {}", e);
+                states.clear();
+            }
+
+            (reader_cstrs, states)
+        };
+
+        let readers: Vec<_> = reader_cstrs
+            .iter()
+            .enumerate()
+            .filter(|(i, name)| match states.get(*i) {
+                Some(state) if YubiKey::identify_from_atr(state.atr()).is_none() => {
+                    debug!("skipping reader without a YubiKey: {:?}", name);
+                    false
+                }
+                _ => true,
+            })
+            .map(|(_, name)| Reader::new(name, Arc::clone(ctx)))
+            .collect();
+
+        Ok(readers.into_iter())
+    }
 }
 
 /// An individual connected reader
@@ -81,9 +133,58 @@ impl<'ctx> Reader<'ctx> {
         self.try_into()
     }
 
-    /// Connect to this reader, returning its `pcsc::Card`
+    /// Open a connection to this reader, selecting the PIV application with
+    /// the given AID rather than the standard PIV AIDs.
+    ///
+    /// This is useful for third-party cards which expose the PIV application
+    /// under a non-standard AID.
+    pub fn open_with_aid(&self, aid: &[u8]) -> Result<YubiKey, Error> {
+        let card = self.connect().map_err(|e| {
+            error!("error connecting to reader '{}': {}", self.name(), e);
+            e
+        })?;
+
+        info!("connected to reader: {}", self.name());
+
+        YubiKey::connect(
+            Connection::Pcsc(card),
+            String::from(self.name()),
+            Some(aid.to_vec()),
+        )
+    }
+
+    /// Get the ATR of the card in this reader, without connecting to it.
+    ///
+    /// Returns an empty ATR if the reader doesn't contain a card.
+    pub fn atr(&self) -> Result<Vec<u8>, Error> {
+        let mut states = [pcsc::ReaderState::new(
+            self.name.to_owned(),
+            pcsc::State::UNAWARE,
+        )];
+
+        let ctx = lock(&self.ctx)?;
+        ctx.get_status_change(Duration::from_secs(0), &mut states)?;
+        Ok(states[0].atr().to_vec())
+    }
+
+    /// Connect to this reader, returning the card as a [`CardTransport`], e.g.
+    /// to record an APDU trace with
+    /// [`RecordTransport`][`crate::transport::RecordTransport`].
+    pub fn transport(&self) -> Result<impl CardTransport, Error> {
+        self.connect()
+    }
+
+    /// Connect to this reader, returning its `pcsc::Card`.
     pub(crate) fn connect(&self) -> Result<pcsc::Card, Error> {
-        let ctx = self.ctx.lock().unwrap();
+        let ctx = lock(&self.ctx)?;
         Ok(ctx.connect(self.name, pcsc::ShareMode::Shared, pcsc::Protocols::T1)?)
     }
 }
+
+/// Lock the shared PC/SC context
+fn lock(ctx: &Mutex<pcsc::Context>) -> Result<MutexGuard<'_, pcsc::Context>, Error> {
+    ctx.lock().map_err(|_| {
+        error!("PC/SC context mutex poisoned");
+        Error::PcscError { inner: None }
+    })
+}
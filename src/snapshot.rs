@@ -0,0 +1,131 @@
+//! Read-only snapshots of a YubiKey's static information.
+//!
+//! A [`DeviceSnapshot`] captures the information which doesn't change during
+//! normal operation (serial number, firmware version, supported algorithms
+//! and the public keys of populated slots) so it can be shared between
+//! threads without contending on the card. Signing and any other operation
+//! which needs the card still goes through the [`YubiKey`] itself.
+//!
+//! Snapshots are never updated in place. After an operation which changes
+//! the device's keys (e.g. generating or importing a key, or resetting the
+//! device), call [`DeviceSnapshot::refresh`] and replace the snapshot shared
+//! with readers with the returned one. Readers holding a clone of the old
+//! snapshot keep seeing the old state until they pick up the new one.
+
+use crate::{
+    certificate::PublicKeyInfo,
+    error::Error,
+    key::{self, AlgorithmId, SlotId, SLOTS},
+    yubikey::{Serial, Version, YubiKey},
+};
+use log::error;
+use std::{sync::Arc, time::SystemTime};
+
+/// Cheaply cloneable, thread-safe snapshot of a YubiKey's static information.
+#[derive(Clone, Debug)]
+pub struct DeviceSnapshot(Arc<Inner>);
+
+#[derive(Debug)]
+struct Inner {
+    name: String,
+    serial: Option<Serial>,
+    version: Option<Version>,
+    algorithms: Vec<AlgorithmId>,
+    public_keys: Vec<(SlotId, PublicKeyInfo)>,
+    captured_at: SystemTime,
+}
+
+impl DeviceSnapshot {
+    /// Capture a snapshot of the given YubiKey.
+    ///
+    /// This reads the public key of every slot, so it may take a while on
+    /// devices without GET METADATA support (i.e. prior to YubiKey 5.3),
+    /// where public keys are read from the slots' certificates.
+    pub fn capture(yubikey: &mut YubiKey) -> Result<Self, Error> {
+        let algorithms = key::supported_algorithms(yubikey);
+        let txn = yubikey.begin_transaction()?;
+        let mut public_keys = vec![];
+
+        for &slot in SLOTS.iter() {
+            match txn.get_public_key(slot) {
+                Ok(public_key) => public_keys.push((slot, public_key)),
+                Err(Error::NotFound) => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        drop(txn);
+
+        Ok(DeviceSnapshot(Arc::new(Inner {
+            name: yubikey.name().to_owned(),
+            serial: yubikey.serial(),
+            version: yubikey.version(),
+            algorithms,
+            public_keys,
+            captured_at: SystemTime::now(),
+        })))
+    }
+
+    /// Capture a new snapshot of the same YubiKey this snapshot was taken of.
+    ///
+    /// Returns [`Error::ArgumentError`] if `yubikey` is a different device.
+    pub fn refresh(&self, yubikey: &mut YubiKey) -> Result<Self, Error> {
+        if yubikey.serial() != self.serial() {
+            error!(
+                "can't refresh snapshot of YubiKey {:?} from YubiKey {:?}",
+                self.serial(),
+                yubikey.serial()
+            );
+            return Err(Error::ArgumentError);
+        }
+
+        Self::capture(yubikey)
+    }
+
+    /// Name of the PC/SC reader (or transport) the YubiKey was connected to
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    /// Device serial number, or `None` for non-YubiKey cards
+    pub fn serial(&self) -> Option<Serial> {
+        self.0.serial
+    }
+
+    /// PIV application version, or `None` for non-YubiKey cards
+    pub fn version(&self) -> Option<Version> {
+        self.0.version
+    }
+
+    /// Is this a YubiKey, as opposed to a third-party PIV card?
+    pub fn is_yubikey(&self) -> bool {
+        self.0.version.is_some()
+    }
+
+    /// Key algorithms supported by the device
+    pub fn supported_algorithms(&self) -> &[AlgorithmId] {
+        &self.0.algorithms
+    }
+
+    /// Public key of the given slot, if it contained a key when the snapshot
+    /// was captured
+    pub fn public_key(&self, slot: SlotId) -> Option<&PublicKeyInfo> {
+        self.public_keys()
+            .find(|(s, _)| *s == slot)
+            .map(|(_, public_key)| public_key)
+    }
+
+    /// Slots which contained a key when the snapshot was captured, along
+    /// with their public keys
+    pub fn public_keys(&self) -> impl Iterator<Item = (SlotId, &PublicKeyInfo)> {
+        self.0
+            .public_keys
+            .iter()
+            .map(|(slot, public_key)| (*slot, public_key))
+    }
+
+    /// When the snapshot was captured
+    pub fn captured_at(&self) -> SystemTime {
+        self.0.captured_at
+    }
+}
@@ -0,0 +1,203 @@
+//! [rustls] integration: TLS keys held in a YubiKey slot.
+//!
+//! [`YubiKeySigningKey`] implements rustls' [`SigningKey`], so that a TLS
+//! server (or client, for mutual TLS) signs its handshakes with the key in a
+//! PIV slot. [`YubiKeyCertResolver`] serves it along with its certificate
+//! chain from a `ServerConfig`.
+//!
+//! RSA keys sign with RSA-PSS (encoded on the host, see
+//! [`key::sign_rsa_pss`]) or PKCS#1 v1.5, so they work with TLS 1.2 and
+//! TLS 1.3, as do ECC keys.
+//!
+//! The YubiKey is shared behind a mutex, and every handshake waits for the
+//! card: a key whose PIN policy requires the PIN to be verified needs it to
+//! be verified (and cached) beforehand, and a key requiring touch blocks
+//! the handshake until the YubiKey is touched.
+//!
+//! [rustls]: https://docs.rs/rustls
+
+use crate::{
+    error::Error,
+    key::{self, AlgorithmId, HashAlgorithm, SlotId},
+    yubikey::YubiKey,
+};
+use log::error;
+use rustls::{
+    sign::{CertifiedKey, Signer, SigningKey},
+    ClientHello, ResolvesServerCert, SignatureAlgorithm, SignatureScheme, TLSError,
+};
+use std::sync::{Arc, Mutex};
+
+/// Signature schemes usable with RSA keys, in order of preference
+const RSA_SCHEMES: &[SignatureScheme] = &[
+    SignatureScheme::RSA_PSS_SHA256,
+    SignatureScheme::RSA_PSS_SHA384,
+    SignatureScheme::RSA_PSS_SHA512,
+    SignatureScheme::RSA_PKCS1_SHA256,
+    SignatureScheme::RSA_PKCS1_SHA384,
+    SignatureScheme::RSA_PKCS1_SHA512,
+];
+
+/// rustls signing key backed by the key in a YubiKey slot.
+#[derive(Clone)]
+pub struct YubiKeySigningKey {
+    yubikey: Arc<Mutex<YubiKey>>,
+    slot: SlotId,
+    algorithm: AlgorithmId,
+}
+
+impl YubiKeySigningKey {
+    /// Use the key of the given algorithm in `slot`.
+    pub fn new(yubikey: Arc<Mutex<YubiKey>>, slot: SlotId, algorithm: AlgorithmId) -> Self {
+        YubiKeySigningKey {
+            yubikey,
+            slot,
+            algorithm,
+        }
+    }
+
+    /// Use the key in `slot`, reading its algorithm from the device (see
+    /// [`key::get_public_key`]).
+    pub fn from_slot(yubikey: Arc<Mutex<YubiKey>>, slot: SlotId) -> Result<Self, Error> {
+        let algorithm = {
+            let mut guard = yubikey.lock().map_err(|_| Error::GenericError)?;
+            key::get_public_key(&mut guard, slot)?.algorithm()
+        };
+
+        Ok(Self::new(yubikey, slot, algorithm))
+    }
+
+    /// Slot the key is stored in
+    pub fn slot(&self) -> SlotId {
+        self.slot
+    }
+
+    /// Pair this key with its certificate chain (leaf first), e.g. to serve
+    /// it with [`YubiKeyCertResolver`].
+    pub fn certified_key(self, chain: Vec<rustls::Certificate>) -> CertifiedKey {
+        CertifiedKey::new(chain, Arc::new(Box::new(self)))
+    }
+
+    /// Signature schemes this key can sign with, in order of preference
+    fn schemes(&self) -> &'static [SignatureScheme] {
+        match self.algorithm {
+            AlgorithmId::Rsa1024 | AlgorithmId::Rsa2048 => RSA_SCHEMES,
+            AlgorithmId::EccP256 => &[SignatureScheme::ECDSA_NISTP256_SHA256],
+            AlgorithmId::EccP384 => &[SignatureScheme::ECDSA_NISTP384_SHA384],
+        }
+    }
+}
+
+impl SigningKey for YubiKeySigningKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
+        let scheme = *self
+            .schemes()
+            .iter()
+            .find(|scheme| offered.contains(scheme))?;
+
+        Some(Box::new(YubiKeySigner {
+            key: self.clone(),
+            scheme,
+        }))
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        match self.algorithm {
+            AlgorithmId::Rsa1024 | AlgorithmId::Rsa2048 => SignatureAlgorithm::RSA,
+            AlgorithmId::EccP256 | AlgorithmId::EccP384 => SignatureAlgorithm::ECDSA,
+        }
+    }
+}
+
+/// Signer for a single signature scheme chosen by [`YubiKeySigningKey`]
+struct YubiKeySigner {
+    key: YubiKeySigningKey,
+    scheme: SignatureScheme,
+}
+
+impl Signer for YubiKeySigner {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, TLSError> {
+        let (hash, pss) = match self.scheme {
+            SignatureScheme::RSA_PKCS1_SHA256 | SignatureScheme::ECDSA_NISTP256_SHA256 => {
+                (HashAlgorithm::Sha256, false)
+            }
+            SignatureScheme::RSA_PKCS1_SHA384 | SignatureScheme::ECDSA_NISTP384_SHA384 => {
+                (HashAlgorithm::Sha384, false)
+            }
+            SignatureScheme::RSA_PKCS1_SHA512 => (HashAlgorithm::Sha512, false),
+            SignatureScheme::RSA_PSS_SHA256 => (HashAlgorithm::Sha256, true),
+            SignatureScheme::RSA_PSS_SHA384 => (HashAlgorithm::Sha384, true),
+            SignatureScheme::RSA_PSS_SHA512 => (HashAlgorithm::Sha512, true),
+            scheme => {
+                return Err(TLSError::General(format!(
+                    "unsupported signature scheme {:?}",
+                    scheme
+                )))
+            }
+        };
+
+        let mut yubikey = self
+            .key
+            .yubikey
+            .lock()
+            .map_err(|_| TLSError::General(String::from("YubiKey mutex poisoned")))?;
+
+        let digest = hash.digest(message);
+
+        // ECDSA signatures are DER-encoded by the card, as TLS expects
+        let result = if pss {
+            key::sign_rsa_pss(
+                &mut yubikey,
+                self.key.slot,
+                self.key.algorithm,
+                &digest,
+                hash,
+            )
+        } else {
+            key::sign_prehashed(
+                &mut yubikey,
+                self.key.slot,
+                self.key.algorithm,
+                &digest,
+                hash,
+            )
+        };
+
+        result.map(|signature| signature.to_vec()).map_err(|e| {
+            error!("TLS signature with slot {:?} failed: {}", self.key.slot, e);
+            TLSError::General(format!("YubiKey signing failed: {}", e))
+        })
+    }
+
+    fn scheme(&self) -> SignatureScheme {
+        self.scheme
+    }
+}
+
+/// Server certificate resolver which always serves the same YubiKey-backed
+/// certificate, to be set as a `ServerConfig`'s `cert_resolver`.
+///
+/// Resolves to `None` if the client offers no signature scheme the key can
+/// sign with, which aborts the handshake.
+pub struct YubiKeyCertResolver {
+    certified_key: CertifiedKey,
+}
+
+impl YubiKeyCertResolver {
+    /// Serve the given key with its certificate chain (leaf first)
+    pub fn new(key: YubiKeySigningKey, chain: Vec<rustls::Certificate>) -> Self {
+        YubiKeyCertResolver {
+            certified_key: key.certified_key(chain),
+        }
+    }
+}
+
+impl ResolvesServerCert for YubiKeyCertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<CertifiedKey> {
+        self.certified_key
+            .key
+            .choose_scheme(client_hello.sigschemes())?;
+
+        Some(self.certified_key.clone())
+    }
+}
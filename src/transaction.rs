@@ -1,12 +1,36 @@
 //! YubiKey PC/SC transactions
 
-use crate::{Buffer, CB_BUF_MAX, CB_OBJ_MAX, MgmKey, ObjectId, PIV_AID, YK_AID, apdu::Response, apdu::{Ins, StatusWords, APDU}, error::Error, key::{AlgorithmId, SlotId}, mgm::DES_LEN_3DES, serialization::*, yubikey::*};
-use log::{error, trace};
-use std::convert::TryInto;
+#[cfg(feature = "x509")]
+use crate::certificate::CertificateInfo;
+use crate::{
+    apdu::{
+        self, ApduTemplate, GeneralAuthenticate, Ins, Response, StatusWords, APDU,
+        TAG_AUTH_RESPONSE,
+    },
+    certificate::{self, CertInfo, Certificate, PublicKeyInfo},
+    error::Error,
+    key::{AlgorithmId, SlotId, SlotMetadata, SLOTS},
+    mgm::DES_LEN_3DES,
+    middleware::Middleware,
+    policy::PinPolicy,
+    serialization::*,
+    transport::{CardTransport, Connection, Interface},
+    yubikey::*,
+    Buffer, MgmKey, ObjectId, CB_BUF_MAX, CB_OBJ_MAX, MGMT_AID, PIV_AID, PIV_AID_FULL, YK_AID,
+};
+use log::{debug, error, trace, warn};
+use std::{convert::TryInto, mem};
 use zeroize::Zeroizing;
 
+const CB_PIN_MIN: usize = 6;
 const CB_PIN_MAX: usize = 8;
 
+/// Key reference for on-card biometric comparison (OCC)
+const KEY_REF_OCC: u8 = 0x96;
+
+/// Tag requesting a biometric match be performed
+const TAG_BIO_MATCH: u8 = 0x03;
+
 pub(crate) enum ChangeRefAction {
     ChangePin,
     ChangePuk,
@@ -15,17 +39,108 @@ pub(crate) enum ChangeRefAction {
 
 /// Exclusive transaction with the YubiKey's PC/SC card.
 pub(crate) struct Transaction<'tx> {
-    inner: pcsc::Transaction<'tx>,
+    inner: Inner<'tx>,
+
+    /// AID to select the PIV application with, overriding the defaults
+    aid: Option<&'tx [u8]>,
+
+    /// Maximum command data per APDU when chaining
+    chunk_size: usize,
+
+    /// Maximum response data requested per GET RESPONSE
+    response_size: usize,
+
+    /// Largest data object the card can store
+    max_object_size: usize,
+
+    /// Hooks called around every APDU
+    middleware: &'tx [Box<dyn Middleware>],
+}
+
+/// Underlying transport for a transaction
+enum Inner<'tx> {
+    /// PC/SC transaction
+    Pcsc(pcsc::Transaction<'tx>),
+
+    /// Custom transport (exclusivity is up to the transport)
+    Transport(&'tx dyn CardTransport),
+}
+
+/// Reselects the PIV application when dropped: see
+/// [`Transaction::reselect_on_drop`].
+pub(crate) struct ReselectGuard<'a, 'tx> {
+    txn: &'a Transaction<'tx>,
+    armed: bool,
+}
+
+impl<'a, 'tx> ReselectGuard<'a, 'tx> {
+    /// Reselect the PIV application now, returning any error
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.armed = false;
+        self.txn.select_application()
+    }
+}
+
+impl<'a, 'tx> Drop for ReselectGuard<'a, 'tx> {
+    fn drop(&mut self) {
+        if self.armed {
+            if let Err(e) = self.txn.select_application() {
+                error!("failed to reselect the PIV application: {}", e);
+            }
+        }
+    }
 }
 
 impl<'tx> Transaction<'tx> {
     /// Create a new transaction with the given card.
-    pub fn new(card: &'tx mut pcsc::Card) -> Result<Self, Error> {
+    pub fn new(
+        card: &'tx mut Connection,
+        aid: Option<&'tx [u8]>,
+        interface: Interface,
+    ) -> Result<Self, Error> {
+        let inner = match card {
+            Connection::Pcsc(card) => Inner::Pcsc(card.transaction()?),
+            Connection::Transport(transport) => {
+                transport.begin_transaction()?;
+                Inner::Transport(transport.as_ref())
+            }
+        };
+
         Ok(Transaction {
-            inner: card.transaction()?,
+            inner,
+            aid,
+            chunk_size: interface.chunk_size(),
+            response_size: interface.response_size(),
+            max_object_size: CB_OBJ_MAX,
+            middleware: &[],
         })
     }
 
+    /// Reject objects larger than `max_object_size` in
+    /// [`Transaction::save_object`], rather than the default [`CB_OBJ_MAX`].
+    pub fn with_max_object_size(mut self, max_object_size: usize) -> Self {
+        self.max_object_size = max_object_size;
+        self
+    }
+
+    /// Call the given middleware around every APDU in this transaction.
+    pub fn with_middleware(mut self, middleware: &'tx [Box<dyn Middleware>]) -> Self {
+        self.middleware = middleware;
+        self
+    }
+
+    /// Reselect the PIV application when the returned guard is dropped.
+    ///
+    /// Operations which switch to another application hold the guard so that
+    /// the PIV application is reselected however they exit, including on
+    /// errors, and subsequent PIV commands don't go to the wrong application.
+    pub fn reselect_on_drop(&self) -> ReselectGuard<'_, 'tx> {
+        ReselectGuard {
+            txn: self,
+            armed: true,
+        }
+    }
+
     /// Transmit a single serialized APDU to the card this transaction is open
     /// with and receive a response.
     ///
@@ -34,39 +149,75 @@ impl<'tx> Transaction<'tx> {
     /// split into multiple APDUs, use the [`Transaction::transfer_data`]
     /// method instead.
     pub fn transmit(&self, send_buffer: &[u8], recv_len: usize) -> Result<Vec<u8>, Error> {
-        trace!(">>> {:?}", send_buffer);
-
         let mut recv_buffer = vec![0u8; recv_len];
-
-        let len = self
-            .inner
-            .transmit(send_buffer, recv_buffer.as_mut())?
-            .len();
-
+        let len = self.transmit_into(send_buffer, &mut recv_buffer)?;
         recv_buffer.truncate(len);
         Ok(recv_buffer)
     }
 
+    /// Transmit a single serialized APDU like [`Transaction::transmit`], but
+    /// receive the response into a caller-provided buffer instead of
+    /// allocating one.
+    ///
+    /// Returns the length of the response, including the status words.
+    pub fn transmit_into(
+        &self,
+        send_buffer: &[u8],
+        recv_buffer: &mut [u8],
+    ) -> Result<usize, Error> {
+        for middleware in self.middleware {
+            middleware.before_transmit(send_buffer);
+        }
+
+        let len = match &self.inner {
+            Inner::Pcsc(txn) => txn.transmit(send_buffer, recv_buffer)?.len(),
+            Inner::Transport(transport) => transport.transmit(send_buffer, recv_buffer)?,
+        };
+
+        for middleware in self.middleware.iter().rev() {
+            middleware.after_transmit(&recv_buffer[..len]);
+        }
+
+        Ok(len)
+    }
+
     /// Select application.
+    ///
+    /// Tries the AID override if one was given, and otherwise the full PIV
+    /// AID followed by the truncated one, as different cards accept different
+    /// forms of the AID.
     pub fn select_application(&self) -> Result<(), Error> {
-        let response = APDU::new(Ins::SelectApplication)
-            .p1(0x04)
-            .data(&PIV_AID)
-            .transmit(self, 0xFF)
-            .map_err(|e| {
-                error!("failed communicating with card: '{}'", e);
-                e
-            })?;
+        let aids: Vec<&[u8]> = match self.aid {
+            Some(aid) => vec![aid],
+            None => vec![&PIV_AID_FULL[..], &PIV_AID[..]],
+        };
 
-        if !response.is_success() {
-            error!(
-                "failed selecting application: {:04x}",
+        for aid in &aids {
+            let response = APDU::new(Ins::SelectApplication)
+                .p1(0x04)
+                .data(aid)
+                .transmit(self, 0xFF)
+                .map_err(|e| {
+                    error!("failed communicating with card: '{}'", e);
+                    e
+                })?;
+
+            if response.is_success() {
+                return Ok(());
+            }
+
+            debug!(
+                "selecting AID {:02x?} failed: {:04x}",
+                aid,
                 response.status_words().code()
             );
-            return Err(Error::GenericError);
         }
 
-        Ok(())
+        error!(
+            "failed selecting PIV application (tried AIDs: {:02x?})",
+            aids
+        );
+        Err(Error::AppletError)
     }
 
     /// Get the version of the PIV application installed on the YubiKey.
@@ -75,7 +226,7 @@ impl<'tx> Transaction<'tx> {
         let response = APDU::new(Ins::GetVersion).transmit(self, 261)?;
 
         if !response.is_success() {
-            return Err(Error::GenericError);
+            return Err(response.status_words().to_error());
         }
 
         if response.data().len() < 3 {
@@ -87,8 +238,10 @@ impl<'tx> Transaction<'tx> {
 
     /// Get YubiKey device serial number.
     pub fn get_serial(&self, version: Version) -> Result<Serial, Error> {
-        let response = if version.major < 5 {
+        let response = if !version.at_least(5, 0, 0) {
             // YK4 requires switching to the yk applet to retrieve the serial
+            let reselect = self.reselect_on_drop();
+
             let sw = APDU::new(Ins::SelectApplication)
                 .p1(0x04)
                 .data(&YK_AID)
@@ -110,18 +263,7 @@ impl<'tx> Transaction<'tx> {
                 return Err(Error::GenericError);
             }
 
-            // reselect the PIV applet
-            let sw = APDU::new(Ins::SelectApplication)
-                .p1(0x04)
-                .data(&PIV_AID)
-                .transmit(self, 0xFF)?
-                .status_words();
-
-            if !sw.is_success() {
-                error!("failed selecting application: {:04x}", sw.code());
-                return Err(Error::GenericError);
-            }
-
+            reselect.finish()?;
             resp
         } else {
             // YK5 implements getting the serial as a PIV applet command (0xf8)
@@ -132,7 +274,7 @@ impl<'tx> Transaction<'tx> {
                     "failed retrieving serial number: {:04x}",
                     resp.status_words().code()
                 );
-                return Err(Error::GenericError);
+                return Err(resp.status_words().to_error());
             }
 
             resp
@@ -144,14 +286,104 @@ impl<'tx> Transaction<'tx> {
             .map_err(|_| Error::SizeError)
     }
 
+    /// Write device configuration (YubiKey 5+) using the management
+    /// application, reselecting the PIV application afterwards (even if the
+    /// write failed).
+    ///
+    /// `config` is the TLV-encoded configuration, without the length prefix.
+    pub fn write_device_config(&self, config: &[u8]) -> Result<(), Error> {
+        let reselect = self.reselect_on_drop();
+        self.select_mgmt_application()?;
+
+        if config.len() > 0xff - 1 {
+            return Err(Error::SizeError);
+        }
+
+        let mut data = Zeroizing::new(Vec::with_capacity(config.len() + 1));
+        data.push(config.len() as u8);
+        data.extend_from_slice(config);
+
+        // WRITE CONFIG
+        let sw = APDU::new(0x1c)
+            .data(data.as_slice())
+            .transmit(self, 0xFF)?
+            .status_words();
+
+        match sw {
+            StatusWords::Success => reselect.finish(),
+            StatusWords::SecurityStatusError | StatusWords::ConditionsNotSatisfiedError => {
+                error!("device config is locked: {:04x}", sw.code());
+                Err(Error::AuthenticationError)
+            }
+            _ => {
+                error!("failed writing device config: {:04x}", sw.code());
+                Err(Error::GenericError)
+            }
+        }
+    }
+
+    /// Read device configuration (YubiKey 5+) using the management
+    /// application, reselecting the PIV application afterwards (even if the
+    /// read failed).
+    ///
+    /// Returns the TLV-encoded configuration, without the length prefix.
+    pub fn read_device_config(&self) -> Result<Buffer, Error> {
+        let reselect = self.reselect_on_drop();
+        self.select_mgmt_application()?;
+
+        // READ CONFIG
+        let response = APDU::new(0x1d).transmit(self, 0xFF)?;
+
+        if !response.is_success() {
+            error!(
+                "failed reading device config: {:04x}",
+                response.status_words().code()
+            );
+            return Err(Error::GenericError);
+        }
+
+        let config = match response.data().split_first() {
+            Some((&len, config)) if config.len() >= len as usize => {
+                Buffer::new(config[..len as usize].to_vec())
+            }
+            _ => {
+                error!("malformed device config");
+                return Err(Error::ParseError);
+            }
+        };
+
+        reselect.finish()?;
+        Ok(config)
+    }
+
+    fn select_mgmt_application(&self) -> Result<(), Error> {
+        let sw = APDU::new(Ins::SelectApplication)
+            .p1(0x04)
+            .data(&MGMT_AID)
+            .transmit(self, 0xFF)?
+            .status_words();
+
+        if !sw.is_success() {
+            error!("failed selecting mgmt application: {:04x}", sw.code());
+            return Err(Error::GenericError);
+        }
+
+        Ok(())
+    }
+
     /// Verify device PIN.
     pub fn verify_pin(&self, pin: &[u8]) -> Result<(), Error> {
+        self.verify_pin_ref(PinReference::Application, pin)
+    }
+
+    /// Verify the PIN with the given reference.
+    pub fn verify_pin_ref(&self, reference: PinReference, pin: &[u8]) -> Result<(), Error> {
         if pin.len() > CB_PIN_MAX {
             return Err(Error::SizeError);
         }
 
         let mut query = APDU::new(Ins::Verify);
-        query.params(0x00, 0x80);
+        query.params(0x00, reference.into());
 
         // Empty pin means we are querying the number of retries. We set no data in this
         // case; if we instead sent [0xff; CB_PIN_MAX] it would count as an attempt and
@@ -168,7 +400,88 @@ impl<'tx> Transaction<'tx> {
             StatusWords::Success => Ok(()),
             StatusWords::AuthBlockedError => Err(Error::WrongPin { tries: 0 }),
             StatusWords::VerifyFailError { tries } => Err(Error::WrongPin { tries }),
-            _ => Err(Error::GenericError),
+            StatusWords::ReferenceNotFoundError => Err(Error::NotFound),
+            other => Err(other.to_error()),
+        }
+    }
+
+    /// Is the PIN currently verified for this session?
+    ///
+    /// Sends VERIFY without any data, which doesn't consume a PIN attempt.
+    pub fn is_pin_verified(&self) -> Result<bool, Error> {
+        let response = APDU::new(Ins::Verify)
+            .params(0x00, 0x80)
+            .transmit(self, 261)?;
+
+        match response.status_words() {
+            StatusWords::Success => Ok(true),
+            StatusWords::VerifyFailError { .. } | StatusWords::AuthBlockedError => Ok(false),
+            sw => {
+                error!("failed to query PIN verification status: {:04x}", sw.code());
+                Err(Error::GenericError)
+            }
+        }
+    }
+
+    /// Read the PIN, PUK and management key state.
+    ///
+    /// Only non-destructive queries are used: VERIFY without data and GET
+    /// METADATA neither consume retries nor reset the PIN verification
+    /// state. Fields which can't be queried this way are left `None` (see
+    /// [`AuthStatus`]).
+    pub fn auth_status(&self) -> Result<AuthStatus, Error> {
+        let (pin_verified, mut pin_retries) = match self.verify_pin(&[]) {
+            Ok(()) => (true, None),
+            Err(Error::WrongPin { tries }) => (false, Some(tries)),
+            Err(e) => return Err(e),
+        };
+
+        let mut status = AuthStatus {
+            pin_retries: None,
+            puk_retries: None,
+            pin_verified,
+            mgm_key_default: None,
+            mgm_key_algorithm: ALGO_3DES,
+        };
+
+        if let Some(data) = self.try_get_metadata(PinReference::Application.into())? {
+            if let Some((_, remaining)) = SlotMetadata::parse(&data)?.retries {
+                pin_retries = Some(remaining);
+            }
+
+            if let Some(data) = self.try_get_metadata(PinReference::Puk.into())? {
+                status.puk_retries = SlotMetadata::parse(&data)?.retries.map(|(_, r)| r);
+            }
+
+            if let Some(data) = self.try_get_metadata(SlotId::CardManagement.into())? {
+                let metadata = SlotMetadata::parse(&data)?;
+                status.mgm_key_default = metadata.is_default;
+                status.mgm_key_algorithm = metadata.algorithm;
+            }
+        }
+
+        status.pin_retries = pin_retries;
+        Ok(status)
+    }
+
+    /// Verify the user's fingerprint using the on-card biometric sensor.
+    pub fn verify_bio(&self) -> Result<BioVerifyResult, Error> {
+        let mut query = APDU::new(Ins::Verify);
+        query.params(0x00, KEY_REF_OCC).data(&[TAG_BIO_MATCH, 0x00]);
+
+        let response = query.transmit(self, 261)?;
+
+        match response.status_words() {
+            StatusWords::Success => Ok(BioVerifyResult::Matched),
+            StatusWords::SecurityStatusError => Ok(BioVerifyResult::MatchRequired),
+            StatusWords::VerifyFailError { tries: 0 } => Ok(BioVerifyResult::Blocked),
+            StatusWords::VerifyFailError { tries } => Ok(BioVerifyResult::NoMatch { tries }),
+            StatusWords::AuthBlockedError => Ok(BioVerifyResult::Blocked),
+            StatusWords::ReferenceNotFoundError => Err(Error::NotSupported),
+            sw => {
+                error!("biometric verification failed: {:04x}", sw.code());
+                Err(Error::GenericError)
+            }
         }
     }
 
@@ -178,32 +491,60 @@ impl<'tx> Transaction<'tx> {
         action: ChangeRefAction,
         current_pin: &[u8],
         new_pin: &[u8],
+    ) -> Result<(), Error> {
+        let (ins, reference) = match action {
+            ChangeRefAction::ChangePin => (Ins::ChangeReference, PinReference::Application),
+            ChangeRefAction::ChangePuk => (Ins::ChangeReference, PinReference::Puk),
+            ChangeRefAction::UnblockPin => (Ins::ResetRetry, PinReference::Application),
+        };
+
+        self.change_ref_inner(ins, reference, current_pin, new_pin)
+    }
+
+    /// Change the PIN with the given reference.
+    pub fn change_pin_ref(
+        &self,
+        reference: PinReference,
+        current_pin: &[u8],
+        new_pin: &[u8],
+    ) -> Result<(), Error> {
+        self.change_ref_inner(Ins::ChangeReference, reference, current_pin, new_pin)
+    }
+
+    /// Send CHANGE REFERENCE or RESET RETRY COUNTER. A wrong current PIN is
+    /// reported as [`Error::WrongPuk`] when it is the PUK.
+    fn change_ref_inner(
+        &self,
+        ins: Ins,
+        reference: PinReference,
+        current_pin: &[u8],
+        new_pin: &[u8],
     ) -> Result<(), Error> {
         if current_pin.len() > CB_PIN_MAX || new_pin.len() > CB_PIN_MAX {
             return Err(Error::SizeError);
         }
 
-        const PIN: u8 = 0x80;
-        const PUK: u8 = 0x81;
-
-        let templ = match action {
-            ChangeRefAction::ChangePin => [0, Ins::ChangeReference.code(), 0, PIN],
-            ChangeRefAction::ChangePuk => [0, Ins::ChangeReference.code(), 0, PUK],
-            ChangeRefAction::UnblockPin => [0, Ins::ResetRetry.code(), 0, PIN],
-        };
+        let templ = ApduTemplate::new(ins, 0, reference.into());
 
         let mut indata = Zeroizing::new([0xff; CB_PIN_MAX * 2]);
         indata[0..current_pin.len()].copy_from_slice(current_pin);
         indata[CB_PIN_MAX..CB_PIN_MAX + new_pin.len()].copy_from_slice(new_pin);
 
         let status_words = self
-            .transfer_data(&templ, indata.as_ref(), 0xFF)?
+            .transfer_data(templ, indata.as_ref(), 0xFF)?
             .status_words();
 
         match status_words {
             StatusWords::Success => Ok(()),
             StatusWords::AuthBlockedError => Err(Error::PinLocked),
-            StatusWords::VerifyFailError { tries } => Err(Error::WrongPin { tries }),
+            StatusWords::VerifyFailError { tries } => {
+                if ins == Ins::ResetRetry || reference == PinReference::Puk {
+                    Err(Error::WrongPuk { tries })
+                } else {
+                    Err(Error::WrongPin { tries })
+                }
+            }
+            StatusWords::ReferenceNotFoundError => Err(Error::NotFound),
             _ => {
                 error!(
                     "failed changing pin, token response code: {:x}.",
@@ -214,6 +555,29 @@ impl<'tx> Transaction<'tx> {
         }
     }
 
+    /// Unblock the PIN using the PUK, setting a new PIN.
+    ///
+    /// Both the PUK and the new PIN must be 6 to 8 bytes long. A wrong PUK
+    /// returns [`Error::WrongPuk`] with the remaining PUK tries, and a blocked
+    /// PUK returns [`Error::PinLocked`].
+    pub fn unblock_pin(&self, puk: &[u8], new_pin: &[u8]) -> Result<(), Error> {
+        if !(CB_PIN_MIN..=CB_PIN_MAX).contains(&puk.len()) {
+            error!("invalid PUK length: {} (must be 6-8 bytes)", puk.len());
+            return Err(Error::SizeError);
+        }
+
+        if !(CB_PIN_MIN..=CB_PIN_MAX).contains(&new_pin.len()) {
+            error!("invalid PIN length: {} (must be 6-8 bytes)", new_pin.len());
+            return Err(Error::SizeError);
+        }
+
+        match self.change_ref(ChangeRefAction::UnblockPin, puk, new_pin) {
+            // Some firmware reports a blocked PUK as zero tries remaining
+            Err(Error::WrongPuk { tries: 0 }) => Err(Error::PinLocked),
+            other => other,
+        }
+    }
+
     /// Set the management key (MGM).
 
     pub fn set_mgm_key(&self, new_key: &MgmKey, require_touch: bool) -> Result<(), Error> {
@@ -221,7 +585,7 @@ impl<'tx> Transaction<'tx> {
 
         let mut data = [0u8; DES_LEN_3DES + 3];
         data[0] = ALGO_3DES;
-        data[1] = KEY_CARDMGM;
+        data[1] = SlotId::CardManagement.into();
         data[2] = DES_LEN_3DES as u8;
         data[3..3 + DES_LEN_3DES].copy_from_slice(new_key.as_ref());
 
@@ -238,12 +602,31 @@ impl<'tx> Transaction<'tx> {
         Ok(())
     }
 
+    /// Set the management key and store it in the PIN-protected data object,
+    /// so it can later be recovered by anyone who knows the PIN.
+    ///
+    /// This is equivalent to `ykman piv access change-management-key --protect`.
+    /// The caller must already be authenticated with the current management key.
+    ///
+    /// Note that this reduces the security of the management key to that of
+    /// the PIN: anyone who learns the PIN can recover the management key and
+    /// perform administrative operations such as overwriting keys.
+    pub fn store_mgm_key_protected(&self, key: &MgmKey, pin: &[u8]) -> Result<(), Error> {
+        self.verify_pin(pin)?;
+        key.write_protected(self)
+    }
+
+    /// Verify the PIN and retrieve a management key previously stored with
+    /// [`Transaction::store_mgm_key_protected`].
+    pub fn fetch_protected_mgm_key(&self, pin: &[u8]) -> Result<MgmKey, Error> {
+        self.verify_pin(pin)?;
+        MgmKey::read_protected(self)
+    }
+
     /// Perform a YubiKey operation which requires authentication.
     ///
     /// This is the common backend for all public key encryption and signing
     /// operations.
-    // TODO(tarcieri): refactor this to be less gross/coupled.
-    #[allow(clippy::too_many_arguments)]
     pub(crate) fn authenticated_command(
         &self,
         sign_in: &[u8],
@@ -251,9 +634,27 @@ impl<'tx> Transaction<'tx> {
         key: SlotId,
         decipher: bool,
     ) -> Result<Buffer, Error> {
+        let mut response = Zeroizing::new(Vec::new());
+        self.authenticated_command_with(sign_in, algorithm, key, decipher, &mut response)
+    }
+
+    /// Perform an operation like [`Transaction::authenticated_command`],
+    /// using `response` as scratch space for the card's response so its
+    /// allocation can be reused across operations.
+    pub(crate) fn authenticated_command_with(
+        &self,
+        sign_in: &[u8],
+        algorithm: AlgorithmId,
+        key: SlotId,
+        decipher: bool,
+        response: &mut Vec<u8>,
+    ) -> Result<Buffer, Error> {
+        if key == SlotId::CardManagement {
+            error!("the card management key cannot be used for signing or decryption");
+            return Err(Error::KeyError);
+        }
+
         let in_len = sign_in.len();
-        let mut indata = [0u8; 1024];
-        let templ = [0, Ins::Authenticate.code(), algorithm.into(), key.into()];
 
         match algorithm {
             AlgorithmId::Rsa1024 | AlgorithmId::Rsa2048 => {
@@ -274,123 +675,187 @@ impl<'tx> Transaction<'tx> {
                     48
                 };
 
-                if (!decipher && (in_len > key_len)) || (decipher && (in_len != (key_len * 2) + 1))
-                {
+                if !decipher && in_len > key_len {
+                    error!(
+                        "cannot sign {} bytes with a {:?} key: ECDSA signs the leftmost bits of \
+                         the digest, so it must be truncated to its first {} bytes",
+                        in_len, algorithm, key_len
+                    );
+                    return Err(Error::SizeError);
+                }
+
+                if decipher && in_len != (key_len * 2) + 1 {
                     return Err(Error::SizeError);
                 }
             }
         }
 
-        let bytes = if in_len < 0x80 {
-            1
-        } else if in_len < 0xff {
-            2
+        let request = if decipher {
+            GeneralAuthenticate::decipher(algorithm, key, sign_in)
         } else {
-            3
+            GeneralAuthenticate::sign(algorithm, key, sign_in)
         };
 
-        let offset = Tlv::write_as(&mut indata, 0x7c, in_len + bytes + 3, |buf| {
-            assert_eq!(Tlv::write(buf, 0x82, &[]).expect("large enough"), 2);
-            assert_eq!(
-                Tlv::write(
-                    &mut buf[2..],
-                    match (algorithm, decipher) {
-                        (AlgorithmId::EccP256, true) | (AlgorithmId::EccP384, true) => 0x85,
-                        _ => 0x81,
-                    },
-                    sign_in
-                )
-                .expect("large enough"),
-                1 + bytes + in_len
-            );
-        })?;
+        // holds the data to be signed or decrypted
+        let mut indata = Zeroizing::new([0u8; 1024]);
+        let len = request.write(&mut indata[..])?;
 
-        let response = self
-            .transfer_data(&templ, &indata[..offset], 1024)
+        let status_words = self
+            .transfer_data_into(request.template(), &indata[..len], 1024, response)
             .map_err(|e| {
                 error!("sign command failed to communicate: {}", e);
                 e
             })?;
 
-        if !response.is_success() {
-            error!("failed sign command with code {:x}", response.code());
+        if !status_words.is_success() {
+            error!("failed sign command with code {:x}", status_words.code());
 
-            if response.status_words() == StatusWords::SecurityStatusError {
-                return Err(Error::AuthenticationError);
-            } else {
-                return Err(Error::GenericError);
+            match status_words {
+                StatusWords::SecurityStatusError => {
+                    return Err(self.key_security_status_error(key));
+                }
+                StatusWords::WrongLengthError => {
+                    // almost always a framing bug: the input length or padding was wrong
+                    error!(
+                        "card rejected the length of the {} bytes of {:?} input",
+                        in_len, algorithm
+                    );
+                    return Err(Error::SizeError);
+                }
+                _ => return Err(Error::GenericError),
             }
         }
 
-        let (_, outer_tlv) = Tlv::parse(response.data())?;
-
-        // skip the first 7c tag
-        if outer_tlv.tag != 0x7c {
-            error!("failed parsing signature reply (0x7c byte)");
-            return Err(Error::ParseError);
-        }
+        let output = GeneralAuthenticate::parse_response(response.as_slice(), TAG_AUTH_RESPONSE)?;
+        Ok(Buffer::new(output.into()))
+    }
 
-        let (_, inner_tlv) = Tlv::parse(outer_tlv.value)?;
+    /// Error for a private key operation which failed with "security status
+    /// not satisfied": [`Error::PinRequired`] if the key's PIN policy (from
+    /// its metadata, where supported) calls for the PIN, and
+    /// [`Error::AuthenticationError`] otherwise.
+    fn key_security_status_error(&self, key: SlotId) -> Error {
+        let pin_policy = match self.try_get_metadata(key.into()) {
+            Ok(Some(data)) => SlotMetadata::parse(&data).ok().and_then(|m| m.pin_policy),
+            _ => None,
+        };
 
-        // skip the 82 tag
-        if inner_tlv.tag != 0x82 {
-            error!("failed parsing signature reply (0x82 byte)");
-            return Err(Error::ParseError);
+        if matches!(pin_policy, Some(PinPolicy::Once) | Some(PinPolicy::Always)) {
+            error!("the key in slot {:?} requires the PIN to be verified", key);
+            Error::PinRequired
+        } else {
+            Error::AuthenticationError
         }
-
-        Ok(Buffer::new(inner_tlv.value.into()))
     }
 
     /// Send/receive large amounts of data to/from the YubiKey, splitting long
     /// messages into smaller APDU-sized messages (using the provided APDU
     /// template to construct them), and then sending those via
     /// [`Transaction::transmit`].
+    ///
+    /// Returns [`Error::PartialWrite`] if an intermediate chunk of a PUT DATA
+    /// or IMPORT KEY command is rejected after earlier chunks were accepted.
+    /// The status words of the final chunk, which the command is executed
+    /// on, are returned as usual.
     pub fn transfer_data(
         &self,
-        templ: &[u8],
+        templ: ApduTemplate,
         in_data: &[u8],
         max_out: usize,
     ) -> Result<Response, Error> {
+        let mut out_data = Zeroizing::new(Vec::new());
+        let status_words = self.transfer_data_into(templ, in_data, max_out, &mut out_data)?;
+        Ok(Response::new(status_words, mem::take(&mut *out_data)))
+    }
+
+    /// Send/receive data like [`Transaction::transfer_data`], but accumulate
+    /// the response data in `out_data` (which is cleared first) so its
+    /// allocation can be reused across calls.
+    ///
+    /// Returns the status words of the last response.
+    pub fn transfer_data_into(
+        &self,
+        templ: ApduTemplate,
+        in_data: &[u8],
+        max_out: usize,
+        out_data: &mut Vec<u8>,
+    ) -> Result<StatusWords, Error> {
         let mut in_offset = 0;
-        let mut out_data = vec![];
+        // reserved upfront so that no unzeroized copies are left behind by
+        // reallocation as the response is accumulated
+        out_data.clear();
+        out_data.reserve(max_out.min(CB_BUF_MAX));
+
+        // reused for every APDU exchanged
+        let mut recv_buffer = Zeroizing::new([0u8; 261]);
         let mut sw;
 
+        // the rest of a response carrying secrets mustn't be logged either
+        let redact_response = apdu::response_is_sensitive(templ.ins, in_data);
+
+        trace!(
+            "transferring {} bytes with APDU template {:02x?}",
+            in_data.len(),
+            templ.to_bytes()
+        );
+
         loop {
-            let mut this_size = 0xff;
+            let mut this_size = self.chunk_size;
 
-            let cla = if in_offset + 0xff < in_data.len() {
+            let cla = if in_offset + self.chunk_size < in_data.len() {
                 0x10
             } else {
                 this_size = in_data.len() - in_offset;
-                templ[0]
+                templ.cla
             };
 
             trace!("going to send {} bytes in this go", this_size);
 
-            let response = APDU::new(templ[1])
-                .cla(cla)
-                .params(templ[2], templ[3])
+            let mut apdu = APDU::new(templ.ins);
+            apdu.cla(cla)
+                .params(templ.p1, templ.p2)
                 .data(&in_data[in_offset..(in_offset + this_size)])
-                .transmit(self, 261)?;
-
-            sw = response.status_words().code();
+                .redact_response(redact_response);
+
+            let (status_words, data) = self.transmit_exact_le(&mut apdu, &mut recv_buffer[..])?;
+
+            sw = status_words.code();
+
+            if !status_words.is_success() && (sw >> 8 != 0x61) {
+                // The card only executes a chained command on its final
+                // chunk, whose status words are returned as usual. A write
+                // whose intermediate chunk is rejected after earlier ones
+                // were accepted may have been partially applied, though.
+                let intermediate = in_offset + this_size < in_data.len();
+                let write = templ.ins == Ins::PutData || templ.ins == Ins::ImportKey;
+
+                if intermediate && in_offset > 0 && write {
+                    error!(
+                        "chained write failed after {} of {} bytes (error {:?})",
+                        in_offset,
+                        in_data.len(),
+                        status_words
+                    );
+                    return Err(Error::PartialWrite {
+                        bytes_written: in_offset,
+                    });
+                }
 
-            if !response.is_success() && (sw >> 8 != 0x61) {
                 // TODO(tarcieri): is this really OK?
-                return Ok(Response::new(sw.into(), out_data));
+                return Ok(status_words);
             }
 
-            if !out_data.is_empty() && (out_data.len() - response.data().len() > max_out) {
+            if !out_data.is_empty() && (out_data.len() - data.len() > max_out) {
                 error!(
                     "output buffer too small: wanted to write {}, max was {}",
-                    out_data.len() - response.data().len(),
+                    out_data.len() - data.len(),
                     max_out
                 );
 
                 return Err(Error::SizeError);
             }
 
-            out_data.extend_from_slice(&response.data()[..response.data().len()]);
+            out_data.extend_from_slice(data);
 
             in_offset += this_size;
             if in_offset >= in_data.len() {
@@ -404,39 +869,82 @@ impl<'tx> Transaction<'tx> {
                 sw & 0xff
             );
 
-            let response = APDU::new(Ins::GetResponseApdu).transmit(self, 261)?;
-            sw = response.status_words().code();
+            // Some third-party cards reject GET RESPONSE unless it carries
+            // the class of the originating command (without command chaining)
+            let cla = templ.cla & !0x10;
+            trace!("sending GET RESPONSE with CLA {:02x}", cla);
+
+            // Request as many bytes as the card says are available (`61 00`
+            // meaning 256), but no more than the interface can relay
+            let available = match sw & 0xff {
+                0 => 0x100,
+                n => n as usize,
+            };
+
+            let mut get_response = APDU::new(Ins::GetResponseApdu);
+            get_response
+                .cla(cla)
+                .le(available.min(self.response_size) as u8)
+                .redact_response(redact_response);
+
+            let (status_words, data) =
+                self.transmit_exact_le(&mut get_response, &mut recv_buffer[..])?;
+            sw = status_words.code();
 
             if sw != StatusWords::Success.code() && (sw >> 8 != 0x61) {
-                return Ok(Response::new(sw.into(), vec![]));
+                out_data.clear();
+                return Ok(status_words);
             }
 
-            if out_data.len() + response.data().len() > max_out {
+            if out_data.len() + data.len() > max_out {
                 error!(
                     "output buffer too small: wanted to write {}, max was {}",
-                    out_data.len() + response.data().len(),
+                    out_data.len() + data.len(),
                     max_out
                 );
 
                 return Err(Error::SizeError);
             }
 
-            out_data.extend_from_slice(&response.data()[..response.data().len()]);
+            out_data.extend_from_slice(data);
         }
 
-        Ok(Response::new(sw.into(), out_data))
+        Ok(sw.into())
+    }
+
+    /// Transmit `apdu`, resending it with the expected response length the
+    /// card asks for if it rejects the one sent with `6C xx` (as some cards
+    /// do over NFC).
+    fn transmit_exact_le<'r>(
+        &self,
+        apdu: &mut APDU,
+        recv_buffer: &'r mut [u8],
+    ) -> Result<(StatusWords, &'r [u8]), Error> {
+        let (status_words, len) = {
+            let (status_words, data) = apdu.transmit_into(self, &mut *recv_buffer)?;
+            (status_words, data.len())
+        };
+
+        if status_words.code() >> 8 != 0x6c {
+            let recv_buffer: &'r [u8] = recv_buffer;
+            return Ok((status_words, &recv_buffer[..len]));
+        }
+
+        let le = (status_words.code() & 0xff) as u8;
+        trace!("card expects Le {:02x}; resending the command", le);
+        apdu.le(le).transmit_into(self, recv_buffer)
     }
 
     /// Fetch an object.
     pub fn fetch_object(&self, object_id: ObjectId) -> Result<Buffer, Error> {
         let mut indata = [0u8; 5];
-        let templ = [0, Ins::GetData.code(), 0x3f, 0xff];
+        let templ = ApduTemplate::new(Ins::GetData, 0x3f, 0xff);
 
         let mut inlen = indata.len();
         let indata_remaining = set_object(object_id, &mut indata);
         inlen -= indata_remaining.len();
 
-        let response = self.transfer_data(&templ, &indata[..inlen], CB_BUF_MAX)?;
+        let response = self.transfer_data(templ, &indata[..inlen], CB_BUF_MAX)?;
 
         if !response.is_success() {
             if response.status_words() == StatusWords::NotFoundError {
@@ -461,32 +969,296 @@ impl<'tx> Transaction<'tx> {
         Ok(Zeroizing::new(tlv.value.to_vec()))
     }
 
+    /// Fetch several objects within this transaction.
+    ///
+    /// Errors specific to an object (e.g. [`Error::NotFound`]) are returned
+    /// alongside its ID, while communication errors abort the whole batch.
+    pub fn fetch_objects(
+        &self,
+        object_ids: &[ObjectId],
+    ) -> Result<Vec<(ObjectId, Result<Buffer, Error>)>, Error> {
+        let mut objects = Vec::with_capacity(object_ids.len());
+
+        for &object_id in object_ids {
+            match self.fetch_object(object_id) {
+                Err(e @ Error::PcscError { .. }) => return Err(e),
+                result => objects.push((object_id, result)),
+            }
+        }
+
+        Ok(objects)
+    }
+
     /// Save an object.
     pub fn save_object(&self, object_id: ObjectId, indata: &[u8]) -> Result<(), Error> {
-        let templ = [0, Ins::PutData.code(), 0x3f, 0xff];
+        match self.put_data(object_id, indata)? {
+            StatusWords::Success => Ok(()),
+            StatusWords::SecurityStatusError => Err(Error::AuthenticationError),
+            other => Err(other.to_error()),
+        }
+    }
 
-        // TODO(tarcieri): replace with vector
-        let mut data = [0u8; CB_BUF_MAX];
+    /// Fetch an arbitrary data object by its 3-byte tag, including objects
+    /// this library has no typed support for.
+    ///
+    /// Returns [`Error::InvalidObject`] if the tag isn't 3 bytes long, and
+    /// [`Error::NotFound`] if the object is empty or unknown to the device.
+    pub fn get_raw_object(&self, tag: u32) -> Result<Buffer, Error> {
+        check_raw_object_tag(tag)?;
 
-        if indata.len() > CB_OBJ_MAX {
-            return Err(Error::SizeError);
+        let mut object_tag = [0u8; 5];
+        set_object(tag, &mut object_tag);
+
+        let templ = ApduTemplate::new(Ins::GetData, 0x3f, 0xff);
+        let response = self.transfer_data(templ, &object_tag, CB_BUF_MAX)?;
+
+        match response.status_words() {
+            StatusWords::Success => (),
+            StatusWords::NotFoundError | StatusWords::IncorrectParamError => {
+                return Err(Error::NotFound)
+            }
+            StatusWords::SecurityStatusError => return Err(Error::AuthenticationError),
+            other => return Err(other.to_error()),
         }
 
-        let mut len = data.len();
-        let mut data_remaining = set_object(object_id, &mut data);
+        let (remaining, tlv) = Tlv::parse(response.data())?;
 
-        let offset = Tlv::write(data_remaining, 0x53, indata)?;
-        data_remaining = &mut data_remaining[offset..];
-        len -= data_remaining.len();
+        if !remaining.is_empty() {
+            error!("trailing data after object {:06x}", tag);
+            return Err(Error::SizeError);
+        }
 
-        let status_words = self
-            .transfer_data(&templ, &data[..len], 255)?
-            .status_words();
+        Ok(Zeroizing::new(tlv.value.to_vec()))
+    }
 
-        match status_words {
+    /// Store an arbitrary data object by its 3-byte tag.
+    ///
+    /// Returns [`Error::InvalidObject`] if the tag isn't 3 bytes long, and
+    /// [`Error::NotFound`] if the device doesn't know the object.
+    pub fn put_raw_object(&self, tag: u32, data: &[u8]) -> Result<(), Error> {
+        check_raw_object_tag(tag)?;
+
+        match self.put_data(tag, data)? {
             StatusWords::Success => Ok(()),
+            StatusWords::NotFoundError | StatusWords::IncorrectParamError => Err(Error::NotFound),
             StatusWords::SecurityStatusError => Err(Error::AuthenticationError),
-            _ => Err(Error::GenericError),
+            other => Err(other.to_error()),
+        }
+    }
+
+    /// Send PUT DATA for the given object, returning the status words.
+    fn put_data(&self, object_id: ObjectId, indata: &[u8]) -> Result<StatusWords, Error> {
+        let templ = ApduTemplate::new(Ins::PutData, 0x3f, 0xff);
+
+        if indata.len() > self.max_object_size {
+            error!(
+                "object {:06x} is {} bytes, exceeding the maximum object size of {} bytes",
+                object_id,
+                indata.len(),
+                self.max_object_size
+            );
+            return Err(Error::SizeError);
+        }
+
+        // Object tag (up to 5 bytes)
+        let mut object_tag = [0u8; 5];
+        let tag_len = object_tag.len() - set_object(object_id, &mut object_tag).len();
+
+        // 0x53 tag and length (up to 4 bytes)
+        let mut wrapper = [0u8; 4];
+        wrapper[0] = 0x53;
+        let wrapper_len = 1 + set_length(&mut wrapper[1..], indata.len())?;
+
+        // Allocated upfront, as objects may contain secrets (e.g. a
+        // PIN-protected management key) which reallocation would leave behind
+        let mut data = Zeroizing::new(Vec::with_capacity(tag_len + wrapper_len + indata.len()));
+        data.extend_from_slice(&object_tag[..tag_len]);
+        data.extend_from_slice(&wrapper[..wrapper_len]);
+        data.extend_from_slice(indata);
+
+        Ok(self.transfer_data(templ, &data, 255)?.status_words())
+    }
+
+    /// Get the raw metadata for a key reference (YubiKey 5.3+).
+    pub fn get_metadata(&self, key_ref: u8) -> Result<Buffer, Error> {
+        let templ = ApduTemplate::new(Ins::GetMetadata, 0, key_ref);
+        let response = self.transfer_data(templ, &[], CB_BUF_MAX)?;
+
+        match response.status_words() {
+            StatusWords::Success => Ok(Zeroizing::new(response.data().to_vec())),
+            StatusWords::NotFoundError | StatusWords::ReferenceNotFoundError => {
+                Err(Error::NotFound)
+            }
+            other => {
+                error!(
+                    "failed to get metadata for key {:02x}: {:?}",
+                    key_ref, other
+                );
+                Err(other.to_error())
+            }
+        }
+    }
+
+    /// Get the raw metadata for a key reference, or `None` if the device
+    /// doesn't support GET METADATA (i.e. prior to YubiKey 5.3), in which
+    /// case callers are expected to fall back to other means of detection.
+    pub(crate) fn try_get_metadata(&self, key_ref: u8) -> Result<Option<Buffer>, Error> {
+        match self.get_metadata(key_ref) {
+            Ok(data) => Ok(Some(data)),
+            Err(Error::NotSupported) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the public key for the given slot.
+    ///
+    /// Prefers the key metadata, falling back to the slot's certificate on
+    /// devices which don't support GET METADATA.
+    pub fn get_public_key(&self, slot: SlotId) -> Result<PublicKeyInfo, Error> {
+        if let Some(data) = self.try_get_metadata(slot.into())? {
+            return SlotMetadata::parse(&data)?
+                .public_key
+                .ok_or(Error::NotFound);
+        }
+
+        let buf = certificate::read_certificate(self, slot)?;
+
+        if buf.is_empty() {
+            return Err(Error::NotFound);
+        }
+
+        Ok(Certificate::from_bytes(buf)?.subject_pki().clone())
+    }
+
+    /// Read the certificate in every populated slot as stored, along with
+    /// how it is stored, e.g. for backup.
+    ///
+    /// Only empty slots are skipped: any other error reading a slot's
+    /// certificate aborts the export.
+    pub fn export_certificates(&self) -> Result<Vec<(SlotId, Buffer, CertInfo)>, Error> {
+        let mut certs = vec![];
+
+        for &slot in SLOTS.iter() {
+            let buf = match self.fetch_object(slot.object_id()?) {
+                Ok(buf) => buf,
+                Err(Error::NotFound) => continue,
+                Err(e) => return Err(e),
+            };
+
+            // deleting a certificate leaves an empty object behind
+            if buf.is_empty() {
+                continue;
+            }
+
+            let (cert, certinfo) = certificate::parse_certificate_object(&buf)?;
+            certs.push((slot, cert, certinfo));
+        }
+
+        Ok(certs)
+    }
+
+    /// Write certificates exported with [`Transaction::export_certificates`]
+    /// back to their slots. Requires management key authentication.
+    pub fn import_certificates(&self, certs: &[(SlotId, Buffer, CertInfo)]) -> Result<(), Error> {
+        for (slot, cert, certinfo) in certs {
+            certificate::write_certificate(self, *slot, Some(cert), *certinfo)?;
         }
+
+        Ok(())
+    }
+
+    /// Read and parse the certificate in the given slot.
+    #[cfg(feature = "x509")]
+    pub fn read_certificate_info(&self, slot: SlotId) -> Result<CertificateInfo, Error> {
+        let buf = certificate::read_certificate(self, slot)?;
+
+        if buf.is_empty() {
+            return Err(Error::NotFound);
+        }
+
+        let cert = Certificate::from_bytes(buf).map_err(|e| match e {
+            Error::InvalidObject => Error::ParseError,
+            other => other,
+        })?;
+
+        Ok(CertificateInfo::from(&cert))
     }
+
+    /// Does the certificate stored in the given slot belong to the slot's key?
+    ///
+    /// The key is read from the slot metadata, so this requires a YubiKey 5.3+
+    /// and returns [`Error::NotSupported`] otherwise. Returns
+    /// [`Error::NotFound`] if the slot has no key or no certificate.
+    pub fn verify_cert_matches_key(&self, slot: SlotId) -> Result<bool, Error> {
+        let metadata = match self.try_get_metadata(slot.into())? {
+            Some(data) => SlotMetadata::parse(&data)?,
+            None => {
+                error!(
+                    "reading the public key of slot {:?} requires GET METADATA",
+                    slot
+                );
+                return Err(Error::NotSupported);
+            }
+        };
+
+        let public_key = metadata.public_key.ok_or(Error::NotFound)?;
+        let buf = certificate::read_certificate(self, slot)?;
+
+        if buf.is_empty() {
+            return Err(Error::NotFound);
+        }
+
+        let cert = Certificate::from_bytes(buf)?;
+
+        if cert.subject_pki() != &public_key {
+            warn!(
+                "certificate in slot {:?} does not match the slot's key",
+                slot
+            );
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    /// Does the given slot contain a key?
+    ///
+    /// Uses GET METADATA where supported (YubiKey 5.3+). Otherwise it
+    /// attempts to attest the slot, which has no side effects and fails with
+    /// "not found" for empty slots.
+    pub fn slot_has_key(&self, slot: SlotId) -> Result<bool, Error> {
+        match self.try_get_metadata(slot.into()) {
+            Ok(Some(_)) => return Ok(true),
+            Ok(None) => (),
+            Err(Error::NotFound) => return Ok(false),
+            Err(e) => return Err(e),
+        }
+
+        let templ = ApduTemplate::new(Ins::Attest, slot.into(), 0);
+        let response = self.transfer_data(templ, &[], CB_OBJ_MAX)?;
+
+        match response.status_words() {
+            StatusWords::Success => Ok(true),
+            // Imported keys can't be attested
+            StatusWords::IncorrectParamError => Ok(true),
+            StatusWords::NotFoundError | StatusWords::ReferenceNotFoundError => Ok(false),
+            other => {
+                error!(
+                    "couldn't determine if slot {:?} has a key: {:?}",
+                    slot, other
+                );
+                Err(Error::GenericError)
+            }
+        }
+    }
+}
+
+/// Check that a raw object tag is 3 bytes long, as all PIV data objects are.
+fn check_raw_object_tag(tag: u32) -> Result<(), Error> {
+    if tag <= 0xffff || tag > 0x00ff_ffff {
+        error!("object tag {:x} is not 3 bytes long", tag);
+        return Err(Error::InvalidObject);
+    }
+
+    Ok(())
 }
@@ -0,0 +1,182 @@
+//! Card transports: the low-level channel APDUs are exchanged over.
+//!
+//! By default a [`YubiKey`][`crate::YubiKey`] talks to the card through
+//! PC/SC. Any other [`CardTransport`] can be used instead via
+//! [`YubiKey::open_with_transport`][`crate::YubiKey::open_with_transport`],
+//! e.g. the in-memory [`MockTransport`] (enabled with the `mock` feature)
+//! which allows exercising the crate without a physical YubiKey, or the
+//! [`ReplayTransport`] which replays an APDU trace captured from a real device
+//! with [`RecordTransport`].
+
+use crate::error::Error;
+use log::debug;
+
+#[cfg(feature = "mock")]
+mod mock;
+mod replay;
+
+#[cfg(feature = "mock")]
+pub use self::mock::MockTransport;
+pub use self::replay::{RecordTransport, ReplayTransport};
+
+/// A channel over which serialized APDUs can be exchanged with a card.
+pub trait CardTransport: Send {
+    /// Transmit a single serialized command APDU and write the response
+    /// (including the trailing status words) into `recv`, returning the
+    /// number of bytes written.
+    fn transmit(&self, send: &[u8], recv: &mut [u8]) -> Result<usize, Error>;
+
+    /// Human-readable name of this transport, reported by
+    /// [`YubiKey::name`][`crate::YubiKey::name`].
+    fn name(&self) -> String {
+        String::from("custom transport")
+    }
+
+    /// Physical interface the card is connected over, which determines how
+    /// commands are split into APDUs. Defaults to [`Interface::Usb`].
+    fn interface(&self) -> Interface {
+        Interface::Usb
+    }
+
+    /// Called whenever a transaction with the card begins. Returning an
+    /// error aborts the transaction, e.g. with [`pcsc::Error::ResetCard`]
+    /// when the card was reset.
+    fn begin_transaction(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Transmit directly over a PC/SC card handle.
+///
+/// Unlike the connections opened by [`YubiKey::open`][`crate::YubiKey::open`],
+/// exchanges made this way are not wrapped in a PC/SC transaction.
+impl CardTransport for pcsc::Card {
+    fn transmit(&self, send: &[u8], recv: &mut [u8]) -> Result<usize, Error> {
+        Ok(pcsc::Card::transmit(self, send, recv)?.len())
+    }
+
+    fn interface(&self) -> Interface {
+        match self.status2_owned() {
+            Ok(status) => {
+                let reader_name = status
+                    .reader_names()
+                    .first()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+
+                Interface::detect(&reader_name, status.atr())
+            }
+            Err(e) => {
+                debug!("couldn't get card status; assuming USB: {}", e);
+                Interface::Usb
+            }
+        }
+    }
+}
+
+/// Maximum command data sent per APDU over NFC: some contactless readers
+/// mishandle frames carrying a full 255 bytes of command data.
+const NFC_CHUNK_SIZE: usize = 0x80;
+
+/// Substrings of PC/SC reader names identifying contactless readers
+const CONTACTLESS_READER_NAMES: &[&str] = &["contactless", "nfc", "picc"];
+
+/// Physical interface a card is connected over.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Interface {
+    /// Contact interface (USB)
+    Usb,
+
+    /// Contactless interface (NFC)
+    Nfc,
+}
+
+impl Interface {
+    /// Guess the interface from a PC/SC reader name and the card's ATR.
+    ///
+    /// Readers are recognized as contactless either by name, or by the ATR
+    /// they synthesize for contactless cards (`3B 8n 80 01 ...`, see PC/SC
+    /// part 3, section 3.1.3.2.3), which covers readers whose names don't
+    /// mention NFC.
+    pub fn detect(reader_name: &str, atr: &[u8]) -> Self {
+        let reader_name = reader_name.to_ascii_lowercase();
+
+        let contactless_name = CONTACTLESS_READER_NAMES
+            .iter()
+            .any(|name| reader_name.contains(name));
+
+        let contactless_atr =
+            atr.len() >= 4 && atr[0] == 0x3b && atr[1] & 0xf0 == 0x80 && atr[2..4] == [0x80, 0x01];
+
+        if contactless_name || contactless_atr {
+            Interface::Nfc
+        } else {
+            Interface::Usb
+        }
+    }
+
+    /// Maximum amount of command data sent in a single APDU when a command
+    /// is split into a chain of APDUs.
+    ///
+    /// Short (non-extended) APDUs are used over either interface.
+    pub fn chunk_size(self) -> usize {
+        match self {
+            Interface::Usb => 0xff,
+            Interface::Nfc => NFC_CHUNK_SIZE,
+        }
+    }
+
+    /// Maximum amount of response data requested per GET RESPONSE when the
+    /// card returns a long response (e.g. an attestation certificate) in
+    /// parts.
+    ///
+    /// Some contactless readers can't relay full 256 byte responses, so
+    /// smaller parts are requested over NFC.
+    pub fn response_size(self) -> usize {
+        match self {
+            Interface::Usb => 0x100,
+            Interface::Nfc => NFC_CHUNK_SIZE,
+        }
+    }
+}
+
+/// Connection to a card: either a PC/SC card handle or a custom transport.
+pub(crate) enum Connection {
+    /// PC/SC card handle
+    Pcsc(pcsc::Card),
+
+    /// Custom transport
+    Transport(Box<dyn CardTransport>),
+}
+
+impl Connection {
+    /// Reconnect to the card. This is a no-op for custom transports.
+    pub(crate) fn reconnect(
+        &mut self,
+        share_mode: pcsc::ShareMode,
+        disposition: pcsc::Disposition,
+    ) -> Result<(), Error> {
+        match self {
+            Connection::Pcsc(card) => {
+                Ok(card.reconnect(share_mode, pcsc::Protocols::T1, disposition)?)
+            }
+            Connection::Transport(_) => Ok(()),
+        }
+    }
+
+    /// Disconnect from the card. This is a no-op for custom transports.
+    pub(crate) fn disconnect(self, disposition: pcsc::Disposition) -> Result<(), Error> {
+        match self {
+            Connection::Pcsc(card) => card.disconnect(disposition).map_err(|(_, e)| e.into()),
+            Connection::Transport(_) => Ok(()),
+        }
+    }
+
+    /// Physical interface the card is connected over.
+    pub(crate) fn interface(&self) -> Interface {
+        match self {
+            Connection::Pcsc(card) => CardTransport::interface(card),
+            Connection::Transport(transport) => transport.interface(),
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! In-memory mock card transport for testing without a YubiKey
+
+use super::CardTransport;
+use crate::error::Error;
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+/// Scripted response handler
+type Handler = Box<dyn FnMut(&[u8]) -> Vec<u8> + Send>;
+
+/// A single scripted exchange
+enum Exchange {
+    /// Expect exactly this command, and reply with the given response
+    Expect { command: Vec<u8>, response: Vec<u8> },
+
+    /// Accept any command, and reply with the given response
+    Respond(Vec<u8>),
+
+    /// Accept any command, and compute the response from it
+    RespondWith(Handler),
+
+    /// Fail the next transaction as if the card was reset
+    Reset,
+}
+
+#[derive(Default)]
+struct State {
+    /// Remaining scripted exchanges
+    script: VecDeque<Exchange>,
+
+    /// Commands transmitted so far
+    transmitted: Vec<Vec<u8>>,
+}
+
+/// In-memory [`CardTransport`] which answers a scripted sequence of APDUs
+/// with canned responses.
+///
+/// Clones share the same script, so a clone can be kept around to inspect
+/// the transmitted commands after handing the transport to a
+/// [`YubiKey`][`crate::YubiKey`].
+///
+/// Transmitting a command which doesn't match the script, or transmitting
+/// past the end of the script, panics.
+#[derive(Clone, Default)]
+pub struct MockTransport {
+    state: Arc<Mutex<State>>,
+}
+
+impl MockTransport {
+    /// Create a new mock transport with an empty script
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Expect the next command to be exactly `command`, and reply with
+    /// `response` (which must include the status words).
+    pub fn expect(&self, command: impl AsRef<[u8]>, response: impl AsRef<[u8]>) -> &Self {
+        self.push(Exchange::Expect {
+            command: command.as_ref().to_vec(),
+            response: response.as_ref().to_vec(),
+        })
+    }
+
+    /// Reply to the next command, whatever it is, with `response`
+    pub fn respond(&self, response: impl AsRef<[u8]>) -> &Self {
+        self.push(Exchange::Respond(response.as_ref().to_vec()))
+    }
+
+    /// Reply to the next command with the result of calling `f` on it
+    pub fn respond_with<F>(&self, f: F) -> &Self
+    where
+        F: FnMut(&[u8]) -> Vec<u8> + Send + 'static,
+    {
+        self.push(Exchange::RespondWith(Box::new(f)))
+    }
+
+    /// Fail the next transaction with [`pcsc::Error::ResetCard`], as PC/SC
+    /// does after the card was reset
+    pub fn reset(&self) -> &Self {
+        self.push(Exchange::Reset)
+    }
+
+    /// Commands transmitted so far, in order
+    pub fn transmitted(&self) -> Vec<Vec<u8>> {
+        self.state.lock().unwrap().transmitted.clone()
+    }
+
+    /// Number of scripted exchanges which haven't happened yet
+    pub fn remaining(&self) -> usize {
+        self.state.lock().unwrap().script.len()
+    }
+
+    /// Panic if any scripted exchanges haven't happened yet
+    pub fn assert_done(&self) {
+        let remaining = self.remaining();
+        assert!(remaining == 0, "{} scripted APDU(s) never sent", remaining);
+    }
+
+    fn push(&self, exchange: Exchange) -> &Self {
+        self.state.lock().unwrap().script.push_back(exchange);
+        self
+    }
+}
+
+impl CardTransport for MockTransport {
+    fn transmit(&self, send: &[u8], recv: &mut [u8]) -> Result<usize, Error> {
+        let mut state = self.state.lock().unwrap();
+        state.transmitted.push(send.to_vec());
+
+        let response = match state.script.pop_front() {
+            Some(Exchange::Expect { command, response }) => {
+                assert_eq!(
+                    command, send,
+                    "unexpected APDU (left: expected, right: sent)"
+                );
+                response
+            }
+            Some(Exchange::Respond(response)) => response,
+            Some(Exchange::RespondWith(mut f)) => f(send),
+            Some(Exchange::Reset) => panic!("APDU sent instead of a card reset: {:02x?}", send),
+            None => panic!("unexpected APDU after end of script: {:02x?}", send),
+        };
+
+        if response.len() > recv.len() {
+            return Err(Error::SizeError);
+        }
+
+        recv[..response.len()].copy_from_slice(&response);
+        Ok(response.len())
+    }
+
+    fn name(&self) -> String {
+        String::from("mock")
+    }
+
+    fn begin_transaction(&self) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+
+        match state.script.front() {
+            Some(Exchange::Reset) => {
+                state.script.pop_front();
+                Err(pcsc::Error::ResetCard.into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for MockTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("MockTransport")
+            .field("remaining", &state.script.len())
+            .field("transmitted", &state.transmitted.len())
+            .finish()
+    }
+}
@@ -0,0 +1,222 @@
+//! Recording and replaying of APDU traces
+//!
+//! Traces are plain text, with one APDU per line: commands sent by the host
+//! are prefixed with `>>>` and responses from the card with `<<<`, followed by
+//! the hex-encoded bytes. Anything before the marker (e.g. a log prefix) is
+//! ignored, as are lines without a marker and lines whose payload isn't a byte
+//! string (e.g. the `CLA 00 INS a4 ..` lines logged at `trace` level):
+//!
+//! ```text
+//! >>> 00a4040005a000000308
+//! <<< 9000
+//! ```
+//!
+//! Bytes may also be given as a list of decimal numbers, as logged by
+//! `Transaction::transmit` (e.g. `>>> [0, 253, 0, 0, 0]`).
+
+use super::CardTransport;
+use crate::error::Error;
+use log::error;
+use std::{collections::VecDeque, fs, io::Write, path::Path, sync::Mutex};
+use subtle_encoding::hex;
+
+/// Marker for commands sent by the host
+const COMMAND_MARKER: &str = ">>>";
+
+/// Marker for responses from the card
+const RESPONSE_MARKER: &str = "<<<";
+
+/// [`CardTransport`] which plays the card side of a recorded APDU trace.
+///
+/// Each command sent by the host must match the next recorded command
+/// exactly, otherwise an error is returned.
+#[derive(Debug)]
+pub struct ReplayTransport {
+    exchanges: Mutex<VecDeque<(Vec<u8>, Vec<u8>)>>,
+}
+
+impl ReplayTransport {
+    /// Load a trace from the file at the given path
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let trace = fs::read_to_string(path).map_err(|e| {
+            error!("couldn't read APDU trace from {}: {}", path.display(), e);
+            Error::GenericError
+        })?;
+
+        Self::parse(&trace)
+    }
+
+    /// Parse a trace
+    pub fn parse(trace: &str) -> Result<Self, Error> {
+        let mut exchanges = VecDeque::new();
+        let mut command = None;
+
+        for (n, line) in trace.lines().enumerate() {
+            let (is_command, payload) = if let Some(i) = line.find(COMMAND_MARKER) {
+                (true, &line[i + COMMAND_MARKER.len()..])
+            } else if let Some(i) = line.find(RESPONSE_MARKER) {
+                (false, &line[i + RESPONSE_MARKER.len()..])
+            } else {
+                continue;
+            };
+
+            let bytes = match parse_bytes(payload.trim()) {
+                Some(bytes) => bytes,
+                None => continue,
+            };
+
+            match (is_command, command.take()) {
+                (true, None) => command = Some(bytes),
+                (false, Some(cmd)) => exchanges.push_back((cmd, bytes)),
+                (true, Some(_)) => {
+                    error!("APDU trace line {}: command without a response", n + 1);
+                    return Err(Error::ParseError);
+                }
+                (false, None) => {
+                    error!("APDU trace line {}: response without a command", n + 1);
+                    return Err(Error::ParseError);
+                }
+            }
+        }
+
+        if command.is_some() {
+            error!("APDU trace ends with a command without a response");
+            return Err(Error::ParseError);
+        }
+
+        Ok(Self {
+            exchanges: Mutex::new(exchanges),
+        })
+    }
+
+    /// Number of recorded exchanges which haven't been replayed yet
+    pub fn remaining(&self) -> usize {
+        self.exchanges.lock().unwrap().len()
+    }
+}
+
+impl CardTransport for ReplayTransport {
+    fn transmit(&self, send: &[u8], recv: &mut [u8]) -> Result<usize, Error> {
+        let (command, response) = self.exchanges.lock().unwrap().pop_front().ok_or_else(|| {
+            error!("unexpected APDU after end of trace: {:02x?}", send);
+            Error::GenericError
+        })?;
+
+        if command != send {
+            error!(
+                "unexpected APDU: expected {:02x?}, got {:02x?}",
+                command, send
+            );
+            return Err(Error::GenericError);
+        }
+
+        if response.len() > recv.len() {
+            return Err(Error::SizeError);
+        }
+
+        recv[..response.len()].copy_from_slice(&response);
+        Ok(response.len())
+    }
+
+    fn name(&self) -> String {
+        String::from("replay")
+    }
+}
+
+/// [`CardTransport`] wrapper which records every exchange with the wrapped
+/// transport as a trace, in the format read by [`ReplayTransport`].
+///
+/// NOTE: traces contain every byte sent to the card, including PINs and
+/// management keys.
+#[derive(Debug)]
+pub struct RecordTransport<T, W> {
+    inner: T,
+    writer: Mutex<W>,
+}
+
+impl<T, W> RecordTransport<T, W>
+where
+    T: CardTransport,
+    W: Write + Send,
+{
+    /// Record exchanges with `inner` to `writer`
+    pub fn new(inner: T, writer: W) -> Self {
+        Self {
+            inner,
+            writer: Mutex::new(writer),
+        }
+    }
+
+    /// Stop recording, returning the wrapped transport and the writer
+    pub fn into_inner(self) -> (T, W) {
+        (self.inner, self.writer.into_inner().unwrap())
+    }
+}
+
+impl<T> RecordTransport<T, fs::File>
+where
+    T: CardTransport,
+{
+    /// Record exchanges with `inner` to a newly created file at `path`
+    pub fn create(inner: T, path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let file = fs::File::create(path).map_err(|e| {
+            error!("couldn't create APDU trace {}: {}", path.display(), e);
+            Error::GenericError
+        })?;
+
+        Ok(Self::new(inner, file))
+    }
+}
+
+impl<T, W> CardTransport for RecordTransport<T, W>
+where
+    T: CardTransport,
+    W: Write + Send,
+{
+    fn transmit(&self, send: &[u8], recv: &mut [u8]) -> Result<usize, Error> {
+        let len = self.inner.transmit(send, recv)?;
+
+        let mut writer = self.writer.lock().unwrap();
+        writeln!(writer, "{} {}", COMMAND_MARKER, hex_string(send))
+            .and_then(|_| writeln!(writer, "{} {}", RESPONSE_MARKER, hex_string(&recv[..len])))
+            .and_then(|_| writer.flush())
+            .map_err(|e| {
+                error!("couldn't record APDU trace: {}", e);
+                Error::GenericError
+            })?;
+
+        Ok(len)
+    }
+
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+}
+
+/// Hex encode the given bytes
+fn hex_string(bytes: &[u8]) -> String {
+    String::from_utf8(hex::encode(bytes)).unwrap()
+}
+
+/// Parse a byte string given either as hex or a list of decimal numbers
+fn parse_bytes(s: &str) -> Option<Vec<u8>> {
+    if s.starts_with('[') && s.ends_with(']') {
+        let list = s[1..s.len() - 1].trim();
+
+        if list.is_empty() {
+            return Some(vec![]);
+        }
+
+        list.split(',').map(|b| b.trim().parse().ok()).collect()
+    } else {
+        let digits: String = s.split_whitespace().collect();
+
+        if digits.is_empty() {
+            return None;
+        }
+
+        hex::decode(digits.as_bytes()).ok()
+    }
+}
@@ -31,32 +31,45 @@
 // OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use crate::{
-    apdu::{Ins, APDU},
+    apdu::{
+        ApduTemplate, GeneralAuthenticate, Ins, APDU, TAG_AUTH_CHALLENGE, TAG_AUTH_RESPONSE,
+        TAG_AUTH_WITNESS,
+    },
     cccid::CCC,
+    certificate::PublicKeyInfo,
     chuid::CHUID,
     config::Config,
+    discovery::Discovery,
     error::Error,
+    keepalive::Keepalive,
+    key::{SlotId, SlotMetadata},
     mgm::MgmKey,
+    middleware::Middleware,
+    policy::TouchPolicy,
     readers::{Reader, Readers},
+    serialization::Tlv,
     transaction::Transaction,
+    transport::{CardTransport, Connection, Interface},
 };
-use log::{error, info};
-use pcsc::Card;
+use log::{debug, error, info};
 use std::{
     convert::{TryFrom, TryInto},
+    ffi::CString,
     fmt::{self, Display},
     str::FromStr,
+    time::{Duration, Instant},
 };
 
 use crate::{
     apdu::StatusWords, metadata::AdminData, transaction::ChangeRefAction, Buffer, ObjectId,
-    MGMT_AID, TAG_ADMIN_FLAGS_1, TAG_ADMIN_TIMESTAMP,
+    CB_OBJ_MAX, CB_OBJ_MAX_NEO, MGMT_AID, TAG_ADMIN_FLAGS_1, TAG_ADMIN_TIMESTAMP,
 };
 use getrandom::getrandom;
-#[cfg(feature = "untested")]
 use secrecy::ExposeSecret;
 #[cfg(feature = "untested")]
 use std::time::{SystemTime, UNIX_EPOCH};
+use subtle::ConstantTimeEq;
+use zeroize::Zeroizing;
 
 /// Flag for PUK blocked
 pub(crate) const ADMIN_FLAGS_1_PUK_BLOCKED: u8 = 0x01;
@@ -64,10 +77,35 @@ pub(crate) const ADMIN_FLAGS_1_PUK_BLOCKED: u8 = 0x01;
 /// 3DES authentication
 pub(crate) const ALGO_3DES: u8 = 0x03;
 
-/// Card management key
-pub(crate) const KEY_CARDMGM: u8 = 0x9b;
+/// Size of the device configuration lock code
+pub const CB_CONFIG_LOCK: usize = 16;
+
+/// Name YubiKeys include in the historical bytes of their ATR
+const ATR_YUBIKEY: &[u8] = b"yubikey";
+
+/// Device config: new configuration lock code
+const TAG_CONFIG_LOCK: u8 = 0x0a;
+
+/// Device config: current configuration lock code
+const TAG_CONFIG_UNLOCK: u8 = 0x0b;
+
+/// Device config: is PIN complexity enforced (YubiKey 5.7+)
+const TAG_PIN_COMPLEXITY: u8 = 0x16;
+
+/// Device config: applications which can't be reset (YubiKey 5.7+)
+const TAG_RESET_BLOCKED: u8 = 0x18;
+
+/// Capability bit of the PIV application in device config
+const CAPABILITY_PIV: u16 = 0x0010;
+
+/// Factory default PIN
+const DEFAULT_PIN: &[u8] = b"123456";
 
-const TAG_DYN_AUTH: u8 = 0x7c;
+/// Factory default PUK
+const DEFAULT_PUK: &[u8] = b"12345678";
+
+/// How long the YubiKey remembers a touch for keys with [`TouchPolicy::Cached`]
+const TOUCH_CACHE_TIMEOUT: Duration = Duration::from_secs(15);
 
 /// Cached YubiKey PIN
 pub type CachedPin = secrecy::SecretVec<u8>;
@@ -88,6 +126,30 @@ impl From<Serial> for u32 {
     }
 }
 
+impl Serial {
+    /// Big-endian hexadecimal encoding of the serial, zero-padded to 8 digits.
+    pub fn to_hex(self) -> String {
+        format!("{:08x}", self.0)
+    }
+
+    /// Big-endian [modhex] encoding of the serial, as used in Yubico OTP
+    /// public IDs.
+    ///
+    /// [modhex]: https://developers.yubico.com/yubico-c/Manuals/modhex.1.html
+    pub fn to_modhex(self) -> String {
+        const MODHEX: &[u8; 16] = b"cbdefghijklnrtuv";
+
+        let mut encoded = String::with_capacity(8);
+
+        for byte in &self.0.to_be_bytes() {
+            encoded.push(char::from(MODHEX[usize::from(byte >> 4)]));
+            encoded.push(char::from(MODHEX[usize::from(byte & 0xf)]));
+        }
+
+        encoded
+    }
+}
+
 impl FromStr for Serial {
     type Err = Error;
 
@@ -103,7 +165,9 @@ impl Display for Serial {
 }
 
 /// YubiKey Version
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+///
+/// Versions are ordered by major, then minor, then patch version.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
 pub struct Version {
     /// Major version component
     pub major: u8,
@@ -124,6 +188,11 @@ impl Version {
             patch: bytes[2],
         }
     }
+
+    /// Is this version `major.minor.patch` or newer?
+    pub fn at_least(self, major: u8, minor: u8, patch: u8) -> bool {
+        self >= Version::new([major, minor, patch])
+    }
 }
 
 impl Display for Version {
@@ -132,6 +201,216 @@ impl Display for Version {
     }
 }
 
+/// YubiKey series
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Series {
+    /// YubiKey NEO
+    Neo,
+
+    /// YubiKey 4
+    YubiKey4,
+}
+
+/// What a card's ATR reveals about a YubiKey, before connecting to it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ModelHint {
+    /// Interface the YubiKey is connected over
+    pub interface: Interface,
+
+    /// YubiKey series, if the ATR identifies it. YubiKey 5 ATRs don't, so
+    /// use [`YubiKey::version`] to tell those apart.
+    pub series: Option<Series>,
+}
+
+/// What to do with the card when disconnecting from it, i.e. the
+/// disposition passed to `SCardDisconnect`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Disposition {
+    /// Leave the card as it is. Its state, including a verified PIN and
+    /// the authenticated management key, remains available to whichever
+    /// application connects next.
+    LeaveCard,
+
+    /// Reset the card (warm reset), which clears the verified PIN, the
+    /// management key authentication and any selected application.
+    ResetCard,
+
+    /// Power the card down (cold reset), which clears all of its volatile
+    /// state like [`Disposition::ResetCard`].
+    UnpowerCard,
+
+    /// Eject the card, for readers with a card ejection mechanism. Most
+    /// readers (and all YubiKeys) don't have one and treat this as
+    /// [`Disposition::LeaveCard`].
+    EjectCard,
+}
+
+impl From<Disposition> for pcsc::Disposition {
+    fn from(disposition: Disposition) -> pcsc::Disposition {
+        match disposition {
+            Disposition::LeaveCard => pcsc::Disposition::LeaveCard,
+            Disposition::ResetCard => pcsc::Disposition::ResetCard,
+            Disposition::UnpowerCard => pcsc::Disposition::UnpowerCard,
+            Disposition::EjectCard => pcsc::Disposition::EjectCard,
+        }
+    }
+}
+
+/// Reference of a PIN (or PUK) to verify or change, i.e. the P2 byte of
+/// VERIFY and CHANGE REFERENCE (SP 800-73-4 Part 2, section 3.2).
+///
+/// YubiKeys only implement [`PinReference::Application`] and
+/// [`PinReference::Puk`]; the others are for PIV cards with additional PINs.
+/// Using a reference the card doesn't implement fails with
+/// [`Error::NotFound`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PinReference {
+    /// Global PIN (`0x00`)
+    Global,
+
+    /// PIV Card Application PIN (`0x80`)
+    Application,
+
+    /// PIN Unblocking Key (`0x81`)
+    Puk,
+
+    /// Pairing Code (`0x98`): 8 digits which establish the virtual contact
+    /// interface over NFC, e.g. for derived credential (PIV-D) issuance
+    /// (SP 800-73-4 Part 1, section 4.1.6)
+    PairingCode,
+
+    /// Any other key reference, e.g. an occupational or profile-specific PIN
+    Other(u8),
+}
+
+impl From<PinReference> for u8 {
+    fn from(reference: PinReference) -> u8 {
+        match reference {
+            PinReference::Global => 0x00,
+            PinReference::Application => 0x80,
+            PinReference::Puk => 0x81,
+            PinReference::PairingCode => 0x98,
+            PinReference::Other(reference) => reference,
+        }
+    }
+}
+
+/// Outcome of a biometric (on-card fingerprint) verification attempt.
+///
+/// When verification doesn't succeed, callers may fall back to
+/// [`YubiKey::verify_pin`] instead.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BioVerifyResult {
+    /// Fingerprint matched: the PIN is considered verified
+    Matched,
+
+    /// No fingerprint match has been performed yet: the user needs to touch
+    /// the sensor
+    MatchRequired,
+
+    /// Fingerprint didn't match
+    NoMatch {
+        /// Remaining biometric attempts
+        tries: u8,
+    },
+
+    /// Biometric verification is blocked: the PIN must be used instead
+    Blocked,
+}
+
+/// PIN, PUK and management key state, as returned by
+/// [`YubiKey::auth_status`].
+///
+/// Fields which are `None` can't be determined without side effects on the
+/// connected device, which is mostly the case on firmware without GET
+/// METADATA (i.e. prior to YubiKey 5.3).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct AuthStatus {
+    /// Remaining PIN attempts. Only available on older firmware while the
+    /// PIN isn't verified, as the retry counter is then read by VERIFY.
+    pub pin_retries: Option<u8>,
+
+    /// Remaining PUK attempts (YubiKey 5.3+ only).
+    pub puk_retries: Option<u8>,
+
+    /// Is the PIN verified in the current session?
+    pub pin_verified: bool,
+
+    /// Is the management key the factory default? (YubiKey 5.3+ only)
+    pub mgm_key_default: Option<bool>,
+
+    /// Raw algorithm identifier of the management key, e.g. `0x03` (3DES).
+    /// Devices prior to YubiKey 5.3 only support 3DES, which is reported
+    /// without querying them.
+    pub mgm_key_algorithm: u8,
+}
+
+/// Settings applied by [`YubiKey::initialize`] when provisioning a YubiKey.
+///
+/// Fields left as `None` keep their current (factory default) value.
+#[derive(Default)]
+pub struct InitConfig {
+    /// Reset the PIV application first. This destroys all keys and
+    /// certificates, and requires both the PIN and the PUK to be blocked.
+    pub reset: bool,
+
+    /// Number of PIN and PUK retries
+    pub retries: Option<(u8, u8)>,
+
+    /// New PIN
+    pub pin: Option<Zeroizing<Vec<u8>>>,
+
+    /// New PUK
+    pub puk: Option<Zeroizing<Vec<u8>>>,
+
+    /// New management key
+    pub mgm_key: Option<MgmKey>,
+
+    /// Require touching the YubiKey to use the new management key
+    pub require_touch: bool,
+}
+
+/// PIN complexity rules enforced by a YubiKey, see
+/// [`YubiKey::pin_complexity`].
+///
+/// The device only reports whether PIN complexity is enabled, so the rules
+/// are those documented for YubiKey 5.7. Besides these, the device rejects a
+/// short list of commonly used PINs, which isn't checked on the host.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PinComplexity {
+    /// Minimum PIN length
+    pub min_length: usize,
+
+    /// Minimum number of distinct characters
+    pub min_unique_chars: usize,
+}
+
+impl Default for PinComplexity {
+    fn default() -> Self {
+        PinComplexity {
+            min_length: 6,
+            min_unique_chars: 2,
+        }
+    }
+}
+
+impl PinComplexity {
+    /// Check a new PIN (or PUK) against these rules, returning
+    /// [`Error::PinComplexity`] if it doesn't meet them.
+    pub fn check(&self, pin: &[u8]) -> Result<(), Error> {
+        let mut unique = Zeroizing::new(pin.to_vec());
+        unique.sort_unstable();
+        unique.dedup();
+
+        if pin.len() < self.min_length || unique.len() < self.min_unique_chars {
+            error!("PIN does not meet the device's complexity requirements");
+            return Err(Error::PinComplexity);
+        }
+
+        Ok(())
+    }
+}
+
 /// YubiKey Device: this is the primary API for opening a session and
 /// performing various operations.
 ///
@@ -140,11 +419,22 @@ impl Display for Version {
 // TODO(tarcieri): reduce coupling to internal fields via `pub(crate)`
 #[cfg_attr(not(feature = "untested"), allow(dead_code))]
 pub struct YubiKey {
-    pub(crate) card: Card,
+    pub(crate) card: Connection,
     pub(crate) name: String,
     pub(crate) pin: Option<CachedPin>,
     pub(crate) version: Version,
     pub(crate) serial: Serial,
+    pub(crate) aid: Option<Vec<u8>>,
+    pub(crate) is_yubikey: bool,
+    pub(crate) interface: Interface,
+    pub(crate) cache_mgm_auth: bool,
+    pub(crate) mgm_authenticated: bool,
+    pub(crate) touch_policies: Vec<(SlotId, TouchPolicy)>,
+    pub(crate) public_keys: Vec<(SlotId, PublicKeyInfo)>,
+    pub(crate) last_touch: Option<Instant>,
+    pub(crate) keepalive: Option<Keepalive>,
+    pub(crate) exclusive: bool,
+    pub(crate) middleware: Vec<Box<dyn Middleware>>,
 }
 
 impl YubiKey {
@@ -177,6 +467,14 @@ impl YubiKey {
         Err(Error::NotFound)
     }
 
+    /// Open a connection to a YubiKey like [`YubiKey::open`], refusing it
+    /// with [`Error::FirmwareTooOld`] if its firmware is older than `min`.
+    pub fn open_with_min_version(min: Version) -> Result<Self, Error> {
+        let yubikey = Self::open()?;
+        yubikey.require_version(min)?;
+        Ok(yubikey)
+    }
+
     /// Open a YubiKey with a specific serial number.
     pub fn open_by_serial(serial: Serial) -> Result<Self, Error> {
         let mut readers = Readers::open().map_err(|e| match e {
@@ -186,13 +484,13 @@ impl YubiKey {
             other => other,
         })?;
 
-        for reader in readers.iter()? {
+        for reader in readers.list()? {
             let yubikey = match reader.open() {
                 Ok(yk) => yk,
                 Err(_) => continue,
             };
 
-            if serial == yubikey.serial() {
+            if yubikey.serial() == Some(serial) {
                 return Ok(yubikey);
             }
         }
@@ -201,23 +499,185 @@ impl YubiKey {
         Err(Error::NotFound)
     }
 
+    /// Open the YubiKey in the reader with the given (zero-based) index, in
+    /// the order enumerated by [`Readers::iter`].
+    ///
+    /// Returns [`Error::NotFound`] if there are fewer readers than that.
+    pub fn open_by_index(index: usize) -> Result<Self, Error> {
+        let mut readers = Readers::open().map_err(|e| match e {
+            Error::PcscError {
+                inner: Some(pcsc::Error::NoReadersAvailable),
+            } => Error::NotFound,
+            other => other,
+        })?;
+
+        match readers.iter()?.nth(index) {
+            Some(reader) => reader.open(),
+            None => {
+                error!("no reader with index {}", index);
+                Err(Error::NotFound)
+            }
+        }
+    }
+
+    /// Wait up to `timeout` for a YubiKey to be inserted into any reader, and
+    /// open it.
+    ///
+    /// Returns immediately if a YubiKey is already present. Cards are
+    /// recognized as YubiKeys by their ATR (see [`YubiKey::identify_from_atr`]),
+    /// and readers attached while waiting are watched too, where the PC/SC
+    /// implementation supports reader hotplug notifications. Returns
+    /// [`Error::Timeout`] if no YubiKey appears in time.
+    pub fn wait_for_device(timeout: Duration) -> Result<Self, Error> {
+        let deadline = Instant::now() + timeout;
+        let ctx = pcsc::Context::establish(pcsc::Scope::System)?;
+        let mut states = reader_states(&ctx)?;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            match ctx.get_status_change(remaining, &mut states) {
+                Ok(()) => (),
+                Err(pcsc::Error::Timeout) => {
+                    error!("no YubiKey inserted within {:?}", timeout);
+                    return Err(Error::Timeout);
+                }
+                Err(e) => return Err(e.into()),
+            }
+
+            let mut readers_changed = false;
+
+            for state in &mut states {
+                let name = state.name().to_owned();
+                let event = state.event_state();
+                state.sync_current_state();
+
+                if name.as_c_str() == pcsc::PNP_NOTIFICATION() {
+                    readers_changed |= event.contains(pcsc::State::CHANGED);
+                    continue;
+                }
+
+                if !event.contains(pcsc::State::PRESENT)
+                    || event.contains(pcsc::State::MUTE)
+                    || Self::identify_from_atr(state.atr()).is_none()
+                {
+                    continue;
+                }
+
+                match ctx.connect(&name, pcsc::ShareMode::Shared, pcsc::Protocols::T1) {
+                    Ok(card) => {
+                        let name = name.to_string_lossy().into_owned();
+                        info!("YubiKey present in reader: {}", name);
+                        return YubiKey::connect(Connection::Pcsc(card), name, None);
+                    }
+                    Err(e) => debug!("couldn't connect to {:?}: {}", name, e),
+                }
+            }
+
+            if readers_changed {
+                states = reader_states(&ctx)?;
+            }
+        }
+    }
+
+    /// Identify a YubiKey from a card's Answer To Reset (ATR), which can be
+    /// obtained without connecting to the card (see [`Reader::atr`]).
+    ///
+    /// YubiKeys identify themselves in the historical bytes of their ATR
+    /// over both USB and NFC. Returns `None` for any other card.
+    pub fn identify_from_atr(atr: &[u8]) -> Option<ModelHint> {
+        let position = atr
+            .windows(ATR_YUBIKEY.len())
+            .position(|window| window.eq_ignore_ascii_case(ATR_YUBIKEY))?;
+
+        let suffix = &atr[position + ATR_YUBIKEY.len()..];
+
+        let series = if suffix.starts_with(b"NEO") {
+            Some(Series::Neo)
+        } else if suffix.starts_with(b"4") {
+            Some(Series::YubiKey4)
+        } else {
+            None
+        };
+
+        Some(ModelHint {
+            interface: Interface::detect("", atr),
+            series,
+        })
+    }
+
+    /// Open a YubiKey over a custom [`CardTransport`] rather than PC/SC.
+    ///
+    /// See the [`transport`][`crate::transport`] module for details.
+    pub fn open_with_transport(transport: impl CardTransport + 'static) -> Result<Self, Error> {
+        let name = transport.name();
+        Self::connect(Connection::Transport(Box::new(transport)), name, None)
+    }
+
+    /// Select the PIV application on a newly established connection and
+    /// query the device's version and serial number.
+    pub(crate) fn connect(
+        mut card: Connection,
+        name: String,
+        aid: Option<Vec<u8>>,
+    ) -> Result<Self, Error> {
+        let interface = card.interface();
+
+        let (version, serial, is_yubikey) = {
+            let txn = Transaction::new(&mut card, aid.as_deref(), interface)?;
+            txn.select_application()?;
+
+            match txn.get_version() {
+                Ok(v) => {
+                    let s = txn.get_serial(v)?;
+                    (v, s, true)
+                }
+                Err(Error::NotSupported) => {
+                    info!("card doesn't support GET VERSION; assuming a non-YubiKey PIV card");
+                    (Version::new([0, 0, 0]), Serial(0), false)
+                }
+                Err(e) => return Err(e),
+            }
+        };
+
+        Ok(YubiKey {
+            card,
+            name,
+            pin: None,
+            version,
+            serial,
+            aid,
+            is_yubikey,
+            interface,
+            cache_mgm_auth: false,
+            mgm_authenticated: false,
+            touch_policies: vec![],
+            public_keys: vec![],
+            last_touch: None,
+            keepalive: None,
+            exclusive: false,
+            middleware: vec![],
+        })
+    }
+
     /// Reconnect to a YubiKey
     #[cfg(feature = "untested")]
     pub fn reconnect(&mut self) -> Result<(), Error> {
         info!("trying to reconnect to current reader");
 
-        self.card.reconnect(
-            pcsc::ShareMode::Shared,
-            pcsc::Protocols::T1,
-            pcsc::Disposition::ResetCard,
-        )?;
+        self.card
+            .reconnect(self.share_mode(), pcsc::Disposition::ResetCard)?;
+        self.mgm_authenticated = false;
+        self.touch_policies.clear();
+        self.last_touch = None;
 
         let pin = self
             .pin
             .as_ref()
             .map(|p| Buffer::new(p.expose_secret().clone()));
 
-        let txn = Transaction::new(&mut self.card)?;
+        let txn = Transaction::new(&mut self.card, self.aid.as_deref(), self.interface)?
+            .with_middleware(&self.middleware);
         txn.select_application()?;
 
         if let Some(p) = &pin {
@@ -227,10 +687,215 @@ impl YubiKey {
         Ok(())
     }
 
+    /// Reconnect to the card and reselect the PIV application if the card was
+    /// reset by a previous operation.
+    ///
+    /// Resetting the PIV application ([`YubiKey::reset_device`]), changing the
+    /// PIN/PUK retry counters ([`YubiKey::set_pin_retries`]) and changing the
+    /// management key ([`YubiKey::change_mgmkey`]) can cause the firmware to
+    /// reset the card, after which PC/SC fails every subsequent operation with
+    /// `SCARD_W_RESET_CARD` until we reconnect. These methods call this function
+    /// so callers don't see a spurious error on their next operation.
+    ///
+    /// Returns whether the card was reset, in which case the cached PIN (if
+    /// any) has already been verified again.
+    fn reselect_if_reset(&mut self) -> Result<bool, Error> {
+        match Transaction::new(&mut self.card, self.aid.as_deref(), self.interface) {
+            Ok(_) => return Ok(false),
+            Err(Error::PcscError {
+                inner: Some(pcsc::Error::ResetCard),
+            }) => (),
+            Err(e) => return Err(e),
+        }
+
+        info!("card was reset; reconnecting and reselecting PIV application");
+
+        self.card
+            .reconnect(self.share_mode(), pcsc::Disposition::LeaveCard)?;
+        self.mgm_authenticated = false;
+        self.touch_policies.clear();
+
+        let txn = Transaction::new(&mut self.card, self.aid.as_deref(), self.interface)?
+            .with_middleware(&self.middleware);
+        txn.select_application()?;
+
+        if let Some(pin) = &self.pin {
+            txn.verify_pin(pin.expose_secret())?;
+        }
+
+        Ok(true)
+    }
+
+    /// Verify the PIN cached by [`YubiKey::verify_pin`] again, reconnecting
+    /// first if the card was reset.
+    ///
+    /// Returns `Ok(false)` without contacting the card if no PIN is cached.
+    pub(crate) fn reverify_cached_pin(&mut self) -> Result<bool, Error> {
+        let pin = match &self.pin {
+            Some(pin) => Buffer::new(pin.expose_secret().clone()),
+            None => return Ok(false),
+        };
+
+        // reconnecting after a reset already verifies the cached PIN
+        if self.reselect_if_reset()? {
+            return Ok(true);
+        }
+
+        let txn = self.begin_transaction()?;
+        txn.verify_pin(&pin)?;
+        Ok(true)
+    }
+
     /// Begin a transaction.
     pub(crate) fn begin_transaction(&mut self) -> Result<Transaction<'_>, Error> {
         // TODO(tarcieri): reconnect support
-        Ok(Transaction::new(&mut self.card)?)
+        Ok(
+            Transaction::new(&mut self.card, self.aid.as_deref(), self.interface)?
+                .with_middleware(&self.middleware)
+                .with_max_object_size(self.max_object_size()),
+        )
+    }
+
+    /// Wait up to `timeout` for this YubiKey to be removed from its reader.
+    ///
+    /// On removal all session state cached on this handle is cleared: the
+    /// cached PIN, management key authentication, the touch tracker and
+    /// cached public keys. Subsequent operations on the handle fail, and if a
+    /// YubiKey is inserted again it may well be a different one, so open it
+    /// again (e.g. with [`YubiKey::wait_for_device`]) rather than reusing this
+    /// handle.
+    ///
+    /// Returns [`Error::Timeout`] if the YubiKey is still present after
+    /// `timeout`, and [`Error::NotSupported`] for custom transports.
+    pub fn wait_for_removal(&mut self, timeout: Duration) -> Result<(), Error> {
+        if let Connection::Transport(_) = self.card {
+            error!("removal can only be detected for PC/SC readers");
+            return Err(Error::NotSupported);
+        }
+
+        let deadline = Instant::now() + timeout;
+        let ctx = pcsc::Context::establish(pcsc::Scope::System)?;
+        let name = CString::new(self.name.as_str()).map_err(|_| Error::ArgumentError)?;
+        let mut states = [pcsc::ReaderState::new(name, pcsc::State::UNAWARE)];
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            match ctx.get_status_change(remaining, &mut states) {
+                Ok(()) => (),
+                Err(pcsc::Error::Timeout) => return Err(Error::Timeout),
+                Err(e) => return Err(e.into()),
+            }
+
+            let event = states[0].event_state();
+
+            if !event.contains(pcsc::State::PRESENT)
+                || event.intersects(pcsc::State::UNAVAILABLE | pcsc::State::UNKNOWN)
+            {
+                info!("YubiKey removed from reader: {}", self.name);
+                self.clear_session_state();
+                return Ok(());
+            }
+
+            states[0].sync_current_state();
+        }
+    }
+
+    /// Keep the connection alive by sending a GET VERSION command to the card
+    /// every `interval`, for readers or middleware which drop idle cards.
+    ///
+    /// The commands are sent from a background thread over a separate shared
+    /// connection to the same reader, each in its own PC/SC transaction so
+    /// they never interleave with operations on this handle. The thread holds
+    /// no secrets, stops by itself if the card is removed, and is stopped
+    /// and joined when this handle is dropped.
+    ///
+    /// Returns [`Error::NotSupported`] for custom transports, and
+    /// [`Error::ArgumentError`] if this handle has exclusive access (see
+    /// [`YubiKey::with_exclusive_access`]), which would lock the keep-alive's
+    /// connection out.
+    pub fn with_keepalive(mut self, interval: Duration) -> Result<Self, Error> {
+        if let Connection::Transport(_) = self.card {
+            error!("keep-alive is only supported for PC/SC readers");
+            return Err(Error::NotSupported);
+        }
+
+        if self.exclusive {
+            error!("keep-alive can't be used with an exclusive connection");
+            return Err(Error::ArgumentError);
+        }
+
+        // Stop any previous keep-alive before starting the new one
+        self.keepalive = None;
+        self.keepalive = Some(Keepalive::start(&self.name, interval)?);
+        Ok(self)
+    }
+
+    /// Connect to the card exclusively, so that no other application (or
+    /// other function of the YubiKey sharing the CCID interface, e.g. OATH)
+    /// can use it until this handle is dropped. Reconnects made by this
+    /// handle keep the exclusive access.
+    ///
+    /// Returns [`Error::Busy`] if another application is connected to the
+    /// card, e.g. a browser or smart card middleware, and
+    /// [`Error::NotSupported`] for custom transports. A keep-alive (see
+    /// [`YubiKey::with_keepalive`]) needs a second, shared connection and
+    /// can't be used together with exclusive access.
+    pub fn with_exclusive_access(mut self) -> Result<Self, Error> {
+        if let Connection::Transport(_) = self.card {
+            error!("exclusive access is only supported for PC/SC readers");
+            return Err(Error::NotSupported);
+        }
+
+        if self.keepalive.is_some() {
+            error!("exclusive access can't be combined with a keep-alive");
+            return Err(Error::ArgumentError);
+        }
+
+        self.card
+            .reconnect(pcsc::ShareMode::Exclusive, pcsc::Disposition::LeaveCard)?;
+        self.exclusive = true;
+        Ok(self)
+    }
+
+    /// PC/SC share mode to (re)connect to the card with
+    fn share_mode(&self) -> pcsc::ShareMode {
+        if self.exclusive {
+            pcsc::ShareMode::Exclusive
+        } else {
+            pcsc::ShareMode::Shared
+        }
+    }
+
+    /// Disconnect from the card, telling the reader what to do with it.
+    ///
+    /// E.g. [`Disposition::ResetCard`] on logout makes the card forget the
+    /// verified PIN, regardless of what other applications do. Dropping the
+    /// handle instead disconnects with the `pcsc` crate's default
+    /// disposition. The keep-alive thread, if any, is stopped first. This is
+    /// a no-op for custom transports.
+    pub fn disconnect(self, disposition: Disposition) -> Result<(), Error> {
+        let YubiKey {
+            card, keepalive, ..
+        } = self;
+
+        drop(keepalive);
+        card.disconnect(disposition.into())
+    }
+
+    /// Add middleware to be called around every APDU exchanged with the card
+    /// through this handle, on top of any middleware added before.
+    pub fn add_middleware<M: Middleware + 'static>(&mut self, middleware: M) {
+        self.middleware.push(Box::new(middleware));
+    }
+
+    /// Forget everything cached about the session with the card
+    fn clear_session_state(&mut self) {
+        self.pin = None;
+        self.mgm_authenticated = false;
+        self.touch_policies.clear();
+        self.public_keys.clear();
+        self.last_touch = None;
     }
 
     /// Get the name of the associated PC/SC card reader
@@ -238,18 +903,111 @@ impl YubiKey {
         &self.name
     }
 
+    /// Is this a YubiKey, as opposed to a third-party PIV card?
+    ///
+    /// Cards which don't support the Yubico-specific GET VERSION command are
+    /// assumed to be third-party PIV cards. Core PIV operations (signing,
+    /// objects, PIN) still work with them, but Yubico extensions don't.
+    pub fn is_yubikey(&self) -> bool {
+        self.is_yubikey
+    }
+
+    /// Largest data object (e.g. a certificate including its compression
+    /// info) which can be stored on this device, in bytes.
+    ///
+    /// YubiKeys don't report this limit, so it is derived from the firmware
+    /// version: the YubiKey NEO has a smaller buffer than the YubiKey 4 and
+    /// 5. Third-party PIV cards get the YubiKey 4 and 5 limit. Writing a
+    /// larger object fails with [`Error::SizeError`] before anything is
+    /// sent to the card.
+    pub fn max_object_size(&self) -> usize {
+        if self.is_yubikey && !self.version.at_least(4, 0, 0) {
+            CB_OBJ_MAX_NEO
+        } else {
+            CB_OBJ_MAX
+        }
+    }
+
+    /// Get the physical interface (USB or NFC) the YubiKey is connected over,
+    /// as detected when it was opened.
+    pub fn interface(&self) -> Interface {
+        self.interface
+    }
+
     /// Get the YubiKey's PIV application version.
     ///
     /// This always uses the cached version queried when the key is initialized.
-    pub fn version(&self) -> Version {
-        self.version
+    /// It is `None` for non-YubiKey cards (see [`YubiKey::is_yubikey`]).
+    pub fn version(&self) -> Option<Version> {
+        if self.is_yubikey {
+            Some(self.version)
+        } else {
+            None
+        }
+    }
+
+    /// Check that the firmware is at least version `min`, returning
+    /// [`Error::FirmwareTooOld`] otherwise. Non-YubiKey cards, which have no
+    /// firmware version, get [`Error::NotSupported`].
+    pub fn require_version(&self, min: Version) -> Result<(), Error> {
+        if !self.is_yubikey {
+            error!("firmware {} is required, but this isn't a YubiKey", min);
+            return Err(Error::NotSupported);
+        }
+
+        if self.version < min {
+            error!(
+                "firmware {} is older than the required {}",
+                self.version, min
+            );
+            return Err(Error::FirmwareTooOld {
+                found: self.version,
+                required: min,
+            });
+        }
+
+        Ok(())
     }
 
     /// Get YubiKey device serial number.
     ///
     /// This always uses the cached version queried when the key is initialized.
-    pub fn serial(&self) -> Serial {
-        self.serial
+    /// It is `None` for non-YubiKey cards (see [`YubiKey::is_yubikey`]).
+    pub fn serial(&self) -> Option<Serial> {
+        if self.is_yubikey {
+            Some(self.serial)
+        } else {
+            None
+        }
+    }
+
+    /// Borrow the underlying PC/SC card handle, e.g. to send commands this
+    /// crate doesn't wrap. Returns `None` if the YubiKey was opened with a
+    /// custom transport.
+    ///
+    /// # Caveats
+    ///
+    /// This is an escape hatch: commands sent directly to the card bypass all
+    /// of this crate's state tracking. In particular, selecting another
+    /// applet, or resetting the card, leaves the PIV application deselected
+    /// and the PIN unverified. Call [`YubiKey::select_application`] (and
+    /// [`YubiKey::verify_pin`] if needed) before using any other method on
+    /// this `YubiKey` afterwards.
+    pub fn card(&mut self) -> Option<&mut pcsc::Card> {
+        match &mut self.card {
+            Connection::Pcsc(card) => Some(card),
+            Connection::Transport(_) => None,
+        }
+    }
+
+    /// Select the PIV application, e.g. after using [`YubiKey::card`] to talk
+    /// to another applet.
+    ///
+    /// Note that selecting the application clears the PIN verification state.
+    pub fn select_application(&mut self) -> Result<(), Error> {
+        self.mgm_authenticated = false;
+        let txn = self.begin_transaction()?;
+        txn.select_application()
     }
 
     /// Get device configuration.
@@ -267,54 +1025,224 @@ impl YubiKey {
         CCC::get(self)
     }
 
+    /// Get the PIV Discovery Object
+    pub fn discovery(&mut self) -> Result<Discovery, Error> {
+        Discovery::get(self)
+    }
+
     /// Authenticate to the card using the provided management key (MGM).
+    ///
+    /// This always performs the challenge-response with the card, whatever
+    /// was authenticated before. See [`YubiKey::ensure_authenticated`] to skip
+    /// it when the connection is already authenticated.
     pub fn authenticate(&mut self, mgm_key: MgmKey) -> Result<(), Error> {
+        self.mgm_authenticated = false;
+        self.mgm_challenge_response(mgm_key)?;
+        self.mgm_authenticated = true;
+
+        Ok(())
+    }
+
+    /// Authenticate with the management key, unless management key
+    /// authentication caching is enabled (see
+    /// [`YubiKey::set_mgm_auth_caching`]) and this connection has already
+    /// authenticated, in which case this returns immediately without talking
+    /// to the card.
+    ///
+    /// Only the fact that authentication succeeded is remembered, not which
+    /// key it succeeded with, so `mgm_key` isn't checked when authentication
+    /// is skipped.
+    pub fn ensure_authenticated(&mut self, mgm_key: MgmKey) -> Result<(), Error> {
+        if self.cache_mgm_auth && self.mgm_authenticated {
+            debug!("management key already authenticated; skipping");
+            return Ok(());
+        }
+
+        self.authenticate(mgm_key)
+    }
+
+    /// Remember successful management key authentication for the rest of the
+    /// connection, so [`YubiKey::ensure_authenticated`] can skip the
+    /// challenge-response round-trips when provisioning several objects or
+    /// keys in a row.
+    ///
+    /// No key material is kept, only whether authentication succeeded. The
+    /// cached state is cleared when this crate reconnects, reselects the PIV
+    /// application or detects a card reset, but a reset or applet selection
+    /// by anything else (e.g. another process, or commands sent via
+    /// [`YubiKey::card`]) also invalidates the card's authentication state
+    /// without this crate noticing. Later operations then fail with
+    /// [`Error::AuthenticationError`]; call [`YubiKey::deauthenticate`] and
+    /// authenticate again in that case.
+    pub fn set_mgm_auth_caching(&mut self, enabled: bool) {
+        self.cache_mgm_auth = enabled;
+    }
+
+    /// Has the management key been authenticated during this connection?
+    ///
+    /// See [`YubiKey::set_mgm_auth_caching`] for when this is reset.
+    pub fn is_mgm_authenticated(&self) -> bool {
+        self.mgm_authenticated
+    }
+
+    /// Until when a touch is probably still cached, so that operations with
+    /// keys with [`TouchPolicy::Cached`] won't require another one.
+    ///
+    /// This is tracked on the host, and is only an approximation: the YubiKey
+    /// itself is authoritative. A touch is assumed to have happened whenever
+    /// an operation with a key whose touch policy is known to be
+    /// [`TouchPolicy::Always`] succeeds, or one with [`TouchPolicy::Cached`]
+    /// succeeds outside of the cache window. Touch policies are learned from
+    /// key generation, key import and [`key::metadata`](crate::key::metadata).
+    ///
+    /// Returns `None` if no touch is cached.
+    pub fn touch_cached_until(&self) -> Option<Instant> {
+        self.last_touch
+            .map(|touch| touch + TOUCH_CACHE_TIMEOUT)
+            .filter(|&until| until > Instant::now())
+    }
+
+    /// Remember the touch policy of the key in the given slot
+    pub(crate) fn note_touch_policy(&mut self, slot: SlotId, policy: TouchPolicy) {
+        self.touch_policies.retain(|&(s, _)| s != slot);
+
+        // the default depends on the slot and firmware, so treat it as unknown
+        if policy != TouchPolicy::Default {
+            self.touch_policies.push((slot, policy));
+        }
+    }
+
+    /// Get the cached public key for the given slot, if any.
+    pub(crate) fn cached_public_key(&self, slot: SlotId) -> Option<&PublicKeyInfo> {
+        self.public_keys
+            .iter()
+            .find(|&&(s, _)| s == slot)
+            .map(|(_, public_key)| public_key)
+    }
+
+    /// Record the public key of the key in the given slot, or forget it
+    /// (with `None`) after the slot's key may have changed.
+    pub(crate) fn note_public_key(&mut self, slot: SlotId, public_key: Option<PublicKeyInfo>) {
+        self.public_keys.retain(|&(s, _)| s != slot);
+
+        if let Some(public_key) = public_key {
+            self.public_keys.push((slot, public_key));
+        }
+    }
+
+    /// Update the touch tracker after a successful operation with the key in
+    /// the given slot, which was started at `started`.
+    pub(crate) fn note_key_operation(&mut self, slot: SlotId, started: Instant) {
+        let policy = self
+            .touch_policies
+            .iter()
+            .find(|&&(s, _)| s == slot)
+            .map(|&(_, policy)| policy);
+
+        let touched = match policy {
+            Some(TouchPolicy::Always) => true,
+            Some(TouchPolicy::Cached) => self
+                .last_touch
+                .map_or(true, |touch| touch + TOUCH_CACHE_TIMEOUT <= started),
+            _ => false,
+        };
+
+        if touched {
+            self.last_touch = Some(Instant::now());
+        }
+    }
+
+    /// Is the management key the factory default?
+    ///
+    /// This performs a full authentication round-trip with the default
+    /// 3DES key (bypassing [`YubiKey::set_mgm_auth_caching`]), which is safe
+    /// as management key authentication has no retry counter. Returns
+    /// `Ok(false)` if authentication fails, and `Err` on any other error.
+    ///
+    /// On success the card is left authenticated with the default key.
+    pub fn is_default_mgm_key(&mut self) -> Result<bool, Error> {
+        self.mgm_authenticated = false;
+
+        match self.mgm_challenge_response(MgmKey::default()) {
+            Ok(()) => {
+                self.mgm_authenticated = true;
+                Ok(true)
+            }
+            Err(Error::AuthenticationError) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Is touch required to authenticate with the management key (see
+    /// [`MgmKey::set_manual`])?
+    ///
+    /// This is read from the management key metadata, and returns `Ok(None)`
+    /// on firmware without GET METADATA (i.e. prior to YubiKey 5.3), where
+    /// the setting can't be read back at all. Probing for it isn't possible
+    /// either: there is no "touch pending" status, an authentication which
+    /// requires touch just blocks until the YubiKey is touched or the touch
+    /// times out, and then fails the same way as one with a wrong key.
+    pub fn mgm_touch_required(&mut self) -> Result<Option<bool>, Error> {
+        let metadata = {
+            let txn = self.begin_transaction()?;
+            txn.try_get_metadata(SlotId::CardManagement.into())?
+        };
+
+        let touch_policy = match metadata {
+            Some(data) => SlotMetadata::parse(&data)?.touch_policy,
+            None => return Ok(None),
+        };
+
+        Ok(touch_policy.map(|policy| match policy {
+            TouchPolicy::Always | TouchPolicy::Cached => true,
+            TouchPolicy::Never | TouchPolicy::Default => false,
+        }))
+    }
+
+    /// Perform management key challenge-response authentication
+    fn mgm_challenge_response(&mut self, mgm_key: MgmKey) -> Result<(), Error> {
         let txn = self.begin_transaction()?;
 
         // get a challenge from the card
-        let challenge = APDU::new(Ins::Authenticate)
-            .params(ALGO_3DES, KEY_CARDMGM)
-            .data(&[TAG_DYN_AUTH, 0x02, 0x80, 0x00])
-            .transmit(&txn, 261)?;
+        let witness = GeneralAuthenticate::new(ALGO_3DES, SlotId::CardManagement.into())
+            .object(TAG_AUTH_WITNESS, &[])
+            .transmit(&txn)?;
 
-        if !challenge.is_success() || challenge.data().len() < 12 {
+        if !witness.is_success() {
             return Err(Error::AuthenticationError);
         }
 
+        let witness = GeneralAuthenticate::parse_response(witness.data(), TAG_AUTH_WITNESS)
+            .ok()
+            .and_then(|witness| <[u8; 8]>::try_from(witness).ok())
+            .ok_or(Error::AuthenticationError)?;
+
         // send a response to the cards challenge and a challenge of our own.
-        let response = mgm_key.decrypt(challenge.data()[4..12].try_into().unwrap());
-
-        let mut data = [0u8; 22];
-        data[0] = TAG_DYN_AUTH;
-        data[1] = 20; // 2 + 8 + 2 +8
-        data[2] = 0x80;
-        data[3] = 8;
-        data[4..12].copy_from_slice(&response);
-        data[12] = 0x81;
-        data[13] = 8;
-
-        if getrandom(&mut data[14..22]).is_err() {
+        let response = mgm_key.decrypt(&witness);
+        let mut challenge = [0u8; 8];
+
+        if getrandom(&mut challenge).is_err() {
             error!("failed getting randomness for authentication");
             return Err(Error::RandomnessError);
         }
 
-        let mut challenge = [0u8; 8];
-        challenge.copy_from_slice(&data[14..22]);
-
-        let authentication = APDU::new(Ins::Authenticate)
-            .params(ALGO_3DES, KEY_CARDMGM)
-            .data(&data)
-            .transmit(&txn, 261)?;
+        let authentication = GeneralAuthenticate::new(ALGO_3DES, SlotId::CardManagement.into())
+            .object(TAG_AUTH_WITNESS, &response)
+            .object(TAG_AUTH_CHALLENGE, &challenge)
+            .transmit(&txn)?;
 
         if !authentication.is_success() {
             return Err(Error::AuthenticationError);
         }
 
+        let card_response =
+            GeneralAuthenticate::parse_response(authentication.data(), TAG_AUTH_RESPONSE)
+                .map_err(|_| Error::AuthenticationError)?;
+
         // compare the response from the card with our challenge
         let response = mgm_key.encrypt(&challenge);
 
-        use subtle::ConstantTimeEq;
-        if response.ct_eq(&authentication.data()[4..12]).unwrap_u8() != 1 {
+        if response[..].ct_eq(card_response).unwrap_u8() != 1 {
             return Err(Error::AuthenticationError);
         }
 
@@ -324,6 +1252,7 @@ impl YubiKey {
     /// Deauthenticate
 
     pub fn deauthenticate(&mut self) -> Result<(), Error> {
+        self.mgm_authenticated = false;
         let txn = self.begin_transaction()?;
 
         let status_words = APDU::new(Ins::SelectApplication)
@@ -343,6 +1272,47 @@ impl YubiKey {
         Ok(())
     }
 
+    /// Set the configuration lock code, which protects device configuration
+    /// changes (e.g. enabling or disabling interfaces) made via the management
+    /// application.
+    ///
+    /// The device must not already have a lock code set. Requires YubiKey 5.
+    pub fn set_config_lock(&mut self, code: [u8; CB_CONFIG_LOCK]) -> Result<(), Error> {
+        self.write_config_lock(code, None)
+    }
+
+    /// Clear the configuration lock code, given the current code.
+    ///
+    /// Requires YubiKey 5.
+    pub fn clear_config_lock(&mut self, code: [u8; CB_CONFIG_LOCK]) -> Result<(), Error> {
+        self.write_config_lock([0u8; CB_CONFIG_LOCK], Some(code))
+    }
+
+    fn write_config_lock(
+        &mut self,
+        new_code: [u8; CB_CONFIG_LOCK],
+        current_code: Option<[u8; CB_CONFIG_LOCK]>,
+    ) -> Result<(), Error> {
+        if !self.is_yubikey || !self.version.at_least(5, 0, 0) {
+            error!(
+                "configuration lock requires YubiKey 5 (found {})",
+                self.version
+            );
+            return Err(Error::NotSupported);
+        }
+
+        let mut config = Zeroizing::new(vec![0u8; 2 * (CB_CONFIG_LOCK + 2)]);
+        let mut len = Tlv::write(&mut config, TAG_CONFIG_LOCK, &new_code)?;
+
+        if let Some(current_code) = current_code {
+            len += Tlv::write(&mut config[len..], TAG_CONFIG_UNLOCK, &current_code)?;
+        }
+
+        self.mgm_authenticated = false;
+        let txn = self.begin_transaction()?;
+        txn.write_device_config(&config[..len])
+    }
+
     /// Verify device PIN.
     pub fn verify_pin(&mut self, pin: &[u8]) -> Result<(), Error> {
         {
@@ -357,8 +1327,109 @@ impl YubiKey {
         Ok(())
     }
 
+    /// Verify the PIN with the given reference.
+    ///
+    /// Unlike [`YubiKey::verify_pin`], the PIN isn't cached for reconnecting.
+    pub fn verify_pin_ref(&mut self, reference: PinReference, pin: &[u8]) -> Result<(), Error> {
+        let txn = self.begin_transaction()?;
+        txn.verify_pin_ref(reference, pin)
+    }
+
+    /// Verify the Pairing Code (see [`PinReference::PairingCode`]).
+    ///
+    /// The code must be exactly 8 digits, otherwise [`Error::ArgumentError`]
+    /// is returned without contacting the card. Returns [`Error::NotFound`]
+    /// if the card has no Pairing Code, which is the case for YubiKeys.
+    pub fn verify_pairing_code(&mut self, code: &[u8]) -> Result<(), Error> {
+        check_pairing_code(code)?;
+        self.verify_pin_ref(PinReference::PairingCode, code)
+    }
+
+    /// Verify device PIN, unless a wrong PIN would block it.
+    ///
+    /// The number of remaining attempts is checked first, and if only one
+    /// remains [`Error::WouldBlockPin`] is returned without attempting to
+    /// verify the PIN, unless `allow_last_attempt` is set. This protects
+    /// interactive tools from blocking the PIN in a loop of typos.
+    ///
+    /// Note that checking the number of remaining attempts resets the PIN
+    /// verification state (see [`YubiKey::get_pin_retries`]).
+    pub fn verify_pin_checked(
+        &mut self,
+        pin: &[u8],
+        allow_last_attempt: bool,
+    ) -> Result<(), Error> {
+        match self.get_pin_retries()? {
+            0 => Err(Error::PinLocked),
+            1 if !allow_last_attempt => Err(Error::WouldBlockPin),
+            _ => self.verify_pin(pin),
+        }
+    }
+
+    /// Try to verify the factory default PIN (123456), e.g. at first
+    /// enrollment where the PIN is presumed to still be the default.
+    ///
+    /// WARNING: this is potentially a destructive check! There is generally
+    /// no way to test a PIN without verifying it, so if the PIN has been
+    /// changed this uses up one of the remaining attempts, and
+    /// [`Error::WrongPin`] reports how many are left.
+    ///
+    /// The exception is YubiKey 5.3 and newer, whose PIN metadata says whether
+    /// the PIN is the default: if it isn't, [`Error::WrongPin`] is returned
+    /// with the remaining attempts without trying the default PIN.
+    pub fn try_default_pin(&mut self) -> Result<(), Error> {
+        let metadata = {
+            let txn = self.begin_transaction()?;
+            txn.try_get_metadata(PinReference::Application.into())?
+        };
+
+        if let Some(data) = metadata {
+            let metadata = SlotMetadata::parse(&data)?;
+
+            if metadata.is_default == Some(false) {
+                let tries = metadata.retries.map_or(0, |(_, remaining)| remaining);
+                debug!("PIN isn't the default ({} tries remaining)", tries);
+                return Err(Error::WrongPin { tries });
+            }
+        }
+
+        self.verify_pin(DEFAULT_PIN)
+    }
+
+    /// Is the PIN currently verified for this session?
+    ///
+    /// Unlike [`YubiKey::get_pin_retries`], this doesn't reselect the
+    /// applet, so an earlier successful [`YubiKey::verify_pin`] is still in
+    /// effect and can be used to skip re-verification.
+    pub fn is_pin_verified(&mut self) -> Result<bool, Error> {
+        let txn = self.begin_transaction()?;
+        txn.is_pin_verified()
+    }
+
+    /// Read the PIN, PUK and management key state in one go, e.g. for a
+    /// status display which is refreshed often.
+    ///
+    /// This consumes no PIN or PUK attempts and, unlike
+    /// [`YubiKey::get_pin_retries`], leaves the PIN verified if it was. See
+    /// [`AuthStatus`] for which fields are only available on YubiKey 5.3+.
+    pub fn auth_status(&mut self) -> Result<AuthStatus, Error> {
+        let txn = self.begin_transaction()?;
+        txn.auth_status()
+    }
+
+    /// Verify the user's fingerprint using the on-card biometric sensor
+    /// (YubiKey Bio series only).
+    ///
+    /// Returns [`Error::NotSupported`] if the device has no biometric
+    /// verification configured.
+    pub fn verify_bio(&mut self) -> Result<BioVerifyResult, Error> {
+        let txn = self.begin_transaction()?;
+        txn.verify_bio()
+    }
+
     /// Get the number of PIN retries
     pub fn get_pin_retries(&mut self) -> Result<u8, Error> {
+        self.mgm_authenticated = false;
         let txn = self.begin_transaction()?;
 
         // Force a re-select to unverify, because once verified the spec dictates that
@@ -377,23 +1448,32 @@ impl YubiKey {
     /// Set the number of PIN retries
     #[cfg(feature = "untested")]
     pub fn set_pin_retries(&mut self, pin_tries: u8, puk_tries: u8) -> Result<(), Error> {
+        self.set_retries(pin_tries, puk_tries)
+    }
+
+    /// Set the number of PIN and PUK retries, resetting both to their defaults
+    fn set_retries(&mut self, pin_tries: u8, puk_tries: u8) -> Result<(), Error> {
         // Special case: if either retry count is 0, it's a successful no-op
         if pin_tries == 0 || puk_tries == 0 {
             return Ok(());
         }
 
-        let txn = self.begin_transaction()?;
-
-        let templ = [0, Ins::SetPinRetries.code(), pin_tries, puk_tries];
-
-        let status_words = txn.transfer_data(&templ, &[], 255)?.status_words();
+        let status_words = {
+            let txn = self.begin_transaction()?;
+            let templ = ApduTemplate::new(Ins::SetPinRetries, pin_tries, puk_tries);
+            txn.transfer_data(templ, &[], 255)?.status_words()
+        };
 
         match status_words {
-            StatusWords::Success => Ok(()),
-            StatusWords::AuthBlockedError => Err(Error::AuthenticationError),
-            StatusWords::SecurityStatusError => Err(Error::AuthenticationError),
-            _ => Err(Error::GenericError),
+            StatusWords::Success => (),
+            StatusWords::AuthBlockedError => return Err(Error::AuthenticationError),
+            StatusWords::SecurityStatusError => return Err(Error::AuthenticationError),
+            other => return Err(other.to_error()),
         }
+
+        // Setting the retry counters also resets the PIN and PUK to their defaults
+        self.pin = None;
+        self.reselect_if_reset().map(|_| ())
     }
 
     /// Change the Personal Identification Number (PIN).
@@ -413,14 +1493,64 @@ impl YubiKey {
         Ok(())
     }
 
-    /// Change the MgmKey to a new one.
-    pub fn change_mgmkey(&mut self, key : &MgmKey) -> Result<(), Error> {
-        
+    /// Change the PIN with the given reference.
+    pub fn change_pin_ref(
+        &mut self,
+        reference: PinReference,
+        current_pin: &[u8],
+        new_pin: &[u8],
+    ) -> Result<(), Error> {
         let txn = self.begin_transaction()?;
-        
-        txn.set_mgm_key(key, true)?;
-        
-        Ok(())
+        txn.change_pin_ref(reference, current_pin, new_pin)
+    }
+
+    /// Change the Pairing Code (see [`PinReference::PairingCode`]).
+    ///
+    /// Both codes must be exactly 8 digits, otherwise
+    /// [`Error::ArgumentError`] is returned without contacting the card.
+    /// Returns [`Error::NotFound`] if the card has no Pairing Code.
+    pub fn change_pairing_code(
+        &mut self,
+        current_code: &[u8],
+        new_code: &[u8],
+    ) -> Result<(), Error> {
+        check_pairing_code(current_code)?;
+        check_pairing_code(new_code)?;
+        self.change_pin_ref(PinReference::PairingCode, current_code, new_code)
+    }
+
+    /// Change the MgmKey to a new one, which requires touch to be used.
+    pub fn change_mgmkey(&mut self, key: &MgmKey) -> Result<(), Error> {
+        self.set_mgm_key(key, true)
+    }
+
+    /// Set the management key, optionally requiring touch to use it
+    fn set_mgm_key(&mut self, key: &MgmKey, require_touch: bool) -> Result<(), Error> {
+        {
+            let txn = self.begin_transaction()?;
+            txn.set_mgm_key(key, require_touch)?;
+        }
+
+        self.mgm_authenticated = false;
+        self.reselect_if_reset().map(|_| ())
+    }
+
+    /// Set the management key and store it in the PIN-protected data object,
+    /// so only the PIN is needed to recover it later.
+    ///
+    /// Requires prior authentication with the current management key.
+    ///
+    /// Note that this reduces the security of the management key to that of
+    /// the PIN: anyone who learns the PIN can recover the management key.
+    pub fn store_mgm_key_protected(&mut self, key: &MgmKey, pin: &[u8]) -> Result<(), Error> {
+        let txn = self.begin_transaction()?;
+        txn.store_mgm_key_protected(key, pin)
+    }
+
+    /// Verify the PIN and retrieve the PIN-protected management key.
+    pub fn fetch_protected_mgm_key(&mut self, pin: &[u8]) -> Result<MgmKey, Error> {
+        let txn = self.begin_transaction()?;
+        txn.fetch_protected_mgm_key(pin)
     }
 
     /// Set PIN last changed
@@ -459,7 +1589,7 @@ impl YubiKey {
     /// The PUK is part of the PIV standard that the YubiKey follows.
     ///
     /// The default PUK code is 12345678.
-    
+
     pub fn change_puk(&mut self, current_puk: &[u8], new_puk: &[u8]) -> Result<(), Error> {
         let txn = self.begin_transaction()?;
         txn.change_ref(ChangeRefAction::ChangePuk, current_puk, new_puk)
@@ -480,7 +1610,7 @@ impl YubiKey {
 
             match res {
                 Ok(()) => puk[0] += 1,
-                Err(Error::WrongPin { tries }) => {
+                Err(Error::WrongPuk { tries }) => {
                     tries_remaining = tries as i32;
                     continue;
                 }
@@ -530,10 +1660,23 @@ impl YubiKey {
 
     /// Unblock a Personal Identification Number (PIN) using a previously
     /// configured PIN Unblocking Key (PUK).
-
+    ///
+    /// Both the PUK and the new PIN must be 6 to 8 bytes long, otherwise
+    /// [`Error::SizeError`] is returned without contacting the card. A wrong
+    /// PUK returns [`Error::WrongPuk`] with the number of PUK tries remaining,
+    /// and a blocked PUK returns [`Error::PinLocked`].
+    ///
+    /// On success the card resets the PIN retry counter to its maximum, as
+    /// reported by [`YubiKey::get_pin_retries`] afterwards. The new PIN isn't
+    /// verified, but replaces any cached PIN like with [`YubiKey::change_pin`].
     pub fn unblock_pin(&mut self, puk: &[u8], new_pin: &[u8]) -> Result<(), Error> {
-        let txn = self.begin_transaction()?;
-        txn.change_ref(ChangeRefAction::UnblockPin, puk, new_pin)
+        {
+            let txn = self.begin_transaction()?;
+            txn.unblock_pin(puk, new_pin)?;
+        }
+
+        self.pin = Some(CachedPin::new(new_pin.into()));
+        Ok(())
     }
 
     /// Fetch an object from the YubiKey
@@ -543,47 +1686,78 @@ impl YubiKey {
         txn.fetch_object(object_id)
     }
 
-    /// Save an object
+    /// Fetch several objects from the YubiKey in a single transaction.
+    ///
+    /// Each object's result is returned alongside its ID, so e.g. a missing
+    /// object ([`Error::NotFound`]) doesn't fail the others. Communication
+    /// errors abort the whole batch.
+    pub fn fetch_objects(
+        &mut self,
+        object_ids: &[ObjectId],
+    ) -> Result<Vec<(ObjectId, Result<Buffer, Error>)>, Error> {
+        let txn = self.begin_transaction()?;
+        txn.fetch_objects(object_ids)
+    }
 
+    /// Save an object
+    ///
+    /// Objects larger than a single APDU are written in chunks. If the card
+    /// rejects an intermediate chunk after accepting earlier ones,
+    /// [`Error::PartialWrite`] is returned as the object may have been left
+    /// partially written; save the whole object again to recover (PUT DATA
+    /// replaces the object). Errors reported for the final chunk (e.g.
+    /// [`Error::AuthenticationError`]) are returned as usual.
     pub fn save_object(&mut self, object_id: ObjectId, indata: &mut [u8]) -> Result<(), Error> {
         let txn = self.begin_transaction()?;
         txn.save_object(object_id, indata)
     }
 
+    /// Fetch an arbitrary data object by its 3-byte tag.
+    ///
+    /// Prefer the typed helpers for standard objects. Returns
+    /// [`Error::InvalidObject`] if the tag isn't 3 bytes long, and
+    /// [`Error::NotFound`] if the object is empty or unknown to the device.
+    pub fn get_raw_object(&mut self, tag: u32) -> Result<Buffer, Error> {
+        let txn = self.begin_transaction()?;
+        txn.get_raw_object(tag)
+    }
+
+    /// Store an arbitrary data object by its 3-byte tag.
+    ///
+    /// Requires prior authentication with the management key. Returns
+    /// [`Error::InvalidObject`] if the tag isn't 3 bytes long, and
+    /// [`Error::NotFound`] if the device doesn't know the object.
+    pub fn put_raw_object(&mut self, tag: u32, data: &[u8]) -> Result<(), Error> {
+        let txn = self.begin_transaction()?;
+        txn.put_raw_object(tag, data)
+    }
+
     /// Get an auth challenge
     #[cfg(feature = "untested")]
     pub fn get_auth_challenge(&mut self) -> Result<[u8; 8], Error> {
         let txn = self.begin_transaction()?;
 
-        let response = APDU::new(Ins::Authenticate)
-            .params(ALGO_3DES, KEY_CARDMGM)
-            .data(&[0x7c, 0x02, 0x81, 0x00])
-            .transmit(&txn, 261)?;
+        let response = GeneralAuthenticate::new(ALGO_3DES, SlotId::CardManagement.into())
+            .object(TAG_AUTH_CHALLENGE, &[])
+            .transmit(&txn)?;
 
         if !response.is_success() {
             return Err(Error::AuthenticationError);
         }
 
-        Ok(response.data()[4..12].try_into().unwrap())
+        let challenge = GeneralAuthenticate::parse_response(response.data(), TAG_AUTH_CHALLENGE)?;
+        challenge.try_into().map_err(|_| Error::AuthenticationError)
     }
 
     /// Verify an auth response
     #[cfg(feature = "untested")]
     pub fn verify_auth_response(&mut self, response: [u8; 8]) -> Result<(), Error> {
-        let mut data = [0u8; 12];
-        data[0] = 0x7c;
-        data[1] = 0x0a;
-        data[2] = 0x82;
-        data[3] = 0x08;
-        data[4..12].copy_from_slice(&response);
-
         let txn = self.begin_transaction()?;
 
         // send the response to the card and a challenge of our own.
-        let status_words = APDU::new(Ins::Authenticate)
-            .params(ALGO_3DES, KEY_CARDMGM)
-            .data(&data)
-            .transmit(&txn, 261)?
+        let status_words = GeneralAuthenticate::new(ALGO_3DES, SlotId::CardManagement.into())
+            .object(TAG_AUTH_RESPONSE, &response)
+            .transmit(&txn)?
             .status_words();
 
         if !status_words.is_success() {
@@ -597,49 +1771,199 @@ impl YubiKey {
     ///
     /// WARNING: this is a destructive operation which will destroy all keys!
     ///
-    /// The reset function is only available when both pins are blocked.
-
+    /// The reset function is only available when both pins are blocked, and
+    /// returns [`Error::ResetBlocked`] if the device configuration disables
+    /// it (see [`YubiKey::reset_allowed`]).
     pub fn reset_device(&mut self) -> Result<(), Error> {
-        let templ = [0, Ins::Reset.code(), 0, 0];
-        let txn = self.begin_transaction()?;
-        let status_words = txn.transfer_data(&templ, &[], 255)?.status_words();
+        match self.reset_allowed() {
+            Ok(true) => (),
+            Ok(false) => {
+                error!("resetting the PIV application is disabled by the device configuration");
+                return Err(Error::ResetBlocked);
+            }
+            Err(e) => debug!("couldn't check whether reset is allowed: {}", e),
+        }
+
+        let templ = ApduTemplate::new(Ins::Reset, 0, 0);
+
+        let status_words = {
+            let txn = self.begin_transaction()?;
+            txn.transfer_data(templ, &[], 255)?.status_words()
+        };
 
         if !status_words.is_success() {
             return Err(Error::GenericError);
         }
 
+        self.clear_session_state();
+        self.reselect_if_reset().map(|_| ())
+    }
+
+    /// Does the device configuration allow resetting the PIV application?
+    ///
+    /// YubiKey 5.7 and newer can be configured to block resetting individual
+    /// applications. Older YubiKeys and other PIV cards always allow a reset
+    /// once both the PIN and PUK are blocked.
+    pub fn reset_allowed(&mut self) -> Result<bool, Error> {
+        if !self.is_yubikey || !self.version.at_least(5, 0, 0) {
+            return Ok(true);
+        }
+
+        let config = {
+            let txn = self.begin_transaction()?;
+            txn.read_device_config()?
+        };
+
+        Ok(match device_config_value(&config, TAG_RESET_BLOCKED)? {
+            Some(value) => {
+                let blocked = value.iter().fold(0u16, |acc, &b| (acc << 8) | u16::from(b));
+
+                blocked & CAPABILITY_PIV == 0
+            }
+            None => true,
+        })
+    }
+
+    /// Get the PIN complexity policy enforced by the device, or `None` if
+    /// PIN complexity isn't enforced.
+    ///
+    /// PIN complexity can be enabled on YubiKey 5.7 and newer, and applies
+    /// to both the PIN and the PUK. Use [`PinComplexity::check`] to validate a
+    /// new PIN before sending it to the device.
+    pub fn pin_complexity(&mut self) -> Result<Option<PinComplexity>, Error> {
+        if !self.is_yubikey || !self.version.at_least(5, 0, 0) {
+            return Ok(None);
+        }
+
+        let config = {
+            let txn = self.begin_transaction()?;
+            txn.read_device_config()?
+        };
+
+        Ok(match device_config_value(&config, TAG_PIN_COMPLEXITY)? {
+            Some([enabled]) if *enabled != 0 => Some(PinComplexity::default()),
+            _ => None,
+        })
+    }
+
+    /// Provision a YubiKey whose PIV application has the factory default
+    /// PIN, PUK and management key (or is reset to them first).
+    ///
+    /// The steps are performed in the order the device requires:
+    ///
+    /// 1. reset the PIV application, if [`InitConfig::reset`] is set
+    /// 2. set the PIN and PUK retries, which resets the PIN and PUK to their
+    ///    defaults and so must happen before they are changed
+    /// 3. change the PIN
+    /// 4. change the PUK
+    /// 5. change the management key
+    ///
+    /// If a step fails, the error is logged along with the step it occurred
+    /// in, and the remaining steps are skipped.
+    ///
+    /// This is a method of the handle rather than of a single transaction, as
+    /// resetting the device and changing the retries or the management key
+    /// can reset the card, after which the handle reconnects, and verifying
+    /// the PIN updates the PIN cached on the handle.
+    pub fn initialize(&mut self, config: InitConfig) -> Result<(), Error> {
+        if config.reset {
+            self.reset_device()
+                .map_err(|e| init_failed("resetting the PIV application", e))?;
+        }
+
+        if let Some((pin_tries, puk_tries)) = config.retries {
+            self.authenticate(MgmKey::default())
+                .and_then(|()| self.verify_pin(DEFAULT_PIN))
+                .and_then(|()| self.set_retries(pin_tries, puk_tries))
+                .map_err(|e| init_failed("setting the PIN and PUK retries", e))?;
+        }
+
+        if let Some(pin) = &config.pin {
+            self.change_pin(DEFAULT_PIN, pin)
+                .map_err(|e| init_failed("changing the PIN", e))?;
+        }
+
+        if let Some(puk) = &config.puk {
+            self.change_puk(DEFAULT_PUK, puk)
+                .map_err(|e| init_failed("changing the PUK", e))?;
+        }
+
+        if let Some(mgm_key) = &config.mgm_key {
+            self.authenticate(MgmKey::default())
+                .and_then(|()| self.set_mgm_key(mgm_key, config.require_touch))
+                .map_err(|e| init_failed("changing the management key", e))?;
+        }
+
         Ok(())
     }
 }
 
+/// Check that a Pairing Code is 8 digits
+fn check_pairing_code(code: &[u8]) -> Result<(), Error> {
+    if code.len() != 8 || !code.iter().all(u8::is_ascii_digit) {
+        error!("the pairing code must be 8 digits");
+        return Err(Error::ArgumentError);
+    }
+
+    Ok(())
+}
+
+/// Find the value of the given tag in the device configuration
+fn device_config_value(mut config: &[u8], tag: u8) -> Result<Option<&[u8]>, Error> {
+    while !config.is_empty() {
+        let (remaining, tlv) = Tlv::parse(config)?;
+        config = remaining;
+
+        if tlv.tag == tag {
+            return Ok(Some(tlv.value));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Log which step of [`YubiKey::initialize`] failed
+fn init_failed(step: &str, err: Error) -> Error {
+    error!("initialization failed while {}: {}", step, err);
+    err
+}
+
+/// Initial PC/SC reader states for every reader, and for reader hotplug
+/// notifications
+fn reader_states(ctx: &pcsc::Context) -> Result<Vec<pcsc::ReaderState>, Error> {
+    let mut states = vec![pcsc::ReaderState::new(
+        pcsc::PNP_NOTIFICATION(),
+        pcsc::State::UNAWARE,
+    )];
+
+    let mut names = match ctx.list_readers_len() {
+        Ok(len) => vec![0u8; len],
+        Err(pcsc::Error::NoReadersAvailable) => return Ok(states),
+        Err(e) => return Err(e.into()),
+    };
+
+    match ctx.list_readers(&mut names) {
+        Ok(names) => states.extend(
+            names.map(|name| pcsc::ReaderState::new(name.to_owned(), pcsc::State::UNAWARE)),
+        ),
+        Err(pcsc::Error::NoReadersAvailable) => (),
+        Err(e) => return Err(e.into()),
+    }
+
+    Ok(states)
+}
+
 impl<'a> TryFrom<&'a Reader<'_>> for YubiKey {
     type Error = Error;
 
     fn try_from(reader: &'a Reader<'_>) -> Result<Self, Error> {
-        let mut card = reader.connect().map_err(|e| {
+        let card = reader.connect().map_err(|e| {
             error!("error connecting to reader '{}': {}", reader.name(), e);
             e
         })?;
 
         info!("connected to reader: {}", reader.name());
 
-        let (version, serial) = {
-            let txn = Transaction::new(&mut card)?;
-            txn.select_application()?;
-
-            let v = txn.get_version()?;
-            let s = txn.get_serial(v)?;
-            (v, s)
-        };
-
-        let yubikey = YubiKey {
-            card,
-            name: String::from(reader.name()),
-            pin: None,
-            version,
-            serial,
-        };
-
-        Ok(yubikey)
+        YubiKey::connect(Connection::Pcsc(card), String::from(reader.name()), None)
     }
 }
@@ -0,0 +1,248 @@
+//! Expected deterministic ECDSA ([RFC 6979]) signatures for known test keys.
+//!
+//! This is a straightforward, variable-time implementation of P-256 and
+//! P-384 which only exists to check signatures made by a device with test
+//! keys. It must never be used with real keys.
+//!
+//! [RFC 6979]: https://tools.ietf.org/html/rfc6979
+
+use hmac::{Hmac, Mac, NewMac};
+use num_bigint_dig::BigUint;
+use num_traits::Zero;
+use sha2::{Sha256, Sha384};
+use yubikey_piv::key::AlgorithmId;
+
+const P256_P: &[u8] = b"ffffffff00000001000000000000000000000000ffffffffffffffffffffffff";
+const P256_N: &[u8] = b"ffffffff00000000ffffffffffffffffbce6faada7179e84f3b9cac2fc632551";
+const P256_GX: &[u8] = b"6b17d1f2e12c4247f8bce6e563a440f277037d812deb33a0f4a13945d898c296";
+const P256_GY: &[u8] = b"4fe342e2fe1a7f9b8ee7eb4a7c0f9e162bce33576b315ececbb6406837bf51f5";
+
+const P384_P: &[u8] = b"fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffe\
+                        ffffffff0000000000000000ffffffff";
+const P384_N: &[u8] = b"ffffffffffffffffffffffffffffffffffffffffffffffffc7634d81f4372ddf\
+                        581a0db248b0a77aecec196accc52973";
+const P384_GX: &[u8] = b"aa87ca22be8b05378eb1c71ef320ad746e1d3b628ba79b9859f741e082542a38\
+                         5502f25dbf55296c3a545e3872760ab7";
+const P384_GY: &[u8] = b"3617de4a96262c6f5d9e98bf9292dc29f8f41dbd289a147ce9da3113b5f0b8c0\
+                         0a60b1ce1d7e819d7a431d7c90ea0e5f";
+
+/// A point in affine coordinates, or `None` for the point at infinity
+type Point = Option<(BigUint, BigUint)>;
+
+/// Compute the signature a device implementing deterministic ECDSA is
+/// expected to produce for `digest` (the already-hashed message) with the key
+/// in `curve` whose private scalar is `private_key`. Returns the DER-encoded
+/// signature.
+///
+/// As in RFC 6979, the nonce is derived with HMAC using the hash function
+/// the message was hashed with, which is inferred from the digest size
+/// (SHA-256 or SHA-384).
+pub fn deterministic_ecdsa_expected(
+    private_key: &[u8],
+    digest: &[u8],
+    curve: AlgorithmId,
+) -> Vec<u8> {
+    Curve::new(curve).sign_deterministic(private_key, digest)
+}
+
+/// NIST prime curve (short Weierstrass form with `a = -3`)
+struct Curve {
+    /// Field modulus
+    p: BigUint,
+
+    /// Curve coefficient `a` (i.e. `p - 3`)
+    a: BigUint,
+
+    /// Order of the base point
+    n: BigUint,
+
+    /// Base point
+    g: (BigUint, BigUint),
+
+    /// Size of field elements and scalars in bytes
+    size: usize,
+}
+
+impl Curve {
+    fn new(algorithm: AlgorithmId) -> Self {
+        let (p, n, gx, gy, size) = match algorithm {
+            AlgorithmId::EccP256 => (P256_P, P256_N, P256_GX, P256_GY, 32),
+            AlgorithmId::EccP384 => (P384_P, P384_N, P384_GX, P384_GY, 48),
+            other => panic!("not an ECC algorithm: {:?}", other),
+        };
+
+        let p = hex_to_int(p);
+        let a = &p - BigUint::from(3u32);
+
+        Curve {
+            p,
+            a,
+            n: hex_to_int(n),
+            g: (hex_to_int(gx), hex_to_int(gy)),
+            size,
+        }
+    }
+
+    fn sign_deterministic(&self, private_key: &[u8], digest: &[u8]) -> Vec<u8> {
+        let d = BigUint::from_bytes_be(private_key);
+        assert!(private_key.len() == self.size && !d.is_zero() && d < self.n);
+
+        let k = self.rfc6979_nonce(&d, digest);
+        let r = match self.mul(&k, &Some(self.g.clone())) {
+            Some((x, _)) => x % &self.n,
+            None => unreachable!("k is in [1, n)"),
+        };
+
+        let z = self.bits_to_int(digest);
+        let k_inv = k.modpow(&(&self.n - BigUint::from(2u32)), &self.n);
+        let s = (k_inv * ((z + &r * &d) % &self.n)) % &self.n;
+
+        let mut body = der_integer(&r);
+        body.extend_from_slice(&der_integer(&s));
+
+        let mut signature = vec![0x30, body.len() as u8];
+        signature.extend_from_slice(&body);
+        signature
+    }
+
+    /// Generate the nonce `k` (RFC 6979, section 3.2)
+    fn rfc6979_nonce(&self, d: &BigUint, digest: &[u8]) -> BigUint {
+        let x = self.int_to_bytes(d);
+        let h1 = self.int_to_bytes(&(self.bits_to_int(digest) % &self.n));
+        let hlen = digest.len();
+
+        let mut v = vec![0x01; hlen];
+        let mut k = vec![0x00; hlen];
+
+        k = hmac(&k, &[&v[..], &[0x00], &x[..], &h1[..]]);
+        v = hmac(&k, &[&v[..]]);
+        k = hmac(&k, &[&v[..], &[0x01], &x[..], &h1[..]]);
+        v = hmac(&k, &[&v[..]]);
+
+        loop {
+            let mut t = vec![];
+
+            while t.len() < self.size {
+                v = hmac(&k, &[&v[..]]);
+                t.extend_from_slice(&v);
+            }
+
+            let nonce = self.bits_to_int(&t);
+
+            if !nonce.is_zero() && nonce < self.n {
+                return nonce;
+            }
+
+            k = hmac(&k, &[&v[..], &[0x00]]);
+            v = hmac(&k, &[&v[..]]);
+        }
+    }
+
+    /// Interpret the leftmost bits of `data` as an integer the size of the
+    /// curve order (RFC 6979, section 2.3.2)
+    fn bits_to_int(&self, data: &[u8]) -> BigUint {
+        BigUint::from_bytes_be(&data[..data.len().min(self.size)])
+    }
+
+    /// Serialize an integer as a big endian byte string of the curve's size
+    fn int_to_bytes(&self, value: &BigUint) -> Vec<u8> {
+        let bytes = value.to_bytes_be();
+        let mut out = vec![0u8; self.size - bytes.len()];
+        out.extend_from_slice(&bytes);
+        out
+    }
+
+    /// Compute the multiplicative inverse of a field element
+    fn invert(&self, value: &BigUint) -> BigUint {
+        value.modpow(&(&self.p - BigUint::from(2u32)), &self.p)
+    }
+
+    /// Add two points on the curve
+    fn add(&self, p1: &Point, p2: &Point) -> Point {
+        let (x1, y1) = match p1 {
+            Some(point) => point,
+            None => return p2.clone(),
+        };
+
+        let (x2, y2) = match p2 {
+            Some(point) => point,
+            None => return p1.clone(),
+        };
+
+        let p = &self.p;
+
+        let lambda = if x1 == x2 {
+            if ((y1 + y2) % p).is_zero() {
+                return None;
+            }
+
+            // Tangent: (3x^2 + a) / 2y
+            let numerator = (BigUint::from(3u32) * x1 * x1 + &self.a) % p;
+            numerator * self.invert(&((BigUint::from(2u32) * y1) % p)) % p
+        } else {
+            ((y2 + p - y1) % p) * self.invert(&((x2 + p - x1) % p)) % p
+        };
+
+        let x3 = ((&lambda * &lambda) % p + p + p - x1 - x2) % p;
+        let y3 = ((&lambda * ((x1 + p - &x3) % p)) % p + p - y1) % p;
+
+        Some((x3, y3))
+    }
+
+    /// Multiply a point by a scalar
+    fn mul(&self, scalar: &BigUint, point: &Point) -> Point {
+        let mut result = None;
+
+        for byte in scalar.to_bytes_be() {
+            for i in (0..8).rev() {
+                result = self.add(&result, &result);
+
+                if (byte >> i) & 1 == 1 {
+                    result = self.add(&result, point);
+                }
+            }
+        }
+
+        result
+    }
+}
+
+/// HMAC with the hash function the message was hashed with, identified by
+/// the size of the key (`K` is always the size of the digest)
+fn hmac(key: &[u8], data: &[&[u8]]) -> Vec<u8> {
+    match key.len() {
+        32 => {
+            let mut mac = Hmac::<Sha256>::new_varkey(key).expect("HMAC accepts any key size");
+            for d in data {
+                mac.update(d);
+            }
+            mac.finalize().into_bytes().to_vec()
+        }
+        48 => {
+            let mut mac = Hmac::<Sha384>::new_varkey(key).expect("HMAC accepts any key size");
+            for d in data {
+                mac.update(d);
+            }
+            mac.finalize().into_bytes().to_vec()
+        }
+        other => panic!("unsupported digest size: {}", other),
+    }
+}
+
+/// Parse a hex-encoded curve parameter
+fn hex_to_int(hex: &[u8]) -> BigUint {
+    BigUint::parse_bytes(hex, 16).expect("valid curve parameter")
+}
+
+/// DER-encode a non-negative INTEGER
+fn der_integer(value: &BigUint) -> Vec<u8> {
+    let mut bytes = value.to_bytes_be();
+
+    if bytes[0] & 0x80 != 0 {
+        bytes.insert(0, 0);
+    }
+
+    let mut out = vec![0x02, bytes.len() as u8];
+    out.extend_from_slice(&bytes);
+    out
+}
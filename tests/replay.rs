@@ -0,0 +1,93 @@
+//! Tests replaying recorded APDU traces (no YubiKey required)
+
+#![forbid(unsafe_code)]
+#![warn(missing_docs, rust_2018_idioms, trivial_casts, unused_qualifications)]
+
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+use yubikey_piv::{
+    transport::{RecordTransport, ReplayTransport},
+    Error, Serial, YubiKey,
+};
+
+/// Trace of opening a YubiKey 5 (5.4.3, serial 12345678) and querying the
+/// number of PIN retries.
+const TRACE: &str = "\
+>>> 00a404000ba000000308000010000100
+<<< 9000
+>>> 00fd000000
+<<< 0504039000
+>>> 00f8000000
+<<< 00bc614e9000
+>>> 00a404000ba000000308000010000100
+<<< 9000
+>>> 0020008000
+<<< 63c3
+";
+
+#[test]
+fn replay() {
+    let mut yubikey = YubiKey::open_with_transport(ReplayTransport::parse(TRACE).unwrap()).unwrap();
+
+    assert_eq!(yubikey.version().unwrap().to_string(), "5.4.3");
+    assert_eq!(yubikey.serial(), Some(Serial(12_345_678)));
+    assert_eq!(yubikey.get_pin_retries(), Ok(3));
+}
+
+#[test]
+fn replay_log_output() {
+    let trace = "\
+[TRACE yubikey_piv::apdu] >>> CLA 00 INS a4 P1 04 P2 00 Lc 05 DATA a0 00 00 03 08
+[TRACE yubikey_piv::transaction] >>> [0, 164, 4, 0, 5, 160, 0, 0, 3, 8]
+[TRACE yubikey_piv::apdu] <<< 90 00
+";
+
+    let replay = ReplayTransport::parse(trace).unwrap();
+    assert_eq!(replay.remaining(), 1);
+}
+
+#[test]
+fn replay_unexpected_apdu() {
+    let mut yubikey = YubiKey::open_with_transport(ReplayTransport::parse(TRACE).unwrap()).unwrap();
+    assert_eq!(yubikey.verify_pin(b"123456"), Err(Error::GenericError));
+}
+
+#[test]
+fn replay_unpaired() {
+    assert_eq!(
+        ReplayTransport::parse(">>> 00fd000000\n").unwrap_err(),
+        Error::ParseError
+    );
+    assert_eq!(
+        ReplayTransport::parse("<<< 9000\n").unwrap_err(),
+        Error::ParseError
+    );
+}
+
+/// Writer which can be inspected after being handed to a `RecordTransport`
+#[derive(Clone, Default)]
+struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn record_replay_roundtrip() {
+    let writer = SharedWriter::default();
+    let record = RecordTransport::new(ReplayTransport::parse(TRACE).unwrap(), writer.clone());
+
+    let mut yubikey = YubiKey::open_with_transport(record).unwrap();
+    assert_eq!(yubikey.get_pin_retries(), Ok(3));
+
+    let recorded = String::from_utf8(writer.0.lock().unwrap().clone()).unwrap();
+    assert_eq!(recorded, TRACE);
+}